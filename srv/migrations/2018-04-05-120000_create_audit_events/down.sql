@@ -0,0 +1 @@
+DROP TABLE public.audit_events;
\ No newline at end of file
@@ -0,0 +1,14 @@
+CREATE TABLE public.audit_events (
+    id SERIAL PRIMARY KEY NOT NULL,
+    created TIMESTAMP DEFAULT NOW() NOT NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT,
+    data_before JSONB,
+    data_after JSONB
+);
+
+CREATE INDEX audit_events_created_index ON public.audit_events (created DESC);
+CREATE INDEX audit_events_actor_index ON public.audit_events (actor);
+CREATE INDEX audit_events_target_index ON public.audit_events (target_type, target_id);
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP TABLE public.student_history_selections;
+DROP TABLE public.student_history;
\ No newline at end of file
@@ -0,0 +1,20 @@
+CREATE TABLE public.student_history (
+    id SERIAL PRIMARY KEY NOT NULL,
+    student INT NOT NULL,
+    session INT NOT NULL,
+    created TIMESTAMP DEFAULT NOW() NOT NULL,
+    comment TEXT,
+    CONSTRAINT student_history_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT student_history_sessions_id_fk FOREIGN KEY (session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX student_history_student_index ON public.student_history (student);
+
+-- Projects are deliberately not foreign keys here, so history survives project deletion.
+CREATE TABLE public.student_history_selections (
+    history INT NOT NULL,
+    project INT NOT NULL,
+    weight NUMERIC(4,2) NOT NULL,
+    PRIMARY KEY (history, project),
+    CONSTRAINT student_history_selections_student_history_id_fk FOREIGN KEY (history) REFERENCES student_history (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE public.projects DROP COLUMN deleted;
+ALTER TABLE public.staff DROP COLUMN deleted;
+ALTER TABLE public.students DROP COLUMN deleted;
\ No newline at end of file
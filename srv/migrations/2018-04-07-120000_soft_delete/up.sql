@@ -0,0 +1,7 @@
+ALTER TABLE public.projects ADD COLUMN deleted TIMESTAMP;
+ALTER TABLE public.staff ADD COLUMN deleted TIMESTAMP;
+ALTER TABLE public.students ADD COLUMN deleted TIMESTAMP;
+
+CREATE INDEX projects_deleted_index ON public.projects (deleted);
+CREATE INDEX staff_deleted_index ON public.staff (deleted);
+CREATE INDEX students_deleted_index ON public.students (deleted);
\ No newline at end of file
@@ -0,0 +1,10 @@
+ALTER TABLE public.students ADD COLUMN last_session INT;
+
+UPDATE public.students
+SET last_session = (SELECT MAX(session) FROM public.student_sessions WHERE student_sessions.student = students.id);
+
+ALTER TABLE public.students
+ADD CONSTRAINT students_sessions_id_fk
+FOREIGN KEY (last_session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE;
+
+DROP TABLE public.student_sessions;
\ No newline at end of file
@@ -0,0 +1,14 @@
+CREATE TABLE public.student_sessions (
+    student INT NOT NULL,
+    session INT NOT NULL,
+    PRIMARY KEY (student, session),
+    CONSTRAINT student_sessions_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT student_sessions_sessions_id_fk FOREIGN KEY (session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX student_sessions_session_index ON public.student_sessions (session);
+
+INSERT INTO public.student_sessions (student, session)
+SELECT id, last_session FROM public.students WHERE last_session IS NOT NULL;
+
+ALTER TABLE public.students DROP COLUMN last_session;
\ No newline at end of file
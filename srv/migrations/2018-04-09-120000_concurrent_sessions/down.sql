@@ -0,0 +1 @@
+DROP INDEX public.sessions_force_archive_index;
\ No newline at end of file
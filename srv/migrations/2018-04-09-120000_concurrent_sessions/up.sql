@@ -0,0 +1,8 @@
+-- Sessions are now open until explicitly archived. Previously only the newest unarchived session was open, so archive
+-- any older ones to keep existing deployments behaving the same.
+UPDATE public.sessions SET force_archive = TRUE
+WHERE NOT force_archive AND id <> (
+    SELECT id FROM public.sessions WHERE NOT force_archive ORDER BY created DESC LIMIT 1
+);
+
+CREATE INDEX sessions_force_archive_index ON public.sessions (force_archive);
\ No newline at end of file
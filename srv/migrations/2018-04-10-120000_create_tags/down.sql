@@ -0,0 +1,2 @@
+DROP TABLE public.project_tags;
+DROP TABLE public.tags;
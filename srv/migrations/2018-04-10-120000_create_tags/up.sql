@@ -0,0 +1,14 @@
+CREATE TABLE public.tags (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE public.project_tags (
+    project INT NOT NULL,
+    tag INT NOT NULL,
+    PRIMARY KEY (project, tag),
+    CONSTRAINT project_tags_projects_id_fk FOREIGN KEY (project) REFERENCES projects (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT project_tags_tags_id_fk FOREIGN KEY (tag) REFERENCES tags (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX project_tags_tag_index ON public.project_tags (tag);
@@ -0,0 +1 @@
+DROP INDEX public.projects_search_index;
@@ -0,0 +1,6 @@
+-- Searches must use exactly this expression to make use of the index. See `db::project::search`.
+CREATE INDEX projects_search_index ON public.projects USING GIN ((
+    setweight(to_tsvector('english', name), 'A') ||
+    setweight(to_tsvector('english', supervisor_name), 'B') ||
+    setweight(to_tsvector('english', description_md), 'C')
+));
@@ -0,0 +1,2 @@
+DROP TABLE public.project_eligibility;
+DROP TABLE public.student_attributes;
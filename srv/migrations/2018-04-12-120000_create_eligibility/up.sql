@@ -0,0 +1,14 @@
+CREATE TABLE public.student_attributes (
+    student INT PRIMARY KEY,
+    programme TEXT,
+    modules TEXT[] NOT NULL DEFAULT '{}',
+    CONSTRAINT student_attributes_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Projects without a row here are open to everyone. Empty arrays don't restrict anything.
+CREATE TABLE public.project_eligibility (
+    project INT PRIMARY KEY,
+    programmes TEXT[] NOT NULL DEFAULT '{}',
+    modules TEXT[] NOT NULL DEFAULT '{}',
+    CONSTRAINT project_eligibility_projects_id_fk FOREIGN KEY (project) REFERENCES projects (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,2 @@
+DROP TABLE public.allocations;
+DROP TABLE public.project_proposals;
@@ -0,0 +1,31 @@
+CREATE TABLE public.project_proposals (
+    id SERIAL PRIMARY KEY,
+    student INT NOT NULL,
+    session INT NOT NULL,
+    name TEXT NOT NULL,
+    description_md TEXT NOT NULL,
+    supervisor_email TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'rejected')),
+    reason TEXT,
+    project INT,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    decided TIMESTAMP,
+    CONSTRAINT project_proposals_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT project_proposals_sessions_id_fk FOREIGN KEY (session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT project_proposals_projects_id_fk FOREIGN KEY (project) REFERENCES projects (id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+CREATE INDEX project_proposals_supervisor_email_index ON public.project_proposals (supervisor_email);
+
+-- A student has at most one project allocated per session.
+CREATE TABLE public.allocations (
+    student INT NOT NULL,
+    session INT NOT NULL,
+    project INT NOT NULL,
+    PRIMARY KEY (student, session),
+    CONSTRAINT allocations_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT allocations_sessions_id_fk FOREIGN KEY (session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT allocations_projects_id_fk FOREIGN KEY (project) REFERENCES projects (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX allocations_project_index ON public.allocations (project);
@@ -0,0 +1,4 @@
+DROP TABLE public.notifications;
+DROP INDEX public.projects_status_index;
+ALTER TABLE public.projects DROP COLUMN status_reason;
+ALTER TABLE public.projects DROP COLUMN status;
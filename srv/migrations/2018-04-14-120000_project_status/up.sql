@@ -0,0 +1,20 @@
+-- Existing projects were published directly, so they start out approved. New projects start as drafts.
+ALTER TABLE public.projects ADD COLUMN status TEXT NOT NULL DEFAULT 'approved'
+    CHECK (status IN ('draft', 'submitted', 'approved', 'rejected'));
+ALTER TABLE public.projects ADD COLUMN status_reason TEXT;
+ALTER TABLE public.projects ALTER COLUMN status SET DEFAULT 'draft';
+
+CREATE INDEX projects_status_index ON public.projects (status);
+
+CREATE TABLE public.notifications (
+    id SERIAL PRIMARY KEY NOT NULL,
+    recipient TEXT NOT NULL,
+    created TIMESTAMP DEFAULT NOW() NOT NULL,
+    kind TEXT NOT NULL,
+    message TEXT NOT NULL,
+    target_type TEXT,
+    target_id TEXT,
+    read TIMESTAMP
+);
+
+CREATE INDEX notifications_recipient_index ON public.notifications (recipient, created DESC);
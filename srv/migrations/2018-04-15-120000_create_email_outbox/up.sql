@@ -0,0 +1,15 @@
+CREATE TABLE public.email_outbox (
+    id SERIAL PRIMARY KEY NOT NULL,
+    recipient TEXT NOT NULL,
+    template TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created TIMESTAMP DEFAULT NOW() NOT NULL,
+    attempts INT DEFAULT 0 NOT NULL,
+    next_attempt TIMESTAMP DEFAULT NOW() NOT NULL,
+    last_error TEXT,
+    sent TIMESTAMP,
+    abandoned TIMESTAMP
+);
+
+CREATE INDEX email_outbox_pending_index ON public.email_outbox (next_attempt) WHERE sent IS NULL AND abandoned IS NULL;
@@ -0,0 +1,2 @@
+DROP TABLE public.session_reminders;
+ALTER TABLE public.sessions DROP COLUMN deadline;
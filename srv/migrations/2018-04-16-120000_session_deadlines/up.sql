@@ -0,0 +1,12 @@
+ALTER TABLE public.sessions ADD COLUMN deadline TIMESTAMP;
+
+-- Records which students have been reminded about a session's deadline, so each reminder is only sent once.
+CREATE TABLE public.session_reminders (
+    session INT NOT NULL,
+    student INT NOT NULL,
+    hours_before INT NOT NULL,
+    sent TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (session, student, hours_before),
+    CONSTRAINT session_reminders_sessions_id_fk FOREIGN KEY (session) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    CONSTRAINT session_reminders_students_id_fk FOREIGN KEY (student) REFERENCES students (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,40 @@
+CREATE TABLE public.webhooks (
+    id SERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- Used to sign payloads, so receivers can check they came from us.
+    secret TEXT NOT NULL,
+    events TEXT[] NOT NULL DEFAULT '{}',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created TIMESTAMP NOT NULL DEFAULT now()
+);
+
+-- Queue of payloads waiting to be delivered to each subscribed webhook.
+CREATE TABLE public.webhook_deliveries (
+    id SERIAL PRIMARY KEY,
+    webhook INT NOT NULL,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMP NOT NULL DEFAULT now(),
+    delivered TIMESTAMP,
+    abandoned TIMESTAMP,
+    CONSTRAINT webhook_deliveries_webhooks_id_fk FOREIGN KEY (webhook) REFERENCES webhooks (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_pending_index ON public.webhook_deliveries (next_attempt) WHERE delivered IS NULL AND abandoned IS NULL;
+CREATE INDEX webhook_deliveries_webhook_index ON public.webhook_deliveries (webhook);
+
+-- Log of every attempt to deliver a payload, successful or not.
+CREATE TABLE public.webhook_attempts (
+    id SERIAL PRIMARY KEY,
+    delivery INT NOT NULL,
+    attempted TIMESTAMP NOT NULL DEFAULT now(),
+    -- HTTP status returned, if the request got a response at all.
+    status INT,
+    error TEXT,
+    duration_ms INT NOT NULL,
+    CONSTRAINT webhook_attempts_webhook_deliveries_id_fk FOREIGN KEY (delivery) REFERENCES webhook_deliveries (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX webhook_attempts_delivery_index ON public.webhook_attempts (delivery);
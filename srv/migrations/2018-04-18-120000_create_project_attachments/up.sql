@@ -0,0 +1,14 @@
+-- Files attached to projects. The content lives in attachment storage under `storage_key`; only metadata is kept here.
+CREATE TABLE public.project_attachments (
+    id SERIAL PRIMARY KEY,
+    project INT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    storage_key TEXT NOT NULL UNIQUE,
+    uploaded_by TEXT NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT project_attachments_projects_id_fk FOREIGN KEY (project) REFERENCES projects (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX project_attachments_project_index ON public.project_attachments (project);
@@ -0,0 +1,2 @@
+ALTER TABLE public.sessions DROP COLUMN supervisor_marks;
+ALTER TABLE public.sessions DROP COLUMN supervisor_demand;
@@ -0,0 +1,5 @@
+-- What supervisors can see of the demand for their own projects: nothing, anonymous counts of each choice, or which
+-- students chose them. Whether they also see bookmark (mark) counts is set separately.
+ALTER TABLE public.sessions ADD COLUMN supervisor_demand TEXT NOT NULL DEFAULT 'hidden'
+    CHECK (supervisor_demand IN ('hidden', 'counts', 'named'));
+ALTER TABLE public.sessions ADD COLUMN supervisor_marks BOOLEAN NOT NULL DEFAULT FALSE;
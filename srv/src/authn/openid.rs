@@ -19,7 +19,7 @@ use url;
 
 use super::{AuthnBackend, AuthnFailure, AuthnHolder};
 use config::Config as HPASConfig;
-use db::{user, DatabaseConnection};
+use db::{audit, user, DatabaseConnection};
 use session::SessionManager;
 use util;
 
@@ -523,7 +523,7 @@ fn post_success(
     }
 
     // Check this is actually a valid user here (not just in e.g. an AD Forest)
-    let _usr = match user::find_user(&conn, &decoded.email) {
+    let usr = match user::find_user(&conn, &decoded.email) {
         None => {
             return Err(get_failure(
                 Status::Forbidden,
@@ -535,6 +535,22 @@ fn post_success(
             u
         }
     };
+    match user::can_log_in(&conn, &usr) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(get_failure(
+                Status::Forbidden,
+                "You are not enrolled in any open session.",
+            ));
+        }
+        Err(e) => {
+            error!("Unable to check whether {} can log in: {:?}", decoded.email, e);
+            return Err(get_failure(
+                Status::InternalServerError,
+                "Unable to check your enrolment. Please try again later.",
+            ));
+        }
+    }
 
     {
         let mut token_bag = auth.old_tokens.write().unwrap();
@@ -546,6 +562,15 @@ fn post_success(
         "New session: {:?}",
         sess
     );
+    audit::record(
+        &conn,
+        &decoded.email,
+        "auth.login",
+        "user",
+        Some(decoded.email.clone()),
+        None,
+        None,
+    );
 
     Ok(util::RedirectWithBody::to("/"))
 }
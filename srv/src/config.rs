@@ -5,13 +5,17 @@ use std::io;
 use std::io::Read;
 use toml;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
     hpas: ConfigHPAS,
     session: Option<SessionConfig>,
+    retention: Option<RetentionConfig>,
+    mail: Option<MailConfig>,
+    reminders: Option<ReminderConfig>,
+    attachments: Option<AttachmentConfig>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 struct ConfigHPAS {
     database_string: String,
     secret_key: Option<String>,
@@ -19,11 +23,46 @@ struct ConfigHPAS {
     server_address: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Clone, Debug)]
 struct SessionConfig {
     pub expiry_minutes: u32,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+struct RetentionConfig {
+    pub deleted_days: u32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct ReminderConfig {
+    pub hours_before: Vec<u32>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct AttachmentConfig {
+    pub storage: Option<String>,
+    pub dir: Option<String>,
+    pub max_size_bytes: Option<u64>,
+}
+
+/// SMTP settings for sending email. Email is disabled if this section is missing.
+#[derive(Deserialize, Clone, Debug)]
+pub struct MailConfig {
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    /// Whether to require STARTTLS, which defaults to on. It can only be turned off for local test servers which don't
+    /// need credentials, as those are never sent unencrypted.
+    pub starttls: Option<bool>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from_address: String,
+    pub from_name: Option<String>,
+    /// Directory of `<template>.txt` files overriding the built-in templates.
+    pub templates_dir: Option<String>,
+    /// Attempts made to send each email before giving up.
+    pub max_attempts: Option<i32>,
+}
+
 impl Config {
     pub fn get_database_str(&self) -> String {
         format!("postgres://{}", self.hpas.database_string)
@@ -43,6 +82,59 @@ impl Config {
         }
     }
 
+    /// Number of days soft deleted projects, staff and students can be restored for before being purged.
+    pub fn get_deleted_retention_days(&self) -> u32 {
+        match self.retention {
+            Some(ref retention) => retention.deleted_days,
+            None => RetentionConfig::default().deleted_days,
+        }
+    }
+
+    /// Hours before a session's deadline at which students with incomplete selections are reminded, in ascending order.
+    pub fn get_reminder_hours(&self) -> Vec<u32> {
+        let mut hours = match self.reminders {
+            Some(ref reminders) => reminders.hours_before.clone(),
+            None => ReminderConfig::default().hours_before,
+        };
+        hours.sort();
+        hours.dedup();
+        hours
+    }
+
+    /// Backend used to store project attachments. Only `local` is currently supported.
+    pub fn get_attachment_storage(&self) -> String {
+        match self.attachments {
+            Some(AttachmentConfig {
+                storage: Some(ref storage),
+                ..
+            }) => storage.to_lowercase(),
+            _ => "local".to_string(),
+        }
+    }
+
+    /// Directory attachments are kept in by the `local` storage backend.
+    pub fn get_attachment_dir(&self) -> String {
+        match self.attachments {
+            Some(AttachmentConfig { dir: Some(ref dir), .. }) => dir.clone(),
+            _ => "attachments".to_string(),
+        }
+    }
+
+    /// Largest attachment accepted, in bytes.
+    pub fn get_attachment_max_size(&self) -> u64 {
+        match self.attachments {
+            Some(AttachmentConfig {
+                max_size_bytes: Some(size),
+                ..
+            }) => size,
+            _ => 10 * 1024 * 1024,
+        }
+    }
+
+    pub fn get_mail(&self) -> Option<&MailConfig> {
+        self.mail.as_ref()
+    }
+
     pub fn get_authn_provider(&self) -> String {
         match self.hpas.authn_provider {
             None => "simple".to_string(),
@@ -63,6 +155,20 @@ impl Default for SessionConfig {
     }
 }
 
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig { deleted_days: 30 }
+    }
+}
+
+impl Default for ReminderConfig {
+    fn default() -> Self {
+        ReminderConfig {
+            hours_before: vec![72, 24],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     IO(io::Error),
@@ -123,5 +229,9 @@ pub fn default_config() -> Config {
             server_address: "http://localhost:8888".to_string(),
         },
         session: None,
+        retention: None,
+        mail: None,
+        reminders: None,
+        attachments: None,
     }
 }
@@ -0,0 +1,82 @@
+v1_imports!();
+
+use rocket::Route;
+
+use db::archive::{self, ArchiveError, ImportSummary, SessionArchive};
+use db::{allocation, audit, staff, webhook};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![export_session, import_session]
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/archive")]
+fn export_session(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<SessionArchive> {
+    let res = archive::export(&conn, id).map_err(archive_error_handler)?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.export",
+        "session",
+        Some(id.to_string()),
+        None,
+        None,
+    );
+    Ok(Json(res))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/sessions/import", data = "<body>")]
+fn import_session(
+    body: Json<SessionArchive>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<ImportSummary> {
+    let res = archive::import(&conn, &body).map_err(archive_error_handler)?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.import",
+        "session",
+        Some(res.session.id.to_string()),
+        None,
+        audit::snapshot(&res),
+    );
+    publish_allocations(&conn, res.session.id);
+    Ok(Json(res))
+}
+
+/// Emits an event for each allocation in an imported session, as if they'd been made there.
+fn publish_allocations(conn: &DatabaseConnection, sess: i32) {
+    let allocs = match allocation::get_all_for_session(conn, sess) {
+        Ok(allocs) => allocs,
+        Err(e) => {
+            error!("Unable to fetch allocations of imported session {}: {:?}", sess, e);
+            return;
+        }
+    };
+    for a in allocs {
+        webhook::emit(
+            conn,
+            "allocation.published",
+            Some(json!({ "student": a.student, "session": a.session, "project": a.project })),
+        );
+    }
+}
+
+fn archive_error_handler(e: ArchiveError) -> ErrorResponse {
+    match e {
+        ArchiveError::NoSuchSession() => not_found!("no such session"),
+        ArchiveError::UnsupportedVersion(v) => bad_request!(
+            "unsupported archive version {}; expected {}",
+            v,
+            archive::ARCHIVE_VERSION
+        ),
+        ArchiveError::Invalid(msg) => bad_request!("invalid archive: {}", msg),
+        ArchiveError::DieselError(e) => diesel_error_handler!(e),
+    }
+}
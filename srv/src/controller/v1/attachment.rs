@@ -0,0 +1,237 @@
+v1_imports!();
+
+use std::io::Read;
+
+use rocket::http::ContentType;
+use rocket::{Data, Route, State};
+
+use config::Config;
+use db::project::{self, attachment};
+use db::{audit, session, staff};
+use session::Session;
+use storage::{self, Storage, StorageHolder};
+use util::Download;
+
+/// Content types which can be attached to projects, along with the bytes every such file starts with. Uploads are
+/// checked against these, so a file can't be passed off as a different type.
+const ALLOWED_TYPES: &[(&str, &[u8])] = &[
+    ("application/pdf", b"%PDF-"),
+    ("image/png", b"\x89PNG\r\n\x1a\n"),
+    ("image/jpeg", b"\xff\xd8\xff"),
+    ("image/gif", b"GIF8"),
+];
+/// Longest filename accepted, in characters.
+const MAX_FILENAME_LENGTH: usize = 255;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_attachments,
+        upload_attachment,
+        download_attachment,
+        rm_attachment
+    ]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct UploadQuery {
+    filename: String,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/projects/<id>/attachments")]
+fn get_attachments(id: i32, session: Session, conn: DatabaseConnection) -> V1Response<AttachmentList> {
+    let p = get_visible_project(&conn, &session, id)?;
+    let attachments = attachment::get_all_for_project(&conn, p.id)
+        .map_err(select_error_handler!("no attachments found"))?;
+    Ok(Json(AttachmentList { attachments }))
+}
+
+/// Attaches a file to a project. The body is the file itself, with its type given by the `Content-Type` header.
+#[allow(needless_pass_by_value)]
+#[post("/projects/<id>/attachments?<query>", data = "<data>")]
+fn upload_attachment(
+    id: i32,
+    query: UploadQuery,
+    content_type: Option<ContentType>,
+    data: Data,
+    usr: staff::Staff,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+    store: State<StorageHolder>,
+) -> V1Response<attachment::ProjectAttachment> {
+    let p = get_editable_project(&conn, &usr, id)?;
+    let filename = check_filename(&query.filename)?;
+    let content_type = check_content_type(content_type)?;
+
+    let max_size = conf.get_attachment_max_size();
+    let mut body = Vec::new();
+    data.open()
+        .take(max_size + 1)
+        .read_to_end(&mut body)
+        .map_err(|_| bad_request!("unable to read upload"))?;
+    if body.len() as u64 > max_size {
+        return Err(bad_request!("attachment is larger than {} bytes", max_size));
+    }
+    check_signature(&content_type, &body)?;
+
+    // Metadata goes in first, so the retention thread never mistakes a file being uploaded for an orphan.
+    let res = attachment::create(
+        &conn,
+        &attachment::NewProjectAttachment {
+            project: p.id,
+            filename,
+            content_type,
+            size: body.len() as i64,
+            storage_key: storage::new_key(),
+            uploaded_by: usr.email.clone(),
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    if let Err(e) = store.put(&res.storage_key, &body) {
+        error!("Unable to store attachment {}: {}", res.storage_key, e);
+        if let Err(e) = attachment::delete(&conn, &res) {
+            error!("Unable to remove metadata for unstored attachment {}: {}", res.id, e);
+        }
+        return Err(internal_server_error!("unable to store attachment"));
+    }
+
+    audit::record(
+        &conn,
+        &usr.email,
+        "attachment.upload",
+        "project",
+        Some(p.id.to_string()),
+        None,
+        audit::snapshot(&res),
+    );
+    Ok(Json(res))
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/projects/<id>/attachments/<attachment_id>")]
+fn download_attachment(
+    id: i32,
+    attachment_id: i32,
+    session: Session,
+    conn: DatabaseConnection,
+    store: State<StorageHolder>,
+) -> Result<Download, ErrorResponse> {
+    let p = get_visible_project(&conn, &session, id)?;
+    let a = attachment::get(&conn, p.id, attachment_id).map_err(select_error_handler!("no such attachment"))?;
+    let body = store.get(&a.storage_key).map_err(|e| {
+        error!("Unable to fetch attachment {}: {}", a.storage_key, e);
+        internal_server_error!("unable to fetch attachment")
+    })?;
+
+    Ok(Download {
+        content_type: ContentType::parse_flexible(&a.content_type).unwrap_or(ContentType::Binary),
+        filename: a.filename,
+        body,
+    })
+}
+
+#[allow(needless_pass_by_value)]
+#[delete("/projects/<id>/attachments/<attachment_id>")]
+fn rm_attachment(
+    id: i32,
+    attachment_id: i32,
+    usr: staff::Staff,
+    conn: DatabaseConnection,
+    store: State<StorageHolder>,
+) -> V1Response<GenericMessage> {
+    let p = get_editable_project(&conn, &usr, id)?;
+    let a = attachment::get(&conn, p.id, attachment_id).map_err(select_error_handler!("no such attachment"))?;
+    attachment::delete(&conn, &a).map_err(|e| diesel_error_handler!(e))?;
+    // If this fails, the file is left for the retention thread to clean up.
+    if let Err(e) = store.delete(&a.storage_key) {
+        warn!("Unable to remove stored attachment {}: {}", a.storage_key, e);
+    }
+
+    audit::record(
+        &conn,
+        &usr.email,
+        "attachment.delete",
+        "project",
+        Some(p.id.to_string()),
+        audit::snapshot(&a),
+        None,
+    );
+    Ok(generic_message!("ok"))
+}
+
+/// Fetches a project if the user can see it. Students can only see approved projects in the sessions they're enrolled
+/// in; anything else looks like it doesn't exist.
+fn get_visible_project(
+    conn: &DatabaseConnection,
+    session: &Session,
+    id: i32,
+) -> Result<project::Project, ErrorResponse> {
+    let p = project::get_project(conn, id).map_err(select_error_handler!("no such project"))?;
+    if let Some(sessions) = super::project::get_visible_sessions(conn, session)? {
+        if !sessions.contains(&p.session) || p.status != project::APPROVED {
+            return Err(not_found!("no such project"));
+        }
+    }
+    Ok(p)
+}
+
+/// Fetches a project if the user supervises or co-supervises it (or is an admin), and it's in a current session.
+fn get_editable_project(
+    conn: &DatabaseConnection,
+    usr: &staff::Staff,
+    id: i32,
+) -> Result<project::Project, ErrorResponse> {
+    let p = project::get_project(conn, id).map_err(select_error_handler!("no such project"))?;
+    super::project::check_owner(conn, usr, &p)?;
+
+    let (is_curr, _) = session::get_session(conn, p.session).map_err(select_error_handler!("no such project"))?;
+    if !is_curr {
+        return Err(bad_request!("cannot edit an archived project"));
+    }
+    Ok(p)
+}
+
+/// Strips any path from a filename, along with characters which would break the download's `Content-Disposition`.
+fn check_filename(raw: &str) -> Result<String, ErrorResponse> {
+    let name = raw.rsplit(|c| c == '/' || c == '\\')
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .collect::<String>();
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(bad_request!("attachment must have a filename"));
+    }
+    if name.chars().count() > MAX_FILENAME_LENGTH {
+        return Err(bad_request!("attachment filename must be at most {} characters", MAX_FILENAME_LENGTH));
+    }
+    Ok(name.to_string())
+}
+
+fn check_content_type(content_type: Option<ContentType>) -> Result<String, ErrorResponse> {
+    let allowed = || {
+        ALLOWED_TYPES
+            .iter()
+            .map(|it| it.0)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    };
+    let ct = content_type
+        .ok_or_else(|| bad_request!("attachment must have a Content-Type; expected one of: {}", allowed()))?;
+    let name = format!("{}/{}", ct.top(), ct.sub()).to_lowercase();
+    if !ALLOWED_TYPES.iter().any(|it| it.0 == name) {
+        return Err(bad_request!("unsupported attachment type {}; expected one of: {}", name, allowed()));
+    }
+    Ok(name)
+}
+
+fn check_signature(content_type: &str, body: &[u8]) -> Result<(), ErrorResponse> {
+    let matches = ALLOWED_TYPES
+        .iter()
+        .any(|&(name, magic)| name == content_type && body.starts_with(magic));
+    if !matches {
+        return Err(bad_request!("attachment content is not a valid {} file", content_type));
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+v1_imports!();
+
+use chrono::naive::{NaiveDate, NaiveDateTime};
+use rocket::Route;
+
+use db::{audit, staff};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_audit, get_audit_unfiltered]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct AuditQuery {
+    actor: Option<String>,
+    target_type: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/audit?<query>")]
+fn get_audit(
+    query: AuditQuery,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<AuditEventList> {
+    let filter = audit::AuditFilter {
+        actor: query.actor,
+        target_type: query.target_type,
+        from: parse_date_param(query.from, "from", false)?,
+        to: parse_date_param(query.to, "to", true)?,
+    };
+    // The audit log is always paged, as it grows without bound.
+    let req = super::page_request(
+        Some(query.limit.unwrap_or(super::DEFAULT_PAGE_LIMIT)),
+        query.cursor,
+        query.sort,
+        audit::SORT_KEYS,
+        "-created",
+    )?.expect("a limit is always given");
+
+    let page = audit::get_filtered(&conn, &filter, &req)
+        .map_err(select_error_handler!("no audit events found"))?;
+
+    Ok(Json(AuditEventList {
+        page: super::page_info(&req, &page),
+        events: page.items,
+    }))
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/audit", rank = 2)]
+fn get_audit_unfiltered(usr: staff::Admin, conn: DatabaseConnection) -> V1Response<AuditEventList> {
+    get_audit(
+        AuditQuery {
+            actor: None,
+            target_type: None,
+            from: None,
+            to: None,
+            limit: None,
+            cursor: None,
+            sort: None,
+        },
+        usr,
+        conn,
+    )
+}
+
+/// Parses a date (`2018-04-05`) or date-time (`2018-04-05T13:00:00`) query parameter. A date on its own means the
+/// start of that day, or the end of it if `end_of_day` is set, so that `to=2018-04-05` includes the whole of the 5th.
+fn parse_date_param(
+    val: Option<String>,
+    name: &str,
+    end_of_day: bool,
+) -> Result<Option<NaiveDateTime>, ErrorResponse> {
+    let val = match val {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    NaiveDateTime::parse_from_str(&val, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(&val, "%Y-%m-%d").map(|d| {
+                if end_of_day {
+                    d.and_hms_micro(23, 59, 59, 999_999)
+                } else {
+                    d.and_hms(0, 0, 0)
+                }
+            })
+        })
+        .map(Some)
+        .map_err(|_| bad_request!("invalid date for '{}'", name))
+}
@@ -0,0 +1,36 @@
+v1_imports!();
+
+use rocket::{Route, State};
+
+use config::Config;
+use db::{project, staff, student};
+use retention;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_deleted]
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/deleted")]
+fn get_deleted(
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<DeletedList> {
+    let retention_days = conf.get_deleted_retention_days();
+    let cutoff = retention::get_cutoff(retention_days);
+
+    let projects = project::get_all_deleted(&conn, cutoff)
+        .map_err(select_error_handler!("no deleted projects found"))?;
+    let staff = staff::get_all_deleted(&conn, cutoff)
+        .map_err(select_error_handler!("no deleted staff found"))?;
+    let students = student::get_all_deleted(&conn, cutoff)
+        .map_err(select_error_handler!("no deleted students found"))?;
+
+    Ok(Json(DeletedList {
+        projects,
+        staff,
+        students,
+        retention_days,
+    }))
+}
@@ -0,0 +1,319 @@
+v1_imports!();
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use csv::{ReaderBuilder, StringRecord};
+use rocket::{Data, Route};
+
+use db::models::normalise_list;
+use db::student::{attributes, enrolment};
+use db::{audit, session, staff, student, webhook};
+use util;
+
+/// Largest CSV upload accepted, in bytes.
+const MAX_UPLOAD_SIZE: u64 = 1024 * 1024;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        import_staff,
+        import_staff_default,
+        import_students,
+        import_students_default
+    ]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Default, Debug)]
+struct ImportQuery {
+    /// Validate and report without writing anything.
+    dry_run: Option<bool>,
+    /// Whether the first row is a header row. Defaults to true.
+    headers: Option<bool>,
+    /// Session to enrol imported students in. Only required if multiple sessions are open.
+    session: Option<i32>,
+}
+
+/// Imports staff from a CSV of `email,full_name[,is_admin]` rows. Existing admins stay admins. Emails are matched after
+/// sanitising both sides, and existing staff keep the email they were stored with.
+#[allow(needless_pass_by_value)]
+#[post("/staff/import?<query>", format = "text/csv", data = "<data>")]
+fn import_staff(
+    query: ImportQuery,
+    data: Data,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<ImportReport> {
+    use diesel::prelude::*;
+    use diesel::result;
+
+    let body = read_body(data)?;
+    let existing = staff::get_all(&conn)
+        .map_err(select_error_handler!("unable to fetch staff"))?
+        .into_iter()
+        .map(|it| {
+            let key = validate_email(it.email.trim()).unwrap_or_else(|_| it.email.trim().to_lowercase());
+            (key, it)
+        })
+        .collect::<HashMap<String, staff::Staff>>();
+
+    // Emails from the CSV have already been through `validate_email`, so stored emails are keyed the same way.
+    let (rows, accepted) = parse_rows(&body, query.headers.unwrap_or(true), |email, full_name, rec| {
+        let is_admin = parse_flag(rec.get(2).unwrap_or(""))
+            .ok_or_else(|| "invalid admin flag".to_string())?;
+        Ok(match existing.get(&email) {
+            Some(s) => staff::NewStaff {
+                is_admin: Some(is_admin || s.is_admin),
+                email: s.email.clone(),
+                full_name,
+            },
+            None => staff::NewStaff {
+                is_admin: Some(is_admin),
+                email,
+                full_name,
+            },
+        })
+    });
+
+    let dry_run = query.dry_run.unwrap_or(false);
+    if !dry_run && !accepted.is_empty() {
+        conn.raw()
+            .transaction::<_, result::Error, _>(|| staff::create_batch(&conn, &accepted))
+            .map_err(|e| diesel_error_handler!(e))?;
+        audit::record(
+            &conn,
+            &usr.email,
+            "staff.import",
+            "staff",
+            None,
+            None,
+            audit::snapshot(&accepted.iter().map(|s| &s.email).collect::<Vec<&String>>()),
+        );
+        webhook::emit(
+            &conn,
+            "staff.created",
+            Some(json!({ "emails": accepted.iter().map(|s| &s.email).collect::<Vec<&String>>() })),
+        );
+    }
+
+    Ok(Json(build_report(dry_run, rows)))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/staff/import", format = "text/csv", data = "<data>", rank = 2)]
+fn import_staff_default(
+    data: Data,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<ImportReport> {
+    import_staff(ImportQuery::default(), data, usr, conn)
+}
+
+/// Imports students from a CSV of `email,full_name[,programme[,modules]]` rows and enrols them in a session. Modules
+/// are separated by semicolons. Rows with attribute columns replace the student's existing attributes, while rows
+/// without them leave any existing attributes alone.
+#[allow(needless_pass_by_value)]
+#[post("/students/import?<query>", format = "text/csv", data = "<data>")]
+fn import_students(
+    query: ImportQuery,
+    data: Data,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<ImportReport> {
+    use diesel::prelude::*;
+    use diesel::result;
+
+    let open =
+        session::get_open_sessions(&conn).map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, query.session)?;
+    let body = read_body(data)?;
+
+    let (rows, accepted) = parse_rows(&body, query.headers.unwrap_or(true), |email, full_name, rec| {
+        let attrs = if rec.len() > 2 {
+            let programme = non_empty(rec.get(2).unwrap_or("").trim());
+            let mut modules = rec.get(3)
+                .unwrap_or("")
+                .split(';')
+                .map(String::from)
+                .collect::<Vec<String>>();
+            normalise_list(&mut modules);
+            Some((programme, modules))
+        } else {
+            None
+        };
+        Ok((student::NewStudent { email, full_name }, attrs))
+    });
+    let (accepted, attrs): (Vec<student::NewStudent>, Vec<_>) = accepted.into_iter().unzip();
+
+    let dry_run = query.dry_run.unwrap_or(false);
+    if !dry_run && !accepted.is_empty() {
+        let emails = accepted
+            .iter()
+            .map(|s| s.email.clone())
+            .collect::<Vec<String>>();
+        conn.raw()
+            .transaction::<_, result::Error, _>(|| {
+                student::create_batch(&conn, &accepted)?;
+                let ids = student::get_id_map_by_email(&conn, &emails)?;
+                enrolment::enrol_batch(&conn, sess.id, &ids.values().cloned().collect::<Vec<i32>>())?;
+
+                let new_attrs = emails
+                    .iter()
+                    .zip(attrs)
+                    .filter_map(|(email, attr)| {
+                        attr.map(|(programme, modules)| attributes::NewStudentAttributes {
+                            student: ids[email],
+                            programme,
+                            modules,
+                        })
+                    })
+                    .collect::<Vec<attributes::NewStudentAttributes>>();
+                if !new_attrs.is_empty() {
+                    attributes::create_batch(&conn, &new_attrs)?;
+                }
+                Ok(())
+            })
+            .map_err(|e| diesel_error_handler!(e))?;
+        audit::record(
+            &conn,
+            &usr.email,
+            "student.import",
+            "student",
+            None,
+            None,
+            audit::snapshot(&emails),
+        );
+        webhook::emit(&conn, "student.created", Some(json!({ "session": sess.id, "emails": emails })));
+    }
+
+    Ok(Json(build_report(dry_run, rows)))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/students/import", format = "text/csv", data = "<data>", rank = 2)]
+fn import_students_default(
+    data: Data,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<ImportReport> {
+    import_students(ImportQuery::default(), data, usr, conn)
+}
+
+fn read_body(data: Data) -> Result<String, ErrorResponse> {
+    let mut body = String::new();
+    data.open()
+        .take(MAX_UPLOAD_SIZE + 1)
+        .read_to_string(&mut body)
+        .map_err(|_| bad_request!("upload must be UTF-8 text"))?;
+    if body.len() as u64 > MAX_UPLOAD_SIZE {
+        return Err(bad_request!("upload is larger than {} bytes", MAX_UPLOAD_SIZE));
+    }
+    Ok(body)
+}
+
+/// Parses and validates CSV rows, where the first two columns are the email and full name. Rows passing the common
+/// checks are handed to `build` for any further validation. Returns a report for every row along with the built values
+/// for accepted rows.
+fn parse_rows<T, F>(body: &str, headers: bool, mut build: F) -> (Vec<ImportRowReport>, Vec<T>)
+where
+    F: FnMut(String, String, &StringRecord) -> Result<T, String>,
+{
+    let mut reader = ReaderBuilder::new()
+        .has_headers(headers)
+        .flexible(true)
+        .from_reader(body.as_bytes());
+    let mut reports = Vec::new();
+    let mut accepted = Vec::new();
+    // Maps emails to the row they were first accepted from, to detect duplicates.
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (i, rec) in reader.records().enumerate() {
+        let row = i + 1;
+        let rec = match rec {
+            Ok(r) => r,
+            Err(e) => {
+                reports.push(reject(row, None, None, format!("unreadable row: {}", e)));
+                continue;
+            }
+        };
+        let raw_email = rec.get(0).unwrap_or("").trim().to_string();
+        let full_name = rec.get(1).unwrap_or("").trim().to_string();
+        let email = non_empty(&raw_email);
+        let name = non_empty(&full_name);
+
+        let res = if raw_email.is_empty() && full_name.is_empty() {
+            Err("blank row".to_string())
+        } else if raw_email.is_empty() {
+            Err("missing email".to_string())
+        } else if full_name.is_empty() {
+            Err("missing full name".to_string())
+        } else {
+            validate_email(&raw_email).and_then(|clean| match seen.get(&clean) {
+                Some(prev) => Err(format!("duplicate of row {}", prev)),
+                None => Ok(clean),
+            })
+        };
+
+        match res.and_then(|clean| build(clean.clone(), full_name.clone(), &rec).map(|it| (clean, it))) {
+            Ok((clean, val)) => {
+                seen.insert(clean.clone(), row);
+                accepted.push(val);
+                reports.push(ImportRowReport {
+                    row,
+                    email: Some(clean),
+                    full_name: name,
+                    accepted: true,
+                    reason: None,
+                });
+            }
+            Err(reason) => reports.push(reject(row, email, name, reason)),
+        }
+    }
+
+    (reports, accepted)
+}
+
+/// Normalises an email the same way logins are, so imported users can log in.
+fn validate_email(email: &str) -> Result<String, String> {
+    if email.chars().any(char::is_whitespace) || email.starts_with('@') || email.ends_with('@') {
+        return Err("invalid email address".to_string());
+    }
+    util::sanitise_email(&email.to_lowercase()).map_err(|_| "invalid email address".to_string())
+}
+
+/// Parses an optional yes/no column. Blank values are treated as no.
+fn parse_flag(val: &str) -> Option<bool> {
+    match &val.trim().to_lowercase()[..] {
+        "" | "0" | "n" | "no" | "false" => Some(false),
+        "1" | "y" | "yes" | "true" => Some(true),
+        _ => None,
+    }
+}
+
+fn non_empty(val: &str) -> Option<String> {
+    if val.is_empty() {
+        None
+    } else {
+        Some(val.to_string())
+    }
+}
+
+fn reject(row: usize, email: Option<String>, full_name: Option<String>, reason: String) -> ImportRowReport {
+    ImportRowReport {
+        row,
+        email,
+        full_name,
+        accepted: false,
+        reason: Some(reason),
+    }
+}
+
+fn build_report(dry_run: bool, rows: Vec<ImportRowReport>) -> ImportReport {
+    let accepted = rows.iter().filter(|it| it.accepted).count();
+    ImportReport {
+        dry_run,
+        accepted,
+        rejected: rows.len() - accepted,
+        rows,
+    }
+}
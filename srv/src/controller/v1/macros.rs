@@ -19,43 +19,43 @@ macro_rules! generic_message {
     ($( $arg:tt )*) => (
         #[allow(useless_format)]
         Json(GenericMessage {
-            message: format!($($arg),*),
+            message: format!($($arg)*),
         })
     )
 }
 
 macro_rules! generic_error {
     ($status:path, $( $arg:tt )*) => (
-        status::Custom($status, generic_message!($($arg),*))
+        status::Custom($status, generic_message!($($arg)*))
     )
 }
 
 macro_rules! ok {
-    ($( $arg:tt )*) => (generic_error!(Status::Ok, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::Ok, $($arg)*))
 }
 
 macro_rules! bad_request {
-    ($( $arg:tt )*) => (generic_error!(Status::BadRequest, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::BadRequest, $($arg)*))
 }
 
 macro_rules! unauthorized {
-    ($( $arg:tt )*) => (generic_error!(Status::Unauthorized, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::Unauthorized, $($arg)*))
 }
 
 macro_rules! forbidden {
-    ($( $arg:tt )*) => (generic_error!(Status::Forbidden, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::Forbidden, $($arg)*))
 }
 
 macro_rules! not_found {
-    ($( $arg:tt )*) => (generic_error!(Status::NotFound, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::NotFound, $($arg)*))
 }
 
 macro_rules! internal_server_error {
-    ($( $arg:tt )*) => (generic_error!(Status::InternalServerError, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::InternalServerError, $($arg)*))
 }
 
 macro_rules! not_implemented {
-    ($( $arg:tt )*) => (generic_error!(Status::NotImplemented, $($arg),*))
+    ($( $arg:tt )*) => (generic_error!(Status::NotImplemented, $($arg)*))
 }
 
 macro_rules! diesel_error_handler {
@@ -68,7 +68,7 @@ macro_rules! diesel_error_handler {
 
 macro_rules! select_error_handler {
     ($( $arg:tt )*) => (|e| match e {
-        SelectError::NoSuchValue() => not_found!($($arg),*),
+        SelectError::NoSuchValue() => not_found!($($arg)*),
         SelectError::DieselError(e) => diesel_error_handler!(e),
     })
 }
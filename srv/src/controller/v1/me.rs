@@ -2,10 +2,13 @@ v1_imports!();
 
 use bigdecimal::BigDecimal;
 use num_traits::cast::FromPrimitive;
-use rocket::Route;
+use rocket::{Route, State};
 
-use db::student::{comment, mark, selection, Student};
-use db::{project, session};
+use config::Config;
+
+use db::student::{attributes, comment, history, mark, selection, Student};
+use db::{allocation, audit, project, session};
+use mail;
 
 pub fn get_routes() -> Vec<Route> {
     routes![get_marks, add_mark, rm_mark, set_selections, set_comment]
@@ -26,7 +29,16 @@ fn add_mark(
     usr: Student,
     conn: DatabaseConnection,
 ) -> V1Response<GenericMessage> {
-    project::get_project(&conn, body.id).map_err(select_error_handler!("no such project"))?;
+    let proj =
+        project::get_project(&conn, body.id).map_err(select_error_handler!("no such project"))?;
+    if proj.status != project::APPROVED {
+        return Err(not_found!("no such project"));
+    }
+    let open = session::get_open_sessions_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("unable to get open sessions"))?;
+    if !open.iter().any(|it| it.id == proj.session) {
+        return Err(bad_request!("project is not in one of your open sessions"));
+    }
     mark::create(
         &conn,
         &mark::NewStudentMark {
@@ -41,6 +53,15 @@ fn add_mark(
             _ => diesel_error_handler!(e),
         }
     })?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "mark.create",
+        "project",
+        Some(body.id.to_string()),
+        None,
+        None,
+    );
     Ok(generic_message!("ok"))
 }
 
@@ -54,6 +75,15 @@ fn rm_mark(id: i32, usr: Student, conn: DatabaseConnection) -> V1Response<Generi
             project: id,
         },
     ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "mark.delete",
+        "project",
+        Some(id.to_string()),
+        None,
+        None,
+    );
     Ok(generic_message!("ok"))
 }
 
@@ -63,14 +93,59 @@ fn set_selections(
     body: Json<SelectionList>,
     usr: Student,
     conn: DatabaseConnection,
+    conf: State<Config>,
 ) -> V1Response<GenericMessage> {
+    use diesel::prelude::*;
     use diesel::result;
 
-    if body.selections.len() != 3 {
-        return Err(bad_request!("only three selections are allowed"));
+    if body.selections.len() != selection::REQUIRED {
+        return Err(bad_request!("exactly {} selections are required", selection::REQUIRED));
     }
 
-    selection::clear_all_for_student(&conn, usr.id).map_err(|e| diesel_error_handler!(e))?;
+    // Check each project exists up front, since the foreign key check won't catch deleted projects. This also tells us
+    // which session the selections are for.
+    let mut sel_sessions = Vec::with_capacity(body.selections.len());
+    let mut names = Vec::with_capacity(body.selections.len());
+    for sel in &body.selections {
+        let proj = project::get_project(&conn, sel.project).map_err(|e| match e {
+            SelectError::NoSuchValue() => bad_request!("unknown project in selections"),
+            SelectError::DieselError(e) => diesel_error_handler!(e),
+        })?;
+        if proj.status != project::APPROVED {
+            return Err(bad_request!("unknown project in selections"));
+        }
+        sel_sessions.push(proj.session);
+        names.push(proj.name);
+    }
+    sel_sessions.dedup();
+    if sel_sessions.len() != 1 {
+        return Err(bad_request!("all selections must be from the same session"));
+    }
+
+    let ids = body.selections.iter().map(|it| it.project).collect::<Vec<i32>>();
+    let rules = project::get_eligibility(&conn, &ids)
+        .map_err(select_error_handler!("unable to fetch eligibility rules"))?;
+    let attrs = attributes::get_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("unable to fetch student attributes"))?;
+    for id in &ids {
+        let reasons = rules.get(id).map(|it| it.check(attrs.as_ref())).unwrap_or_default();
+        if !reasons.is_empty() {
+            return Err(bad_request!("not eligible for project {}: {}", id, reasons.join(", ")));
+        }
+    }
+    // Projects already allocated, such as accepted proposals, are only open to the student they're allocated to.
+    let taken = allocation::get_all_for_projects(&conn, &ids)
+        .map_err(select_error_handler!("unable to fetch allocations"))?;
+    if let Some(a) = taken.iter().find(|it| it.student != usr.id) {
+        return Err(bad_request!("project {} is already allocated", a.project));
+    }
+
+    let open = session::get_open_sessions_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, sel_sessions.pop())?;
+    let before = selection::get_all_for_student(&conn, usr.id, sess.id)
+        .map_err(select_error_handler!("unable to fetch existing selections"))?;
+
     let raw_sels = &body.selections;
     let new_sels: Vec<selection::NewStudentSelection> = raw_sels
         .into_iter()
@@ -80,13 +155,50 @@ fn set_selections(
             weight: BigDecimal::from_f64(it.weight).unwrap(),
         })
         .collect();
+    let after = new_sels
+        .iter()
+        .map(|it| (it.project, it.weight.clone()))
+        .collect::<Vec<(i32, BigDecimal)>>();
+    let comm = match comment::get_for_student(&conn, usr.id, sess.id) {
+        Ok(c) => c,
+        Err(SelectError::NoSuchValue()) => None,
+        Err(SelectError::DieselError(e)) => return Err(diesel_error_handler!(e)),
+    };
 
-    selection::create_batch(&conn, &new_sels).map_err(|e| match e {
-        result::Error::DatabaseError(result::DatabaseErrorKind::ForeignKeyViolation, _) => {
-            bad_request!("unknown project in selections")
-        }
-        _ => diesel_error_handler!(e),
-    })?;
+    conn.raw()
+        .transaction::<_, result::Error, _>(|| {
+            selection::clear_all_for_student(&conn, usr.id, sess.id)?;
+            selection::create_batch(&conn, &new_sels)?;
+            history::record(&conn, usr.id, sess.id, comm, &after)?;
+            Ok(())
+        })
+        .map_err(|e| match e {
+            result::Error::DatabaseError(result::DatabaseErrorKind::ForeignKeyViolation, _) => {
+                bad_request!("unknown project in selections")
+            }
+            _ => diesel_error_handler!(e),
+        })?;
+
+    audit::record(
+        &conn,
+        &usr.email,
+        "selection.set",
+        "student",
+        Some(usr.id.to_string()),
+        Some(audit::snapshot_selections(&before)),
+        Some(audit::snapshot_selections(&after)),
+    );
+    mail::queue(
+        &conn,
+        &conf,
+        mail::Template::SelectionsConfirmed,
+        &usr.email,
+        &[
+            ("name", usr.full_name.clone()),
+            ("session", sess.name.clone()),
+            ("selections", names.iter().map(|it| format!("- {}", it)).collect::<Vec<_>>().join("\n")),
+        ],
+    );
 
     Ok(generic_message!("ok"))
 }
@@ -98,15 +210,39 @@ fn set_comment(
     usr: Student,
     conn: DatabaseConnection,
 ) -> V1Response<GenericMessage> {
-    let sess = session::get_latest_session(&conn)
-        .map_err(select_error_handler!("unable to get current session"))?;
-    comment::create(
+    use diesel::prelude::*;
+    use diesel::result;
+
+    let open = session::get_open_sessions_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, body.session)?;
+    let sels = selection::get_all_for_student(&conn, usr.id, sess.id)
+        .map_err(select_error_handler!("unable to fetch existing selections"))?;
+
+    conn.raw()
+        .transaction::<_, result::Error, _>(|| {
+            comment::create(
+                &conn,
+                &comment::NewStudentComment {
+                    student: usr.id,
+                    session: sess.id,
+                    comment: body.comment.clone(),
+                },
+            )?;
+            history::record(&conn, usr.id, sess.id, body.comment.clone(), &sels)?;
+            Ok(())
+        })
+        .map_err(|e| diesel_error_handler!(e))?;
+
+    audit::record(
         &conn,
-        &comment::NewStudentComment {
-            student: usr.id,
-            session: sess.id,
-            comment: body.comment.clone(),
-        },
-    ).map_err(|e| diesel_error_handler!(e))?;
+        &usr.email,
+        "comment.set",
+        "student",
+        Some(usr.id.to_string()),
+        None,
+        audit::snapshot(&body.comment),
+    );
     Ok(generic_message!("ok"))
 }
+
@@ -1,11 +1,17 @@
 use std::sync::Arc;
 
+use base64;
 use rocket::http::{Cookie, Cookies};
 use rocket::response::content;
 use rocket::{Catcher, Route, State};
+use serde::Serialize;
+use serde_json::{self, Map, Value};
 
 use authn::{AuthnBackend, AuthnFailure, AuthnHolder};
 use config::Config as HPASConfig;
+use db::audit as db_audit;
+use db::session as db_session;
+use db::{Cursor, Page, PageRequest};
 use db::user;
 use session::{Session, SessionManager};
 use util;
@@ -14,13 +20,23 @@ mod types;
 
 #[macro_use]
 mod macros;
+mod archive;
+mod attachment;
+mod audit;
+mod deleted;
 mod errors;
+mod import;
 mod me;
 mod meta;
+mod notification;
 mod project;
+mod proposal;
+mod report;
 mod session;
 mod staff;
 mod student;
+mod tag;
+mod webhook;
 
 v1_imports!();
 
@@ -45,12 +61,22 @@ pub fn get_routes(conf: &HPASConfig) -> Vec<Route> {
 
     concat_vec![
         mod_routes,
+        archive::get_routes(),
+        attachment::get_routes(),
+        audit::get_routes(),
+        deleted::get_routes(),
+        import::get_routes(),
         session::get_routes(),
         project::get_routes(),
+        proposal::get_routes(),
+        report::get_routes(),
         staff::get_routes(),
         student::get_routes(),
+        tag::get_routes(),
         me::get_routes(),
         meta::get_routes(),
+        notification::get_routes(),
+        webhook::get_routes(),
     ]
 }
 
@@ -58,6 +84,162 @@ pub fn get_catchers(_conf: &HPASConfig) -> Vec<Catcher> {
     errors::get_catchers()
 }
 
+/// Picks which session a request applies to from the open sessions available to the caller. An explicitly requested
+/// session must be one of those available; otherwise there must be exactly one to choose from.
+fn resolve_session(
+    open: Vec<db_session::Session>,
+    requested: Option<i32>,
+) -> Result<db_session::Session, ErrorResponse> {
+    match requested {
+        Some(id) => open.into_iter()
+            .find(|it| it.id == id)
+            .ok_or_else(|| bad_request!("session {} is not open", id)),
+        None if open.len() > 1 => Err(bad_request!(
+            "multiple sessions are open; specify which session to use"
+        )),
+        None => open.into_iter()
+            .next()
+            .ok_or_else(|| bad_request!("no open session")),
+    }
+}
+
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 500;
+
+/// Builds a page request from the list parameters shared by list endpoints: `limit`, an opaque `cursor` taken from a
+/// previous page, and `sort`, a column name optionally prefixed with `-` for descending order. Returns `None` if none
+/// were given, so endpoints can keep returning the whole list to existing clients. A sort on its own returns the whole
+/// list in that order, and a cursor on its own carries on in the order it was issued for.
+fn page_request(
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    sort_keys: &[&str],
+    default_sort: &str,
+) -> Result<Option<PageRequest>, ErrorResponse> {
+    if limit.is_none() && cursor.is_none() && sort.is_none() {
+        return Ok(None);
+    }
+
+    let after = match cursor {
+        Some(c) => Some(decode_cursor(&c)?),
+        None => None,
+    };
+    let sort = match (sort, after.as_ref()) {
+        (Some(sort), _) => sort,
+        (None, Some(after)) => sort_param(&after.sort, after.descending),
+        (None, None) => default_sort.to_string(),
+    };
+    let (descending, key) = if sort.starts_with('-') {
+        (true, sort[1..].to_string())
+    } else {
+        (false, sort)
+    };
+    if !sort_keys.contains(&&key[..]) {
+        return Err(bad_request!(
+            "cannot sort by '{}'; expected one of: {}",
+            key,
+            sort_keys.join(", ")
+        ));
+    }
+    if let Some(ref after) = after {
+        if after.sort != key || after.descending != descending {
+            return Err(bad_request!("cursor is for a different sort order"));
+        }
+    }
+
+    let limit = if limit.is_some() || after.is_some() {
+        Some(limit.unwrap_or(DEFAULT_PAGE_LIMIT).max(1).min(MAX_PAGE_LIMIT))
+    } else {
+        None
+    };
+    Ok(Some(PageRequest {
+        after,
+        limit,
+        sort: key,
+        descending,
+    }))
+}
+
+fn sort_param(key: &str, descending: bool) -> String {
+    if descending {
+        format!("-{}", key)
+    } else {
+        key.to_string()
+    }
+}
+
+fn encode_cursor(cursor: &Cursor) -> Option<String> {
+    serde_json::to_vec(cursor)
+        .map(|raw| base64::encode_config(&raw, base64::URL_SAFE))
+        .map_err(|e| error!("Unable to encode cursor {:?}: {}", cursor, e))
+        .ok()
+}
+
+fn decode_cursor(cursor: &str) -> Result<Cursor, ErrorResponse> {
+    base64::decode_config(cursor, base64::URL_SAFE)
+        .ok()
+        .and_then(|raw| serde_json::from_slice::<Cursor>(&raw).ok())
+        .ok_or_else(|| bad_request!("invalid cursor"))
+}
+
+/// Describes a fetched page for the response, including the cursor for the next page if there is one.
+fn page_info<T>(req: &PageRequest, page: &Page<T>) -> PageInfo {
+    PageInfo {
+        total: page.total,
+        limit: req.limit,
+        sort: sort_param(&req.sort, req.descending),
+        next_cursor: page.next.as_ref().and_then(encode_cursor),
+    }
+}
+
+/// Trims each item in a list response down to the fields named in a comma separated `fields` parameter, which must be
+/// among `allowed`. The items are the array under `key`; the rest of the response, such as page details, is left as it
+/// is.
+fn select_fields<T: Serialize>(
+    res: &T,
+    key: &str,
+    allowed: &[&str],
+    fields: Option<String>,
+) -> V1Response<Value> {
+    let mut res = serde_json::to_value(res).map_err(|e| {
+        error!("Unable to serialise response: {}", e);
+        internal_server_error!("unable to serialise response")
+    })?;
+    let fields = match fields {
+        Some(ref fields) => fields
+            .split(',')
+            .map(|it| it.trim())
+            .filter(|it| !it.is_empty())
+            .collect::<Vec<&str>>(),
+        None => return Ok(Json(res)),
+    };
+    if fields.is_empty() {
+        return Err(bad_request!("no fields given"));
+    }
+    if let Some(field) = fields.iter().find(|it| !allowed.contains(it)) {
+        return Err(bad_request!(
+            "unknown field '{}'; expected any of: {}",
+            field,
+            allowed.join(", ")
+        ));
+    }
+
+    if let Some(&mut Value::Array(ref mut items)) = res.get_mut(key) {
+        for item in items.iter_mut() {
+            let trimmed = match *item {
+                Value::Object(ref obj) => fields
+                    .iter()
+                    .filter_map(|it| obj.get(*it).map(|val| (it.to_string(), val.clone())))
+                    .collect::<Map<String, Value>>(),
+                _ => continue,
+            };
+            *item = Value::Object(trimmed);
+        }
+    }
+    Ok(Json(res))
+}
+
 #[allow(needless_pass_by_value)]
 #[post("/auth", data = "<body>")]
 fn login(
@@ -74,6 +256,15 @@ fn login(
                 return Err(internal_server_error!("internal server error"));
             }
             _ => {
+                db_audit::record(
+                    &conn,
+                    &body.username,
+                    "auth.login_failed",
+                    "user",
+                    None,
+                    None,
+                    None,
+                );
                 return Err(forbidden!("incorrect username or password"));
             }
         },
@@ -90,14 +281,27 @@ fn login(
         }
     };
 
+    let allowed = user::can_log_in(&conn, &usr).map_err(select_error_handler!("user does not exist"))?;
+    if !allowed {
+        return Err(forbidden!("you are not enrolled in any open session"));
+    }
+
     let sess = session_manager.new_session(&res, &mut cookies);
     debug!(
         "New session: {:?}",
         sess
     );
+    db_audit::record(
+        &conn,
+        &res,
+        "auth.login",
+        "user",
+        Some(res.clone()),
+        None,
+        None,
+    );
 
     let resp = match usr {
-        // TODO: Check student is valid for *this* session.
         user::User::Student(s) => WhoAmIMessage {
             email: s.email,
             name: s.full_name,
@@ -140,11 +344,21 @@ fn whoami(usr: user::User) -> Json<WhoAmIMessage> {
 #[get("/logout")]
 fn logout(
     sess: Session,
+    conn: DatabaseConnection,
     authn_manager: State<AuthnHolder>,
     session_manager: State<Arc<SessionManager>>,
     mut cookies: Cookies,
 ) -> Result<content::Html<&'static str>, util::RedirectWithBody> {
     cookies.remove_private(Cookie::named("session"));
+    db_audit::record(
+        &conn,
+        &sess.email,
+        "auth.logout",
+        "user",
+        Some(sess.email.clone()),
+        None,
+        None,
+    );
     // Redirect if asked by the auth provider (e.g. Azure AD uses this to provide Single Sign Out)
     if let Some(ref redir) = session_manager.remove_session(&sess.email, &authn_manager) {
         return Err(util::RedirectWithBody::to(redir));
@@ -0,0 +1,47 @@
+v1_imports!();
+
+use rocket::Route;
+
+use db::{notification, user};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_notifications, get_notifications_all, read_notification]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct NotificationQuery {
+    /// Only include notifications which haven't been read yet.
+    unread: Option<bool>,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/notifications?<query>")]
+fn get_notifications(
+    query: NotificationQuery,
+    usr: user::User,
+    conn: DatabaseConnection,
+) -> V1Response<NotificationList> {
+    let notifications =
+        notification::get_all_for_recipient(&conn, &usr.email(), query.unread.unwrap_or(false))
+            .map_err(select_error_handler!("no notifications found"))?;
+    Ok(Json(NotificationList { notifications }))
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/notifications", rank = 2)]
+fn get_notifications_all(usr: user::User, conn: DatabaseConnection) -> V1Response<NotificationList> {
+    get_notifications(NotificationQuery { unread: None }, usr, conn)
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/notifications/<id>/read")]
+fn read_notification(
+    id: i32,
+    usr: user::User,
+    conn: DatabaseConnection,
+) -> V1Response<notification::Notification> {
+    let n = notification::mark_read(&conn, &usr.email(), id)
+        .map_err(select_error_handler!("no such notification"))?;
+    Ok(Json(n))
+}
@@ -1,33 +1,197 @@
 v1_imports!();
 
-use rocket::Route;
+use std::collections::HashMap;
 
-use db::{project, session, staff, student, user};
+use rocket::{Route, State};
+use serde_json::Value;
+
+use config::Config;
+use db::models::normalise_list;
+use db::{audit, demand, notification, project, session, staff, student, tag, user, webhook};
+use mail;
+use markdown;
+use retention;
 use session::Session;
+use util;
+
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
 
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_projs,
+        get_projs_unfiltered,
+        search_projs,
+        search_projs_no_query,
         new_proj,
         update_proj,
         rm_proj,
-        get_project_students
+        restore_proj,
+        get_project_students,
+        get_project_demand,
+        get_review_queue,
+        submit_proj,
+        approve_proj,
+        reject_proj
     ]
 }
 
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Default, Debug)]
+struct ProjectQuery {
+    tag: Option<i32>,
+    /// Supervisor or additional staff member's email.
+    supervisor: Option<String>,
+    session: Option<i32>,
+    /// Review status. Ignored for students, who only see approved projects.
+    status: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    /// Comma separated fields to include for each project.
+    fields: Option<String>,
+}
+
 #[allow(needless_pass_by_value)]
-#[get("/projects")]
-fn get_projs(conn: DatabaseConnection, session: Session) -> V1Response<ProjectList> {
-    let res = match user::find_user(&conn, &session.email[..]) {
-        Some(user::User::Staff(_s)) => project::get_all(&conn),
-        Some(user::User::Student(_s)) => project::get_all_current(&conn),
-        None => panic!("A session exists for a user which does not exist!"),
-    }.map_err(select_error_handler!("no projects found"))?;
+#[get("/projects?<query>")]
+fn get_projs(query: ProjectQuery, conn: DatabaseConnection, session: Session) -> V1Response<Value> {
+    let req = super::page_request(query.limit, query.cursor, query.sort, project::SORT_KEYS, "id")?;
+    let visible_sessions = get_visible_sessions(&conn, &session)?;
+    let filter = project::ProjectFilter {
+        tag: query.tag,
+        supervisor: query.supervisor,
+        session: query.session,
+        approved_only: visible_sessions.is_some(),
+        status: query.status,
+        visible_sessions,
+    };
+    let (res, page) = match req {
+        Some(ref req) => {
+            let page = project::get_filtered_page(&conn, &filter, req)
+                .map_err(select_error_handler!("no projects found"))?;
+            let info = super::page_info(req, &page);
+            (page.items, Some(info))
+        }
+        None => (
+            project::get_filtered(&conn, &filter).map_err(select_error_handler!("no projects found"))?,
+            None,
+        ),
+    };
 
     let projs =
         project::attach_staff(&conn, res).map_err(select_error_handler!("error fetching staff"))?;
+    let ineligible = match user::find_user(&conn, &session.email[..]) {
+        Some(user::User::Student(s)) => Some(find_ineligible(&conn, s.id, &projs)?),
+        _ => None,
+    };
+
+    let res = ProjectList {
+        projects: projs,
+        page,
+        ineligible,
+    };
+    super::select_fields(&res, "projects", project::FIELDS, query.fields)
+}
+
+/// Checks which projects a student doesn't meet the eligibility rules for, so they can be flagged before the student
+/// tries to select them.
+fn find_ineligible(
+    conn: &DatabaseConnection,
+    id: i32,
+    projs: &[project::ProjectWithStaff],
+) -> Result<Vec<IneligibleProject>, ErrorResponse> {
+    let attrs = student::attributes::get_for_student(conn, id)
+        .map_err(select_error_handler!("unable to fetch student attributes"))?;
+    Ok(projs
+        .iter()
+        .map(|p| IneligibleProject {
+            project: p.id,
+            reasons: p.eligibility.check(attrs.as_ref()),
+        })
+        .filter(|it| !it.reasons.is_empty())
+        .collect())
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/projects", rank = 2)]
+fn get_projs_unfiltered(conn: DatabaseConnection, session: Session) -> V1Response<Value> {
+    get_projs(ProjectQuery::default(), conn, session)
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
 
-    Ok(Json(ProjectList { projects: projs }))
+#[allow(needless_pass_by_value)]
+#[get("/projects/search?<query>")]
+fn search_projs(
+    query: SearchQuery,
+    conn: DatabaseConnection,
+    session: Session,
+) -> V1Response<ProjectSearchList> {
+    if query.q.trim().is_empty() {
+        return Err(bad_request!("search query cannot be empty"));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).max(1).min(MAX_SEARCH_LIMIT);
+    let visible_sessions = get_visible_sessions(&conn, &session)?;
+
+    let approved_only = visible_sessions.is_some();
+    let hits = project::search(&conn, &query.q, visible_sessions, approved_only, limit)
+        .map_err(select_error_handler!("no projects found"))?;
+    let ids = hits.iter().map(|it| it.id).collect::<Vec<i32>>();
+    let projs = project::get_all_by_ids(&conn, &ids).map_err(select_error_handler!("no projects found"))?;
+    let mut projs = project::attach_staff(&conn, projs)
+        .map_err(select_error_handler!("error fetching staff"))?
+        .into_iter()
+        .map(|it| (it.id, it))
+        .collect::<HashMap<i32, project::ProjectWithStaff>>();
+
+    // Keep the ranked order from the search.
+    let results = hits.into_iter()
+        .filter_map(|hit| {
+            projs.remove(&hit.id).map(|p| ProjectSearchResult {
+                project: p,
+                rank: hit.rank,
+                name_highlight: highlight_html(&hit.name_highlight),
+                snippet: highlight_html(&hit.snippet),
+            })
+        })
+        .collect();
+
+    Ok(Json(ProjectSearchList { results }))
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/projects/search", rank = 2)]
+fn search_projs_no_query(_session: Session) -> V1Response<ProjectSearchList> {
+    Err(bad_request!("missing search query"))
+}
+
+/// Escapes search highlights for HTML, then swaps the highlight markers for `<mark>` tags.
+fn highlight_html(text: &str) -> String {
+    util::escape_html(text)
+        .replace(project::HIGHLIGHT_START, "<mark>")
+        .replace(project::HIGHLIGHT_END, "</mark>")
+}
+
+/// Finds which sessions' projects the user may see. Students only see projects in the open sessions they're enrolled
+/// in, while staff can see everything.
+pub fn get_visible_sessions(conn: &DatabaseConnection, session: &Session) -> Result<Option<Vec<i32>>, ErrorResponse> {
+    match user::find_user(conn, &session.email[..]) {
+        Some(user::User::Staff(_s)) => Ok(None),
+        Some(user::User::Student(s)) => Ok(Some(
+            session::get_open_sessions_for_student(conn, s.id)
+                .map_err(select_error_handler!("no sessions found"))?
+                .into_iter()
+                .map(|it| it.id)
+                .collect(),
+        )),
+        // The user was removed after logging in, e.g. by purging their only session.
+        None => Err(unauthorized!("no such user")),
+    }
 }
 
 #[allow(needless_pass_by_value)]
@@ -38,57 +202,255 @@ fn new_proj(
     conn: DatabaseConnection,
 ) -> V1Response<project::ProjectWithStaff> {
     if !usr.is_admin {
-        body.supervisor_name = usr.full_name;
-        body.supervisor_email = usr.email;
+        body.supervisor_name = usr.full_name.clone();
+        body.supervisor_email = usr.email.clone();
     }
 
-    match project::create_with_staff(&conn, &body) {
-        Ok(p) => Ok(Json(p)),
+    let open =
+        session::get_open_sessions(&conn).map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, body.session)?;
+    check_tags(&conn, &body.tags)?;
+    check_description(&body.description_md)?;
+    body.eligibility.normalise();
+    // Admins publish projects directly, while other staff have theirs reviewed first.
+    body.status = Some(if usr.is_admin {
+        project::APPROVED.to_string()
+    } else {
+        project::DRAFT.to_string()
+    });
+
+    match project::create_with_staff(&conn, &body, sess.id) {
+        Ok(p) => {
+            audit::record(
+                &conn,
+                &usr.email,
+                "project.create",
+                "project",
+                Some(p.id.to_string()),
+                None,
+                audit::snapshot(&p),
+            );
+            webhook::emit(&conn, "project.created", audit::snapshot(&p));
+            Ok(Json(p))
+        }
         Err(e) => Err(diesel_error_handler!(e)),
     }
 }
 
+/// Updates a project. Its supervisor and co-supervisors can edit it, but only admins can hand it over to another
+/// supervisor or move it to another session, and only the main supervisor or an admin can change its co-supervisors.
 #[allow(needless_pass_by_value)]
 #[put("/projects/<id>", data = "<body>")]
 fn update_proj(
     id: i32,
-    body: Json<project::Project>,
+    body: Json<ProjectUpdate>,
     usr: staff::Staff,
     conn: DatabaseConnection,
-) -> V1Response<project::Project> {
-    if !usr.is_admin && usr.email != body.supervisor_email {
-        return Err(bad_request!("you do not own that project"));
-    }
+) -> V1Response<project::ProjectWithStaff> {
+    use diesel::prelude::*;
+    use diesel::result;
 
+    let mut body = body.into_inner();
     if body.id != id {
         return Err(bad_request!("project ID does not match ID in body"));
     }
 
-    let current_proj = project::get_project(&conn, id).map_err(|e| match e {
-        SelectError::NoSuchValue() => not_found!("no such project"),
-        SelectError::DieselError(e) => diesel_error_handler!(e),
-    })?;
-
-    let (is_curr, _) = session::get_session(&conn, current_proj.session)
-        .map_err(|_e| internal_server_error!("database error"))?;
-
-    if !is_curr {
+    let current_proj = project::get_project(&conn, id).map_err(select_error_handler!("no such project"))?;
+    check_owner(&conn, &usr, &current_proj)?;
+    if !is_current_session(&conn, current_proj.session)? {
         return Err(bad_request!("cannot edit an archived project"));
     }
+    let before = project::attach_staff(&conn, vec![current_proj.clone()])
+        .map_err(select_error_handler!("error fetching staff"))?
+        .pop()
+        .expect("attach_staff returns one entry per project");
 
-    project::update(&conn, &body).map_err(|e| diesel_error_handler!(e))?;
+    if !usr.is_admin {
+        body.supervisor_name = current_proj.supervisor_name.clone();
+        body.supervisor_email = current_proj.supervisor_email.clone();
+        body.session = current_proj.session;
+    } else if body.session != current_proj.session && !is_current_session(&conn, body.session)? {
+        return Err(bad_request!("cannot move a project to an archived session"));
+    }
+    if let Some(ref tags) = body.tags {
+        check_tags(&conn, tags)?;
+    }
+    check_description(&body.description_md)?;
+    if let Some(ref mut rules) = body.eligibility {
+        rules.normalise();
+    }
+    if let Some(ref mut additional) = body.additional_staff {
+        check_additional_staff(&conn, &usr, &before, &body.supervisor_email, additional)?;
+    }
+
+    // Edits by anyone but an admin before approval have to be vetted again, so rejected projects become drafts ready to
+    // be resubmitted. Approved projects stay approved, since students may already have selected them.
+    let (status, status_reason) = if usr.is_admin
+        || current_proj.status == project::SUBMITTED
+        || current_proj.status == project::APPROVED
+    {
+        (current_proj.status.clone(), current_proj.status_reason.clone())
+    } else {
+        (project::DRAFT.to_string(), None)
+    };
+    let proj = project::Project {
+        id: body.id,
+        session: body.session,
+        // Cloned rather than moved, since the transaction below borrows the rest of the body.
+        supervisor_name: body.supervisor_name.clone(),
+        supervisor_email: body.supervisor_email.clone(),
+        name: body.name.clone(),
+        description_md: body.description_md.clone(),
+        // Deletion goes through the delete and restore endpoints only.
+        deleted: None,
+        status,
+        status_reason,
+    };
+    conn.raw()
+        .transaction::<_, result::Error, _>(|| {
+            project::update(&conn, &proj)?;
+            if let Some(ref tags) = body.tags {
+                tag::set_for_project(&conn, id, tags)?;
+            }
+            if let Some(ref rules) = body.eligibility {
+                project::set_eligibility(&conn, id, rules)?;
+            }
+            if let Some(ref additional) = body.additional_staff {
+                project::set_staff(&conn, id, additional)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| diesel_error_handler!(e))?;
+    let res = project::attach_staff(&conn, vec![proj])
+        .map_err(select_error_handler!("error fetching staff"))?
+        .pop()
+        .expect("attach_staff returns one entry per project");
+    audit::record(
+        &conn,
+        &usr.email,
+        "project.update",
+        "project",
+        Some(id.to_string()),
+        audit::snapshot(&before),
+        audit::snapshot(&res),
+    );
+    webhook::emit(&conn, "project.updated", audit::snapshot(&res));
+    if res.status != current_proj.status {
+        webhook::emit(&conn, "project.status_changed", audit::snapshot(&res));
+    }
 
-    Ok(body)
+    Ok(Json(res))
 }
 
 #[allow(needless_pass_by_value)]
 #[delete("/projects/<id>")]
-fn rm_proj(id: i32, _usr: staff::Admin, conn: DatabaseConnection) -> V1Response<GenericMessage> {
+fn rm_proj(id: i32, usr: staff::Admin, conn: DatabaseConnection) -> V1Response<GenericMessage> {
     let p = project::get_project(&conn, id).map_err(select_error_handler!("no such project"))?;
-    project::delete(&conn, &p).map_err(|e| diesel_error_handler!(e))?;
+    project::soft_delete(&conn, p.id).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "project.delete",
+        "project",
+        Some(id.to_string()),
+        audit::snapshot(&p),
+        None,
+    );
+    webhook::emit(&conn, "project.deleted", audit::snapshot(&p));
     Ok(generic_message!("ok"))
 }
 
+#[allow(needless_pass_by_value)]
+#[post("/projects/<id>/restore")]
+fn restore_proj(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<project::Project> {
+    let cutoff = retention::get_cutoff(conf.get_deleted_retention_days());
+    let p = project::restore(&conn, id, cutoff)
+        .map_err(select_error_handler!("no such deleted project"))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "project.restore",
+        "project",
+        Some(id.to_string()),
+        None,
+        audit::snapshot(&p),
+    );
+    webhook::emit(&conn, "project.restored", audit::snapshot(&p));
+    Ok(Json(p))
+}
+
+/// Rejects staff who neither supervise nor co-supervise a project, unless they're an admin.
+pub fn check_owner(conn: &DatabaseConnection, usr: &staff::Staff, p: &project::Project) -> Result<(), ErrorResponse> {
+    if usr.is_admin {
+        return Ok(());
+    }
+    if !project::is_supervised_by(conn, p, &usr.email).map_err(select_error_handler!("no such project"))? {
+        return Err(bad_request!("you do not own that project"));
+    }
+    Ok(())
+}
+
+fn is_current_session(conn: &DatabaseConnection, id: i32) -> Result<bool, ErrorResponse> {
+    let (is_curr, _) = session::get_session(conn, id).map_err(select_error_handler!("no such session"))?;
+    Ok(is_curr)
+}
+
+/// Tidies up a new list of co-supervisors, checking they're all staff and that the user may change the list at all.
+fn check_additional_staff(
+    conn: &DatabaseConnection,
+    usr: &staff::Staff,
+    before: &project::ProjectWithStaff,
+    supervisor: &str,
+    additional: &mut Vec<String>,
+) -> Result<(), ErrorResponse> {
+    normalise_list(additional);
+    additional.retain(|it| it.to_lowercase() != supervisor.to_lowercase());
+
+    let mut old = before.additional_staff.clone();
+    let mut new = additional.clone();
+    old.sort();
+    new.sort();
+    if old == new {
+        return Ok(());
+    }
+    if !usr.is_admin && usr.email.to_lowercase() != before.supervisor_email.to_lowercase() {
+        return Err(bad_request!("only the project's supervisor can change its additional staff"));
+    }
+
+    let known = staff::get_all(conn)
+        .map_err(select_error_handler!("unable to fetch staff"))?
+        .into_iter()
+        .map(|it| it.email.to_lowercase())
+        .collect::<Vec<String>>();
+    let unknown = additional
+        .iter()
+        .filter(|it| !known.contains(&it.to_lowercase()))
+        .cloned()
+        .collect::<Vec<String>>();
+    if !unknown.is_empty() {
+        return Err(bad_request!("no such staff: {}", unknown.join(", ")));
+    }
+    Ok(())
+}
+
+fn check_description(md: &str) -> Result<(), ErrorResponse> {
+    markdown::validate(md).map_err(|e| bad_request!("{}", e))
+}
+
+/// Rejects requests referring to tags which don't exist.
+fn check_tags(conn: &DatabaseConnection, tags: &[i32]) -> Result<(), ErrorResponse> {
+    let unknown = tag::find_unknown(conn, tags).map_err(select_error_handler!("no tags found"))?;
+    if !unknown.is_empty() {
+        return Err(bad_request!("unknown tags: {:?}", unknown));
+    }
+    Ok(())
+}
+
 #[allow(needless_pass_by_value)]
 #[get("/projects/<id>/students")]
 fn get_project_students(
@@ -99,5 +461,199 @@ fn get_project_students(
     let students = student::selection::get_students_for_project(&conn, id)
         .map_err(select_error_handler!("database error"))?;
 
-    Ok(Json(StudentList { students }))
+    Ok(Json(StudentList {
+        students,
+        page: None,
+    }))
+}
+
+/// Shows a project's supervisors how popular it is. How much they see is set per session, while admins see everything.
+#[allow(needless_pass_by_value)]
+#[get("/projects/<id>/demand")]
+fn get_project_demand(id: i32, usr: staff::Staff, conn: DatabaseConnection) -> V1Response<ProjectDemand> {
+    let p = project::get_project(&conn, id).map_err(select_error_handler!("no such project"))?;
+    check_owner(&conn, &usr, &p)?;
+    let (_, sess) =
+        session::get_session(&conn, p.session).map_err(select_error_handler!("no such session"))?;
+    let (visibility, show_marks) = if usr.is_admin {
+        (session::DEMAND_NAMED, true)
+    } else {
+        (&sess.supervisor_demand[..], sess.supervisor_marks)
+    };
+    if visibility == session::DEMAND_HIDDEN && !show_marks {
+        return Err(forbidden!("demand for projects in this session is not visible to supervisors"));
+    }
+
+    let sels = if visibility == session::DEMAND_HIDDEN {
+        Vec::new()
+    } else {
+        demand::get_ranked_selections_for_project(&conn, p.id)
+            .map_err(select_error_handler!("unable to fetch selections"))?
+    };
+    let marked = if show_marks {
+        demand::get_marking_students(&conn, p.id).map_err(select_error_handler!("unable to fetch marks"))?
+    } else {
+        Vec::new()
+    };
+
+    let mut choices = vec![0; student::selection::REQUIRED];
+    for sel in &sels {
+        let idx = (sel.rank - 1) as usize;
+        if idx >= choices.len() {
+            choices.resize(idx + 1, 0);
+        }
+        choices[idx] += 1;
+    }
+    let students = if visibility == session::DEMAND_NAMED {
+        let ids = sels.iter().map(|it| it.student).collect::<Vec<i32>>();
+        let mut by_id = student::get_all_by_ids(&conn, &ids)
+            .map_err(select_error_handler!("unable to fetch students"))?
+            .into_iter()
+            .map(|it| (it.id, it))
+            .collect::<HashMap<i32, student::Student>>();
+        Some(
+            sels.iter()
+                .filter_map(|sel| {
+                    by_id.remove(&sel.student).map(|s| ProjectDemandStudent {
+                        student: s,
+                        rank: sel.rank,
+                        marked: if show_marks { Some(marked.contains(&sel.student)) } else { None },
+                    })
+                })
+                .collect(),
+        )
+    } else {
+        None
+    };
+
+    Ok(Json(ProjectDemand {
+        project: p.id,
+        visibility: visibility.to_string(),
+        choices: if visibility == session::DEMAND_HIDDEN { None } else { Some(choices) },
+        marks: if show_marks { Some(marked.len()) } else { None },
+        students,
+    }))
+}
+
+/// Lists projects waiting to be reviewed.
+#[allow(needless_pass_by_value)]
+#[get("/projects/review")]
+fn get_review_queue(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<ProjectList> {
+    let filter = project::ProjectFilter {
+        status: Some(project::SUBMITTED.to_string()),
+        ..Default::default()
+    };
+    let projs = project::get_filtered(&conn, &filter).map_err(select_error_handler!("no projects found"))?;
+    let projs =
+        project::attach_staff(&conn, projs).map_err(select_error_handler!("error fetching staff"))?;
+
+    Ok(Json(ProjectList {
+        projects: projs,
+        page: None,
+        ineligible: None,
+    }))
+}
+
+/// Submits a draft or rejected project for review.
+#[allow(needless_pass_by_value)]
+#[post("/projects/<id>/submit")]
+fn submit_proj(
+    id: i32,
+    usr: staff::Staff,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<project::Project> {
+    let p = project::get_project(&conn, id).map_err(select_error_handler!("no such project"))?;
+    check_owner(&conn, &usr, &p)?;
+    if p.status != project::DRAFT && p.status != project::REJECTED {
+        return Err(bad_request!("only draft or rejected projects can be submitted"));
+    }
+    change_status(&conn, &conf, &usr, p, project::SUBMITTED, None)
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/projects/<id>/approve")]
+fn approve_proj(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<project::Project> {
+    let p = get_submitted(&conn, id)?;
+    change_status(&conn, &conf, &usr, p, project::APPROVED, None)
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/projects/<id>/reject", data = "<body>")]
+fn reject_proj(
+    id: i32,
+    body: Json<ProjectRejection>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<project::Project> {
+    let reason = body.into_inner().reason;
+    if reason.trim().is_empty() {
+        return Err(bad_request!("a reason must be given when rejecting a project"));
+    }
+    let p = get_submitted(&conn, id)?;
+    change_status(&conn, &conf, &usr, p, project::REJECTED, Some(reason.trim()))
+}
+
+fn get_submitted(conn: &DatabaseConnection, id: i32) -> Result<project::Project, ErrorResponse> {
+    let p = project::get_project(conn, id).map_err(select_error_handler!("no such project"))?;
+    if p.status != project::SUBMITTED {
+        return Err(bad_request!("project has not been submitted for review"));
+    }
+    Ok(p)
+}
+
+/// Updates a project's review status, and lets the supervisor know if someone else changed it.
+fn change_status(
+    conn: &DatabaseConnection,
+    conf: &Config,
+    usr: &staff::Staff,
+    p: project::Project,
+    status: &str,
+    reason: Option<&str>,
+) -> V1Response<project::Project> {
+    let res = project::set_status(conn, p.id, status, reason).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        conn,
+        &usr.email,
+        &format!("project.{}", status),
+        "project",
+        Some(p.id.to_string()),
+        audit::snapshot(&p),
+        audit::snapshot(&res),
+    );
+    webhook::emit(conn, "project.status_changed", audit::snapshot(&res));
+    if usr.email != res.supervisor_email {
+        let message = match reason {
+            Some(r) => format!("Your project \"{}\" was {}: {}", res.name, status, r),
+            None => format!("Your project \"{}\" was {}.", res.name, status),
+        };
+        notification::notify(
+            conn,
+            &res.supervisor_email,
+            "project.status",
+            message,
+            "project",
+            Some(res.id.to_string()),
+        );
+        mail::queue(
+            conn,
+            conf,
+            mail::Template::ProjectStatusChanged,
+            &res.supervisor_email,
+            &[
+                ("name", res.supervisor_name.clone()),
+                ("project", res.name.clone()),
+                ("status", status.to_string()),
+                ("reason", reason.unwrap_or("").to_string()),
+            ],
+        );
+    }
+
+    Ok(Json(res))
 }
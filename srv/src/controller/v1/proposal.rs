@@ -0,0 +1,286 @@
+v1_imports!();
+
+use rocket::{Route, State};
+
+use config::Config;
+use db::student::{self, Student};
+use db::{allocation, audit, notification, project, proposal, session, staff, webhook};
+use mail;
+use markdown;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_my_proposals,
+        new_proposal,
+        get_proposals,
+        accept_proposal,
+        reject_proposal
+    ]
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/me/proposals")]
+fn get_my_proposals(usr: Student, conn: DatabaseConnection) -> V1Response<ProposalList> {
+    let proposals = proposal::get_all_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("no proposals found"))?;
+    Ok(Json(ProposalList { proposals }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/me/proposals", data = "<body>")]
+fn new_proposal(
+    body: Json<NewProposal>,
+    usr: Student,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<proposal::ProjectProposal> {
+    let body = body.into_inner();
+    if body.name.trim().is_empty() {
+        return Err(bad_request!("proposal must have a title"));
+    }
+    // Accepted proposals become projects, so their descriptions are held to the same rules.
+    markdown::validate(&body.description_md).map_err(|e| bad_request!("{}", e))?;
+
+    let open = session::get_open_sessions_for_student(&conn, usr.id)
+        .map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, body.session)?;
+    let supervisor = staff::find_email_ignoring_case(&conn, &body.supervisor_email).map_err(|e| match e {
+        SelectError::NoSuchValue() => bad_request!("suggested supervisor is not a staff member"),
+        SelectError::DieselError(e) => diesel_error_handler!(e),
+    })?;
+    check_unallocated(&conn, usr.id, sess.id)?;
+
+    let prop = proposal::create(
+        &conn,
+        &proposal::NewProjectProposal {
+            student: usr.id,
+            session: sess.id,
+            name: body.name.trim().to_string(),
+            description_md: body.description_md,
+            supervisor_email: supervisor.email.clone(),
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "proposal.create",
+        "proposal",
+        Some(prop.id.to_string()),
+        None,
+        audit::snapshot(&prop),
+    );
+    notification::notify(
+        &conn,
+        &prop.supervisor_email,
+        "proposal.submitted",
+        format!("{} has proposed the project \"{}\" for you to supervise.", usr.full_name, prop.name),
+        "proposal",
+        Some(prop.id.to_string()),
+    );
+    mail::queue(
+        &conn,
+        &conf,
+        mail::Template::ProposalSubmitted,
+        &prop.supervisor_email,
+        &[
+            ("name", supervisor.full_name),
+            ("student", usr.full_name.clone()),
+            ("project", prop.name.clone()),
+        ],
+    );
+
+    Ok(Json(prop))
+}
+
+/// Lists proposals awaiting or given a decision. Admins see every proposal, other staff only those naming them.
+#[allow(needless_pass_by_value)]
+#[get("/proposals")]
+fn get_proposals(usr: staff::Staff, conn: DatabaseConnection) -> V1Response<ProposalList> {
+    let proposals = if usr.is_admin {
+        proposal::get_all(&conn)
+    } else {
+        proposal::get_all_for_supervisor(&conn, &usr.email)
+    }.map_err(select_error_handler!("no proposals found"))?;
+    Ok(Json(ProposalList { proposals }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/proposals/<id>/accept")]
+fn accept_proposal(
+    id: i32,
+    usr: staff::Staff,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<ProposalDecision> {
+    let prop = get_pending(&conn, &usr, id)?;
+    check_unallocated(&conn, prop.student, prop.session)?;
+    // The project is supervised by whoever was named, even if an admin accepts on their behalf.
+    let supervisor = staff::find_email(&conn, &prop.supervisor_email)
+        .map_err(select_error_handler!("suggested supervisor is no longer a staff member"))?;
+
+    let (res, proj) =
+        proposal::accept(&conn, &prop, &supervisor.full_name).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "proposal.accept",
+        "proposal",
+        Some(id.to_string()),
+        audit::snapshot(&prop),
+        audit::snapshot(&res),
+    );
+    webhook::emit(
+        &conn,
+        "allocation.published",
+        Some(json!({ "student": res.student, "session": res.session, "project": proj.id })),
+    );
+    notify_student(&conn, &conf, &res);
+    notify_supervisor(&conn, &conf, &supervisor, &res, &proj);
+
+    Ok(Json(ProposalDecision {
+        proposal: res,
+        project: Some(proj),
+    }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/proposals/<id>/reject", data = "<body>")]
+fn reject_proposal(
+    id: i32,
+    body: Json<ProposalRejection>,
+    usr: staff::Staff,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<ProposalDecision> {
+    let prop = get_pending(&conn, &usr, id)?;
+    let res = proposal::reject(&conn, &prop, body.into_inner().reason)
+        .map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "proposal.reject",
+        "proposal",
+        Some(id.to_string()),
+        audit::snapshot(&prop),
+        audit::snapshot(&res),
+    );
+    notify_student(&conn, &conf, &res);
+
+    Ok(Json(ProposalDecision {
+        proposal: res,
+        project: None,
+    }))
+}
+
+/// Fetches a proposal the user may decide on. It must name them as supervisor (unless they're an admin), still be
+/// pending, and belong to an open session.
+fn get_pending(
+    conn: &DatabaseConnection,
+    usr: &staff::Staff,
+    id: i32,
+) -> Result<proposal::ProjectProposal, ErrorResponse> {
+    let prop = proposal::get(conn, id).map_err(select_error_handler!("no such proposal"))?;
+    if !usr.is_admin && usr.email != prop.supervisor_email {
+        return Err(not_found!("no such proposal"));
+    }
+    if prop.status != proposal::PENDING {
+        return Err(bad_request!("proposal has already been {}", prop.status));
+    }
+    let (is_curr, _) =
+        session::get_session(conn, prop.session).map_err(select_error_handler!("no such session"))?;
+    if !is_curr {
+        return Err(bad_request!("cannot decide on a proposal in an archived session"));
+    }
+    Ok(prop)
+}
+
+/// Rejects requests for students who already have a project in the session.
+fn check_unallocated(conn: &DatabaseConnection, student: i32, sess: i32) -> Result<(), ErrorResponse> {
+    match allocation::get_for_student(conn, student, sess) {
+        Ok(_) => Err(bad_request!("student already has a project allocated in this session")),
+        Err(SelectError::NoSuchValue()) => Ok(()),
+        Err(SelectError::DieselError(e)) => Err(diesel_error_handler!(e)),
+    }
+}
+
+/// Tells a supervisor that a student has been allocated to their project, both in-app and by email. They're told even
+/// if they accepted the proposal themselves, so every supervisor has a record of their allocations.
+fn notify_supervisor(
+    conn: &DatabaseConnection,
+    conf: &Config,
+    supervisor: &staff::Staff,
+    prop: &proposal::ProjectProposal,
+    proj: &project::ProjectWithStaff,
+) {
+    let stud = match student::get(conn, prop.student) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to fetch student {} to notify about allocation: {:?}", prop.student, e);
+            return;
+        }
+    };
+    let sess = match session::get_session(conn, prop.session) {
+        Ok((_, s)) => s,
+        Err(e) => {
+            error!("Unable to fetch session {} to notify about allocation: {:?}", prop.session, e);
+            return;
+        }
+    };
+    notification::notify(
+        conn,
+        &supervisor.email,
+        "allocation.published",
+        format!("{} has been allocated to your project \"{}\".", stud.full_name, proj.name),
+        "project",
+        Some(proj.id.to_string()),
+    );
+    mail::queue(
+        conn,
+        conf,
+        mail::Template::AllocationPublished,
+        &supervisor.email,
+        &[
+            ("name", supervisor.full_name.clone()),
+            ("student", stud.full_name.clone()),
+            ("project", proj.name.clone()),
+            ("session", sess.name.clone()),
+        ],
+    );
+}
+
+/// Tells a student that their proposal has been decided on, both in-app and by email.
+fn notify_student(conn: &DatabaseConnection, conf: &Config, prop: &proposal::ProjectProposal) {
+    let stud = match student::get(conn, prop.student) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to fetch student {} to notify about proposal {}: {:?}", prop.student, prop.id, e);
+            return;
+        }
+    };
+    let reason = prop.reason.clone().unwrap_or_default();
+    let message = if reason.is_empty() {
+        format!("Your proposal \"{}\" was {}.", prop.name, prop.status)
+    } else {
+        format!("Your proposal \"{}\" was {}: {}", prop.name, prop.status, reason)
+    };
+    notification::notify(
+        conn,
+        &stud.email,
+        "proposal.decided",
+        message,
+        "proposal",
+        Some(prop.id.to_string()),
+    );
+    mail::queue(
+        conn,
+        conf,
+        mail::Template::ProposalDecided,
+        &stud.email,
+        &[
+            ("name", stud.full_name.clone()),
+            ("project", prop.name.clone()),
+            ("status", prop.status.clone()),
+            ("reason", reason),
+        ],
+    );
+}
@@ -0,0 +1,334 @@
+v1_imports!();
+
+use std::collections::HashMap;
+
+use csv;
+use rocket::Route;
+use rocket::http::ContentType;
+use simple_excel_writer::{Row, Workbook};
+
+use db::staff;
+use util::Download;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_report_csv, get_report_csv_default, get_report_xlsx]
+}
+
+/// Sheets included in exported reports, in the order they appear in spreadsheets.
+const SHEETS: &[&str] = &["by-student", "by-project", "comments", "allocation", "violations"];
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct ReportQuery {
+    /// Which sheet to export, as CSV can only hold one. Defaults to `by-student`.
+    sheet: Option<String>,
+}
+
+/// A single value in a report sheet.
+enum Cell {
+    Int(i64),
+    Text(String),
+    Bool(bool),
+    Empty,
+}
+
+impl Cell {
+    fn to_csv(&self) -> String {
+        match *self {
+            Cell::Int(i) => i.to_string(),
+            Cell::Text(ref s) => s.clone(),
+            Cell::Bool(b) => b.to_string(),
+            Cell::Empty => String::new(),
+        }
+    }
+}
+
+/// A table of report data, exported as a CSV file or a spreadsheet worksheet.
+struct Sheet {
+    name: &'static str,
+    header: &'static [&'static str],
+    rows: Vec<Vec<Cell>>,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/report.csv?<query>")]
+fn get_report_csv(
+    id: i32,
+    query: ReportQuery,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> Result<Download, ErrorResponse> {
+    let name = query.sheet.unwrap_or_else(|| SHEETS[0].to_string());
+    if !SHEETS.contains(&&name[..]) {
+        return Err(bad_request!("unknown sheet; expected one of: {}", SHEETS.join(", ")));
+    }
+
+    let report = super::session::build_report(&conn, id)?;
+    let sheet = build_sheets(&report)
+        .into_iter()
+        .find(|it| it.name == name)
+        .expect("every sheet name is built");
+
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    wtr.write_record(sheet.header)
+        .map_err(|e| export_error(&e))?;
+    for row in &sheet.rows {
+        wtr.write_record(row.iter().map(Cell::to_csv))
+            .map_err(|e| export_error(&e))?;
+    }
+    let body = wtr.into_inner().map_err(|e| export_error(&e))?;
+
+    Ok(Download {
+        filename: format!("session-{}-{}.csv", id, sheet.name),
+        content_type: ContentType::new("text", "csv"),
+        body,
+    })
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/report.csv", rank = 2)]
+fn get_report_csv_default(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> Result<Download, ErrorResponse> {
+    get_report_csv(id, ReportQuery { sheet: None }, usr, conn)
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/report.xlsx")]
+fn get_report_xlsx(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> Result<Download, ErrorResponse> {
+    let report = super::session::build_report(&conn, id)?;
+
+    let mut wb = Workbook::create_in_memory();
+    for sheet in build_sheets(&report) {
+        let mut ws = wb.create_sheet(sheet.name);
+        wb.write_sheet(&mut ws, |sw| {
+            let mut header = Row::new();
+            for h in sheet.header {
+                header.add_cell(*h);
+            }
+            sw.append_row(header)?;
+            for cells in &sheet.rows {
+                let mut row = Row::new();
+                for cell in cells {
+                    match *cell {
+                        Cell::Int(i) => row.add_cell(i as f64),
+                        Cell::Text(ref s) => row.add_cell(s.clone()),
+                        Cell::Bool(b) => row.add_cell(b),
+                        Cell::Empty => row.add_cell(""),
+                    }
+                }
+                sw.append_row(row)?;
+            }
+            Ok(())
+        }).map_err(|e| export_error(&e))?;
+    }
+    let body = wb.close()
+        .map_err(|e| export_error(&e))?
+        .expect("in-memory workbooks return their contents");
+
+    Ok(Download {
+        filename: format!("session-{}-report.xlsx", id),
+        content_type: ContentType::new(
+            "application",
+            "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+        body,
+    })
+}
+
+fn export_error<E: ::std::fmt::Display>(e: &E) -> ErrorResponse {
+    error!("Unable to write report export: {}", e);
+    internal_server_error!("unable to write report")
+}
+
+/// Flattens a session report into tables with one row per student choice, project selection or comment.
+fn build_sheets(report: &SessionReport) -> Vec<Sheet> {
+    let students = report
+        .students
+        .iter()
+        .map(|it| (it.id, it))
+        .collect::<HashMap<_, _>>();
+    let projects = report
+        .projects
+        .iter()
+        .map(|it| (it.id, it))
+        .collect::<HashMap<_, _>>();
+    let student_cells = |id: i32| match students.get(&id) {
+        Some(s) => vec![
+            Cell::Int(i64::from(id)),
+            Cell::Text(s.email.clone()),
+            Cell::Text(s.full_name.clone()),
+        ],
+        None => vec![Cell::Int(i64::from(id)), Cell::Empty, Cell::Empty],
+    };
+    let project_name = |id: i32| match projects.get(&id) {
+        Some(p) => Cell::Text(p.name.clone()),
+        None => Cell::Empty,
+    };
+
+    // By student: each choice, ranked with ties sharing a rank. Students with no choices get a single blank row.
+    let mut by_student = Vec::new();
+    for s in &report.students {
+        let entry = report.by_student.iter().find(|it| it.student == s.id);
+        let choices = entry.map(|it| &it.choices[..]).unwrap_or(&[]);
+        if choices.is_empty() {
+            let mut row = student_cells(s.id);
+            row.extend(vec![Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty]);
+            by_student.push(row);
+            continue;
+        }
+        let is_eq = &entry.expect("choices imply an entry").is_eq;
+        let mut rank = 0;
+        for (i, proj) in choices.iter().enumerate() {
+            let tied = i > 0 && is_eq.get(i - 1).cloned().unwrap_or(false);
+            if !tied {
+                rank += 1;
+            }
+            let mut row = student_cells(s.id);
+            row.extend(vec![
+                Cell::Int(rank),
+                Cell::Int(i64::from(*proj)),
+                project_name(*proj),
+                Cell::Bool(tied),
+            ]);
+            by_student.push(row);
+        }
+    }
+
+    // By project: each student selecting the project, by rank. Projects with no interest get a single blank row.
+    let mut by_project = Vec::new();
+    for p in &report.projects {
+        let project_cells = || {
+            vec![
+                Cell::Int(i64::from(p.id)),
+                Cell::Text(p.name.clone()),
+                Cell::Text(p.supervisor_email.clone()),
+            ]
+        };
+        let entry = report.by_project.iter().find(|it| it.project == p.id);
+        let mut any = false;
+        if let Some(entry) = entry {
+            for (rank, (studs, eqs)) in entry.selections.iter().zip(&entry.is_eq).enumerate() {
+                for (stud, eq) in studs.iter().zip(eqs) {
+                    any = true;
+                    let mut row = project_cells();
+                    row.push(Cell::Int(rank as i64 + 1));
+                    row.extend(student_cells(*stud));
+                    row.push(Cell::Bool(*eq));
+                    by_project.push(row);
+                }
+            }
+        }
+        if !any {
+            let mut row = project_cells();
+            row.extend(vec![Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty, Cell::Empty]);
+            by_project.push(row);
+        }
+    }
+
+    let mut comments = Vec::new();
+    for s in &report.students {
+        if let Some(c) = report.comments.get(&s.id) {
+            let mut row = student_cells(s.id);
+            row.push(Cell::Text(c.clone()));
+            comments.push(row);
+        }
+    }
+
+    // Every student is listed, with blank projects for those still to be allocated.
+    let allocated = report
+        .allocations
+        .iter()
+        .map(|it| (it.student, it.project))
+        .collect::<HashMap<_, _>>();
+    let allocation = report
+        .students
+        .iter()
+        .map(|s| {
+            let mut row = student_cells(s.id);
+            match allocated.get(&s.id) {
+                Some(&proj) => row.extend(vec![Cell::Int(i64::from(proj)), project_name(proj)]),
+                None => row.extend(vec![Cell::Empty, Cell::Empty]),
+            }
+            row
+        })
+        .collect();
+
+    let violations = report
+        .violations
+        .iter()
+        .map(|v| {
+            let mut row = student_cells(v.student);
+            row.extend(vec![
+                Cell::Int(i64::from(v.project)),
+                project_name(v.project),
+                Cell::Text(v.reasons.join("; ")),
+            ]);
+            row
+        })
+        .collect();
+
+    vec![
+        Sheet {
+            name: SHEETS[0],
+            header: &[
+                "student_id",
+                "student_email",
+                "student_name",
+                "rank",
+                "project_id",
+                "project_name",
+                "tied_with_previous",
+            ],
+            rows: by_student,
+        },
+        Sheet {
+            name: SHEETS[1],
+            header: &[
+                "project_id",
+                "project_name",
+                "supervisor_email",
+                "rank",
+                "student_id",
+                "student_email",
+                "student_name",
+                "tied",
+            ],
+            rows: by_project,
+        },
+        Sheet {
+            name: SHEETS[2],
+            header: &["student_id", "student_email", "student_name", "comment"],
+            rows: comments,
+        },
+        Sheet {
+            name: SHEETS[3],
+            header: &[
+                "student_id",
+                "student_email",
+                "student_name",
+                "project_id",
+                "project_name",
+            ],
+            rows: allocation,
+        },
+        Sheet {
+            name: SHEETS[4],
+            header: &[
+                "student_id",
+                "student_email",
+                "student_name",
+                "project_id",
+                "project_name",
+                "reasons",
+            ],
+            rows: violations,
+        },
+    ]
+}
@@ -1,28 +1,112 @@
 v1_imports!();
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use bigdecimal::BigDecimal;
-use rocket::Route;
+use chrono::Utc;
+use rocket::{Route, State};
+use serde_json::Value;
 
-use db::{project, session, staff, student, user};
+use authn::AuthnHolder;
+use config::Config;
+use db::{allocation, audit, demand, project, session, staff, student, user, webhook};
+use reminder;
+use session::SessionManager;
+
+/// Number of oversubscribed projects listed in session statistics.
+const MAX_OVERSUBSCRIBED: usize = 10;
 
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_sessions_full,
+        get_sessions_full_paged,
         new_session,
+        clone_session,
         archive_session,
+        set_session_deadline,
+        set_session_demand_visibility,
+        preview_session_reminders,
         rm_session,
-        get_session_report
+        preview_rm_session,
+        get_session_report,
+        get_session_analytics,
+        get_session_stats
     ]
 }
 
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct SessionQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    /// Comma separated fields to include for each project.
+    fields: Option<String>,
+}
+
+/// Pages through sessions, returning only the projects belonging to sessions on the page.
 #[allow(needless_pass_by_value)]
-#[get("/sessions/complete")]
+#[get("/sessions/complete?<query>")]
+fn get_sessions_full_paged(
+    query: SessionQuery,
+    usr: user::User,
+    conn: DatabaseConnection,
+) -> V1Response<Value> {
+    let req = super::page_request(query.limit, query.cursor, query.sort, session::SORT_KEYS, "-created")?;
+    let req = match req {
+        Some(r) => r,
+        None => {
+            let res = get_sessions_full(usr, conn)?.into_inner();
+            return super::select_fields(&res, "projects", project::FIELDS, query.fields);
+        }
+    };
+
+    let visible = match usr {
+        user::User::Staff(_) => None,
+        user::User::Student(ref s) => Some(
+            session::get_open_sessions_for_student(&conn, s.id)
+                .map_err(select_error_handler!("no sessions found"))?
+                .into_iter()
+                .map(|it| it.id)
+                .collect::<Vec<i32>>(),
+        ),
+    };
+    let page = session::get_page(&conn, visible.as_ref().map(|it| &it[..]), &req)
+        .map_err(select_error_handler!("no sessions found"))?;
+    let info = super::page_info(&req, &page);
+
+    let filter = project::ProjectFilter {
+        visible_sessions: Some(page.items.iter().map(|it| it.1.id).collect()),
+        approved_only: visible.is_some(),
+        ..Default::default()
+    };
+    let projects =
+        project::get_filtered(&conn, &filter).map_err(select_error_handler!("no projects found"))?;
+    let projects_staffed = project::attach_staff(&conn, projects)
+        .map_err(select_error_handler!("error fetching additional staff"))?;
+
+    let res = SessionFullList {
+        sessions: page.items
+            .into_iter()
+            .map(|(current, sess)| SessionEntry {
+                session: sess,
+                is_current: current,
+            })
+            .collect(),
+        projects: projects_staffed,
+        page: Some(info),
+    };
+    super::select_fields(&res, "projects", project::FIELDS, query.fields)
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/sessions/complete", rank = 2)]
 fn get_sessions_full(usr: user::User, conn: DatabaseConnection) -> V1Response<SessionFullList> {
     let sessions_fetch = match usr {
         user::User::Staff(_) => session::get_all(&conn),
-        user::User::Student(_) => session::get_latest_session(&conn).map(|it| vec![(true, it)]),
+        user::User::Student(ref s) => session::get_open_sessions_for_student(&conn, s.id)
+            .map(|it| it.into_iter().map(|sess| (true, sess)).collect()),
     }.map_err(select_error_handler!("no sessions found"))?;
     let sessions = sessions_fetch
         .into_iter()
@@ -34,7 +118,7 @@ fn get_sessions_full(usr: user::User, conn: DatabaseConnection) -> V1Response<Se
 
     let projects = match usr {
         user::User::Staff(_) => project::get_all(&conn),
-        user::User::Student(_) => project::get_all_current(&conn),
+        user::User::Student(ref s) => project::get_all_current_for_student(&conn, s.id),
     }.map_err(select_error_handler!("no projects found"))?;
 
     let projects_staffed = project::attach_staff(&conn, projects)
@@ -43,6 +127,7 @@ fn get_sessions_full(usr: user::User, conn: DatabaseConnection) -> V1Response<Se
     Ok(Json(SessionFullList {
         sessions,
         projects: projects_staffed,
+        page: None,
     }))
 }
 
@@ -50,33 +135,262 @@ fn get_sessions_full(usr: user::User, conn: DatabaseConnection) -> V1Response<Se
 #[post("/sessions", data = "<body>")]
 fn new_session(
     mut body: Json<session::NewSession>,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
 ) -> V1Response<session::Session> {
     body.created = None;
     body.force_archive = None;
+    if let Some(ref demand) = body.supervisor_demand {
+        check_demand_visibility(demand)?;
+    }
     let sess = session::create(&conn, &body).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.create",
+        "session",
+        Some(sess.id.to_string()),
+        None,
+        audit::snapshot(&sess),
+    );
+    webhook::emit(&conn, "session.opened", audit::snapshot(&sess));
     Ok(Json(sess))
 }
 
+#[allow(needless_pass_by_value)]
+#[post("/sessions/<id>/clone", data = "<body>")]
+fn clone_session(
+    id: i32,
+    body: Json<SessionCloneRequest>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<SessionCloneResult> {
+    let body = body.into_inner();
+    let (_, src) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+
+    let mut projs = project::get_all_by_session(&conn, src.id)
+        .map_err(select_error_handler!("no projects found"))?;
+    if let Some(ref wanted) = body.projects {
+        if wanted.iter().any(|p| !projs.iter().any(|it| it.id == *p)) {
+            return Err(bad_request!("not all projects are in the session being cloned"));
+        }
+        projs.retain(|it| wanted.contains(&it.id));
+    }
+    let projs = project::attach_staff(&conn, projs)
+        .map_err(select_error_handler!("error fetching additional staff"))?;
+
+    let current_staff = staff::get_all(&conn)
+        .map_err(select_error_handler!("unable to fetch staff"))?
+        .into_iter()
+        .map(|it| it.email)
+        .collect::<HashSet<String>>();
+    let mut skipped = Vec::new();
+    let mut new_projs = Vec::with_capacity(projs.len());
+    for p in projs {
+        let mut additional_staff = p.additional_staff;
+        if !body.keep_removed_staff {
+            if !current_staff.contains(&p.supervisor_email) {
+                skipped.push(p.id);
+                continue;
+            }
+            additional_staff.retain(|it| current_staff.contains(it));
+        }
+        new_projs.push(project::NewProjectWithStaff {
+            supervisor_name: p.supervisor_name,
+            supervisor_email: p.supervisor_email,
+            name: p.name,
+            description_md: p.description_md,
+            additional_staff,
+            session: None,
+            tags: p.tags,
+            eligibility: p.eligibility,
+            // Anything not yet approved goes back to being a draft, as review reasons aren't copied.
+            status: Some(if p.status == project::APPROVED {
+                p.status
+            } else {
+                project::DRAFT.to_string()
+            }),
+        });
+    }
+
+    let new_sess = session::NewSession {
+        name: body.name,
+        supervisor_name: body.supervisor_name.unwrap_or_else(|| src.supervisor_name.clone()),
+        supervisor_email: body.supervisor_email.unwrap_or_else(|| src.supervisor_email.clone()),
+        created: None,
+        force_archive: None,
+        deadline: body.deadline,
+        supervisor_demand: Some(src.supervisor_demand.clone()),
+        supervisor_marks: Some(src.supervisor_marks),
+    };
+    let (sess, projects) = session::clone_with_projects(&conn, &new_sess, new_projs)
+        .map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.clone",
+        "session",
+        Some(sess.id.to_string()),
+        audit::snapshot(&src),
+        audit::snapshot(&sess),
+    );
+    webhook::emit(&conn, "session.opened", audit::snapshot(&sess));
+
+    Ok(Json(SessionCloneResult {
+        session: sess,
+        projects,
+        skipped,
+    }))
+}
+
 #[allow(needless_pass_by_value)]
 #[post("/sessions/<id>/archive")]
 fn archive_session(
     id: i32,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
 ) -> V1Response<GenericMessage> {
     let (_, mut sess) =
         session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let before = audit::snapshot(&sess);
     sess.force_archive = true;
     let sess = sess;
     session::update(&conn, &sess).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.archive",
+        "session",
+        Some(id.to_string()),
+        before,
+        audit::snapshot(&sess),
+    );
+    webhook::emit(&conn, "session.archived", audit::snapshot(&sess));
     Ok(generic_message!("ok"))
 }
 
+#[allow(needless_pass_by_value)]
+#[put("/sessions/<id>/deadline", data = "<body>")]
+fn set_session_deadline(
+    id: i32,
+    body: Json<SessionDeadline>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<session::Session> {
+    let (active, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    if !active {
+        return Err(bad_request!("cannot set the deadline of an archived session"));
+    }
+    let res = session::update(
+        &conn,
+        &session::Session {
+            deadline: body.deadline,
+            ..sess.clone()
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.deadline",
+        "session",
+        Some(id.to_string()),
+        audit::snapshot(&sess),
+        audit::snapshot(&res),
+    );
+    webhook::emit(&conn, "session.deadline_changed", audit::snapshot(&res));
+    Ok(Json(res))
+}
+
+/// Sets what supervisors can see of the demand for their own projects in a session.
+#[allow(needless_pass_by_value)]
+#[put("/sessions/<id>/demand", data = "<body>")]
+fn set_session_demand_visibility(
+    id: i32,
+    body: Json<SessionDemandVisibility>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<session::Session> {
+    let body = body.into_inner();
+    check_demand_visibility(&body.supervisor_demand)?;
+    let (_, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let res = session::update(
+        &conn,
+        &session::Session {
+            supervisor_demand: body.supervisor_demand,
+            supervisor_marks: body.supervisor_marks,
+            ..sess.clone()
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.demand",
+        "session",
+        Some(id.to_string()),
+        audit::snapshot(&sess),
+        audit::snapshot(&res),
+    );
+    Ok(Json(res))
+}
+
+fn check_demand_visibility(demand: &str) -> Result<(), ErrorResponse> {
+    if !session::DEMAND_VISIBILITIES.contains(&demand) {
+        return Err(bad_request!(
+            "unknown demand visibility {}; expected one of: {}",
+            demand,
+            session::DEMAND_VISIBILITIES.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Lists the students who will be sent the next deadline reminder, so admins can check before it goes out.
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/reminders")]
+fn preview_session_reminders(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<ReminderPreview> {
+    let (_, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let plan = reminder::plan(&conn, &conf.get_reminder_hours(), &sess, Utc::now().naive_utc())
+        .map_err(select_error_handler!("unable to work out reminders"))?;
+    Ok(Json(match plan {
+        Some(plan) => ReminderPreview {
+            session: sess,
+            hours_before: Some(plan.hours_before),
+            send_at: Some(plan.send_at),
+            students: plan.students
+                .into_iter()
+                .map(|(student, selections)| ReminderPreviewStudent {
+                    student,
+                    selections,
+                })
+                .collect(),
+        },
+        None => ReminderPreview {
+            session: sess,
+            hours_before: None,
+            send_at: None,
+            students: Vec::new(),
+        },
+    }))
+}
+
 #[allow(needless_pass_by_value)]
 #[delete("/sessions/<id>")]
-fn rm_session(id: i32, _usr: staff::Admin, conn: DatabaseConnection) -> V1Response<GenericMessage> {
+fn rm_session(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    auth: State<AuthnHolder>,
+    manager: State<Arc<SessionManager>>,
+) -> V1Response<SessionPurgeSummary> {
     let (active, sess) =
         session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
     if active {
@@ -84,9 +398,156 @@ fn rm_session(id: i32, _usr: staff::Admin, conn: DatabaseConnection) -> V1Respon
             "cannot delete active sessions; archive it first."
         ));
     }
-    // TODO: Also purge assosciated student records.
-    session::delete(&conn, &sess).map_err(|e| diesel_error_handler!(e))?;
-    Ok(generic_message!("ok"))
+    let purged = session::get_purged_student_emails(&conn, id).map_err(|e| diesel_error_handler!(e))?;
+    let counts = session::purge(&conn, id).map_err(|e| diesel_error_handler!(e))?;
+    // Students removed with the session could otherwise carry on using their logins.
+    for email in &purged {
+        manager.remove_session(email, &auth);
+    }
+    audit::record(
+        &conn,
+        &usr.email,
+        "session.delete",
+        "session",
+        Some(id.to_string()),
+        audit::snapshot(&sess),
+        audit::snapshot(&counts),
+    );
+    webhook::emit(&conn, "session.deleted", audit::snapshot(&sess));
+    Ok(Json(SessionPurgeSummary {
+        session: sess,
+        counts,
+    }))
+}
+
+/// Reports what deleting a session would remove, so it can be confirmed before calling `rm_session`.
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/purge")]
+fn preview_rm_session(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<SessionPurgeSummary> {
+    let (_, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let counts = session::count_purge(&conn, id).map_err(|e| diesel_error_handler!(e))?;
+    Ok(Json(SessionPurgeSummary {
+        session: sess,
+        counts,
+    }))
+}
+
+/// Shows how popular each project in a session is, least popular first, along with how selections have come in over
+/// time. This helps spot projects which need promoting while students are still choosing.
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/analytics")]
+fn get_session_analytics(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<SessionAnalytics> {
+    let (_, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let projects = project::get_all_by_session(&conn, sess.id)
+        .map_err(select_error_handler!("no projects found"))?;
+    let sels = demand::get_ranked_selections(&conn, sess.id)
+        .map_err(select_error_handler!("unable to fetch selections"))?;
+    let marks = demand::get_marks_for_session(&conn, sess.id)
+        .map_err(select_error_handler!("unable to fetch marks"))?;
+    let activity = demand::get_selection_activity(&conn, sess.id)
+        .map_err(select_error_handler!("unable to fetch selection activity"))?;
+
+    let selected = sels
+        .iter()
+        .map(|it| (it.student, it.project))
+        .collect::<HashSet<(i32, i32)>>();
+    let mut by_project = projects
+        .into_iter()
+        .map(|p| {
+            (
+                p.id,
+                ProjectAnalytics {
+                    project: p.id,
+                    name: p.name,
+                    supervisor_name: p.supervisor_name,
+                    status: p.status,
+                    marks: 0,
+                    choices: vec![0; student::selection::REQUIRED],
+                    selections: 0,
+                    marked_and_selected: 0,
+                    conversion: None,
+                },
+            )
+        })
+        .collect::<HashMap<i32, ProjectAnalytics>>();
+    for sel in &sels {
+        if let Some(entry) = by_project.get_mut(&sel.project) {
+            let idx = (sel.rank - 1) as usize;
+            if idx >= entry.choices.len() {
+                entry.choices.resize(idx + 1, 0);
+            }
+            entry.choices[idx] += 1;
+            entry.selections += 1;
+        }
+    }
+    for &(student, project) in &marks {
+        if let Some(entry) = by_project.get_mut(&project) {
+            entry.marks += 1;
+            if selected.contains(&(student, project)) {
+                entry.marked_and_selected += 1;
+            }
+        }
+    }
+
+    let mut projects = by_project
+        .into_iter()
+        .map(|(_, mut it)| {
+            if it.marks > 0 {
+                it.conversion = Some(it.marked_and_selected as f64 / it.marks as f64);
+            }
+            it
+        })
+        .collect::<Vec<ProjectAnalytics>>();
+    projects.sort_by_key(|it| (it.selections, it.marks, it.project));
+
+    Ok(Json(SessionAnalytics {
+        session: sess.id,
+        projects,
+        activity,
+    }))
+}
+
+/// Summarises how a session's selections are going. Everything is counted in the database, so it's cheap enough for
+/// dashboards to poll.
+#[allow(needless_pass_by_value)]
+#[get("/sessions/<id>/stats")]
+fn get_session_stats(id: i32, _usr: staff::Admin, conn: DatabaseConnection) -> V1Response<SessionStats> {
+    let (_, sess) =
+        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
+    let students = session::get_student_stats(&conn, sess.id)
+        .map_err(select_error_handler!("unable to count students"))?;
+    let interest = session::get_project_interest(&conn, sess.id)
+        .map_err(select_error_handler!("unable to count project interest"))?;
+
+    // Each project can only be allocated to one student, so any with several first choices will disappoint someone.
+    let oversubscribed = interest
+        .iter()
+        .filter(|it| it.first_choices > 1)
+        .take(MAX_OVERSUBSCRIBED)
+        .cloned()
+        .collect();
+    let zero_interest = interest
+        .iter()
+        .filter(|it| it.selections == 0 && it.marks == 0)
+        .cloned()
+        .collect();
+    Ok(Json(SessionStats {
+        session: sess.id,
+        students,
+        projects: interest.len(),
+        zero_interest,
+        oversubscribed,
+    }))
 }
 
 #[allow(needless_pass_by_value)]
@@ -96,19 +557,25 @@ fn get_session_report(
     _usr: staff::Admin,
     conn: DatabaseConnection,
 ) -> V1Response<SessionReport> {
+    Ok(Json(build_report(&conn, id)?))
+}
+
+/// Builds the selection report for a session. This backs both the JSON report and the file exports.
+pub fn build_report(conn: &DatabaseConnection, id: i32) -> Result<SessionReport, ErrorResponse> {
     let (_, sess) =
-        session::get_session(&conn, id).map_err(select_error_handler!("no such session"))?;
-    let mut projects = project::get_all_by_session(&conn, sess.id)
+        session::get_session(conn, id).map_err(select_error_handler!("no such session"))?;
+    let mut projects = project::get_all_by_session(conn, sess.id)
         .map_err(select_error_handler!("no projects found"))?;
+    let project_ids = projects.iter().map(|it| it.id).collect::<Vec<i32>>();
     // Down convert from full Project structs to ProjectStripped structs to save memory and bandwidth.
     let projects = projects
         .drain(..)
         .map(Into::into)
         .collect::<Vec<ProjectStripped>>();
 
-    let students = student::get_all_by_session(&conn, sess.id)
+    let students = student::get_all_by_session(conn, sess.id)
         .map_err(select_error_handler!("no students found"))?;
-    let sels = student::selection::get_all_for_session(&conn, sess.id)
+    let sels = student::selection::get_all_for_session(conn, sess.id)
         .map_err(select_error_handler!("no student selections found"))?;
 
     // Generate by-student breakdown.
@@ -190,7 +657,7 @@ fn get_session_report(
         .collect::<Vec<SessionReportByProject>>();
 
     // Fetch comments
-    let mut comments_raw = student::comment::get_all_for_session(&conn, sess.id)
+    let mut comments_raw = student::comment::get_all_for_session(conn, sess.id)
         .map_err(select_error_handler!("unable to find comments"))?;
 
     let mut comments: HashMap<i32, String> = HashMap::new();
@@ -201,12 +668,42 @@ fn get_session_report(
         };
     }
 
-    Ok(Json(SessionReport {
+    // Check choices against eligibility rules, which may have changed since the choices were made.
+    let rules = project::get_eligibility(conn, &project_ids)
+        .map_err(select_error_handler!("unable to fetch eligibility rules"))?;
+    let attrs = student::attributes::get_for_students(
+        conn,
+        &students.iter().map(|it| it.id).collect::<Vec<i32>>(),
+    ).map_err(select_error_handler!("unable to fetch student attributes"))?;
+    let mut violations = Vec::new();
+    for entry in &by_student {
+        for proj in &entry.choices {
+            let reasons = match rules.get(proj) {
+                Some(rule) => rule.check(attrs.get(&entry.student)),
+                None => continue,
+            };
+            if !reasons.is_empty() {
+                violations.push(EligibilityViolation {
+                    student: entry.student,
+                    project: *proj,
+                    reasons,
+                });
+            }
+        }
+    }
+    violations.sort_by_key(|it| (it.student, it.project));
+
+    let allocations = allocation::get_all_for_session(conn, sess.id)
+        .map_err(select_error_handler!("unable to fetch allocations"))?;
+
+    Ok(SessionReport {
         session: sess,
         by_student,
         by_project,
         students,
         projects,
         comments,
-    }))
+        allocations,
+        violations,
+    })
 }
@@ -3,20 +3,59 @@ v1_imports!();
 use std::sync::Arc;
 
 use rocket::{Route, State};
+use serde_json::Value;
 
 use authn::AuthnHolder;
-use db::staff;
+use config::Config;
+use db::{audit, staff, webhook};
+use retention;
 use session::SessionManager;
 
 pub fn get_routes() -> Vec<Route> {
-    routes![get_staff, rm_staff, new_staff]
+    routes![
+        get_staff,
+        get_staff_unpaged,
+        rm_staff,
+        restore_staff,
+        new_staff
+    ]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct StaffQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    /// Comma separated fields to include for each entry.
+    fields: Option<String>,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/staff?<query>")]
+fn get_staff(query: StaffQuery, usr: staff::Admin, conn: DatabaseConnection) -> V1Response<Value> {
+    let req = super::page_request(query.limit, query.cursor, query.sort, staff::SORT_KEYS, "id")?;
+    let res = match req {
+        Some(req) => {
+            let page = staff::get_page(&conn, &req).map_err(select_error_handler!("no staff found"))?;
+            StaffList {
+                page: Some(super::page_info(&req, &page)),
+                staff: page.items,
+            }
+        }
+        None => get_staff_unpaged(usr, conn)?.into_inner(),
+    };
+    super::select_fields(&res, "staff", staff::FIELDS, query.fields)
 }
 
 #[allow(needless_pass_by_value)]
-#[get("/staff")]
-fn get_staff(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StaffList> {
+#[get("/staff", rank = 2)]
+fn get_staff_unpaged(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StaffList> {
     match staff::get_all(&conn) {
-        Ok(v) => Ok(Json(StaffList { staff: v })),
+        Ok(v) => Ok(Json(StaffList {
+            staff: v,
+            page: None,
+        })),
         Err(e) => {
             error!("Unable to fetch staff: {:?}", e);
             Err(internal_server_error!("database error"))
@@ -28,25 +67,70 @@ fn get_staff(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StaffLi
 #[delete("/staff/<id>")]
 fn rm_staff(
     id: i32,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
     auth: State<AuthnHolder>,
     manager: State<Arc<SessionManager>>,
 ) -> V1Response<GenericMessage> {
     let target = staff::get(&conn, id).map_err(select_error_handler!("no such staff member"))?;
-    staff::delete(&conn, &target).map_err(|e| diesel_error_handler!(e))?;
+    staff::soft_delete(&conn, target.id).map_err(|e| diesel_error_handler!(e))?;
     manager.remove_session(&target.email, &auth);
+    audit::record(
+        &conn,
+        &usr.email,
+        "staff.delete",
+        "staff",
+        Some(id.to_string()),
+        audit::snapshot(&target),
+        None,
+    );
+    webhook::emit(&conn, "staff.removed", audit::snapshot(&target));
     Ok(generic_message!("ok"))
 }
 
+#[allow(needless_pass_by_value)]
+#[post("/staff/<id>/restore")]
+fn restore_staff(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<staff::Staff> {
+    let cutoff = retention::get_cutoff(conf.get_deleted_retention_days());
+    let target = staff::restore(&conn, id, cutoff)
+        .map_err(select_error_handler!("no such deleted staff member"))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "staff.restore",
+        "staff",
+        Some(id.to_string()),
+        None,
+        audit::snapshot(&target),
+    );
+    webhook::emit(&conn, "staff.restored", audit::snapshot(&target));
+    Ok(Json(target))
+}
+
 #[allow(needless_pass_by_value)]
 #[post("/staff", data = "<body>")]
 fn new_staff(
     mut body: Json<NewStaffList>,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
 ) -> V1Response<GenericMessage> {
     body.staff.retain(|s| s.email != "" && s.full_name != "");
     staff::create_batch(&conn, &body.staff).map_err(|e| diesel_error_handler!(e))?;
+    let emails = body.staff.iter().map(|s| &s.email).collect::<Vec<&String>>();
+    audit::record(
+        &conn,
+        &usr.email,
+        "staff.create_batch",
+        "staff",
+        None,
+        None,
+        audit::snapshot(&emails),
+    );
+    webhook::emit(&conn, "staff.created", Some(json!({ "emails": emails })));
     Ok(generic_message!("ok"))
 }
@@ -2,21 +2,72 @@ v1_imports!();
 
 use std::sync::Arc;
 
+use bigdecimal::BigDecimal;
 use rocket::{Route, State};
+use serde_json::Value;
 
 use authn::AuthnHolder;
-use db::{session, staff, student};
+use config::Config;
+use db::models::normalise_list;
+use db::student::{attributes, comment, enrolment, history, selection};
+use db::{audit, session, staff, student, webhook};
+use retention;
 use session::SessionManager;
 
 pub fn get_routes() -> Vec<Route> {
-    routes![get_students, get_curr_students, rm_student, new_students]
+    routes![
+        get_students,
+        get_students_unpaged,
+        get_curr_students,
+        rm_student,
+        restore_student,
+        new_students,
+        get_student_history,
+        restore_student_history,
+        get_student_attributes,
+        set_student_attributes
+    ]
+}
+
+#[allow(print_literal, suspicious_else_formatting)] // Silence Clippy about Rocket's FromForm impl.
+#[derive(FromForm, Debug)]
+struct StudentQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    /// Comma separated fields to include for each entry.
+    fields: Option<String>,
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/students?<query>")]
+fn get_students(
+    query: StudentQuery,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<Value> {
+    let req = super::page_request(query.limit, query.cursor, query.sort, student::SORT_KEYS, "id")?;
+    let res = match req {
+        Some(req) => {
+            let page = student::get_page(&conn, &req).map_err(select_error_handler!("no students found"))?;
+            StudentList {
+                page: Some(super::page_info(&req, &page)),
+                students: page.items,
+            }
+        }
+        None => get_students_unpaged(usr, conn)?.into_inner(),
+    };
+    super::select_fields(&res, "students", student::FIELDS, query.fields)
 }
 
 #[allow(needless_pass_by_value)]
-#[get("/students")]
-fn get_students(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StudentList> {
+#[get("/students", rank = 2)]
+fn get_students_unpaged(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StudentList> {
     match student::get_all(&conn) {
-        Ok(v) => Ok(Json(StudentList { students: v })),
+        Ok(v) => Ok(Json(StudentList {
+            students: v,
+            page: None,
+        })),
         Err(e) => {
             error!("Unable to fetch students: {:?}", e);
             Err(internal_server_error!("database error"))
@@ -28,9 +79,13 @@ fn get_students(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<Stud
 #[get("/students/current")]
 fn get_curr_students(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<StudentList> {
     match student::get_all_current(&conn) {
-        Ok(v) => Ok(Json(StudentList { students: v })),
+        Ok(v) => Ok(Json(StudentList {
+            students: v,
+            page: None,
+        })),
         Err(SelectError::NoSuchValue()) => Ok(Json(StudentList {
             students: Vec::new(),
+            page: None,
         })),
         Err(e) => {
             error!("Unable to fetch students: {:?}", e);
@@ -43,7 +98,7 @@ fn get_curr_students(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response
 #[delete("/students/<id>")]
 fn rm_student(
     id: i32,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
     auth: State<AuthnHolder>,
     manager: State<Arc<SessionManager>>,
@@ -52,26 +107,58 @@ fn rm_student(
         SelectError::NoSuchValue() => not_found!("no such student"),
         SelectError::DieselError(e) => diesel_error_handler!(e),
     })?;
-    student::delete(&conn, &target).map_err(|e| diesel_error_handler!(e))?;
+    student::soft_delete(&conn, target.id).map_err(|e| diesel_error_handler!(e))?;
     manager.remove_session(&target.email, &auth);
+    audit::record(
+        &conn,
+        &usr.email,
+        "student.delete",
+        "student",
+        Some(id.to_string()),
+        audit::snapshot(&target),
+        None,
+    );
+    webhook::emit(&conn, "student.removed", audit::snapshot(&target));
     Ok(generic_message!("ok"))
 }
 
+#[allow(needless_pass_by_value)]
+#[post("/students/<id>/restore")]
+fn restore_student(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+    conf: State<Config>,
+) -> V1Response<student::Student> {
+    let cutoff = retention::get_cutoff(conf.get_deleted_retention_days());
+    let target = student::restore(&conn, id, cutoff)
+        .map_err(select_error_handler!("no such deleted student"))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "student.restore",
+        "student",
+        Some(id.to_string()),
+        None,
+        audit::snapshot(&target),
+    );
+    webhook::emit(&conn, "student.restored", audit::snapshot(&target));
+    Ok(Json(target))
+}
+
 #[allow(needless_pass_by_value)]
 #[post("/students", data = "<body>")]
 fn new_students(
     mut body: Json<NewStudentList>,
-    _usr: staff::Admin,
+    usr: staff::Admin,
     conn: DatabaseConnection,
 ) -> V1Response<GenericMessage> {
-    let sess = match session::get_latest_session(&conn) {
-        Ok(s) => s,
-        Err(SelectError::NoSuchValue()) => return Err(bad_request!("no current session")),
-        Err(SelectError::DieselError(e)) => {
-            error!("Unable to fetch latest session: {}", e);
-            return Err(internal_server_error!("database error"));
-        }
-    };
+    use diesel::prelude::*;
+    use diesel::result;
+
+    let open =
+        session::get_open_sessions(&conn).map_err(select_error_handler!("unable to get open sessions"))?;
+    let sess = super::resolve_session(open, body.session)?;
 
     let students = body.students
         .drain(..)
@@ -79,10 +166,177 @@ fn new_students(
         .map(move |s| student::NewStudent {
             email: s.email,
             full_name: s.full_name,
-            last_session: sess.id,
         })
         .collect::<Vec<student::NewStudent>>();
+    let emails = students
+        .iter()
+        .map(|s| s.email.clone())
+        .collect::<Vec<String>>();
 
-    student::create_batch(&conn, &students).map_err(|e| diesel_error_handler!(e))?;
+    // Students are upserted by email, so existing students (e.g. those repeating a year) keep their old enrolments.
+    conn.raw()
+        .transaction::<_, result::Error, _>(|| {
+            student::create_batch(&conn, &students)?;
+            let ids = student::get_ids_by_email(&conn, &emails)?;
+            enrolment::enrol_batch(&conn, sess.id, &ids)?;
+            Ok(())
+        })
+        .map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "student.create_batch",
+        "student",
+        None,
+        None,
+        audit::snapshot(&emails),
+    );
+    webhook::emit(&conn, "student.created", Some(json!({ "session": sess.id, "emails": emails })));
     Ok(generic_message!("ok"))
 }
+
+#[allow(needless_pass_by_value)]
+#[get("/students/<id>/history")]
+fn get_student_history(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<StudentHistoryList> {
+    let target = student::get(&conn, id).map_err(select_error_handler!("no such student"))?;
+    let entries = history::get_all_for_student(&conn, id)
+        .map_err(select_error_handler!("no history found"))?;
+
+    Ok(Json(StudentHistoryList {
+        student: target,
+        history: entries.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/students/<id>/history/<version>/restore")]
+fn restore_student_history(
+    id: i32,
+    version: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<GenericMessage> {
+    use diesel::prelude::*;
+    use diesel::result;
+
+    let target = student::get(&conn, id).map_err(select_error_handler!("no such student"))?;
+    let (entry, sels) =
+        history::get(&conn, version).map_err(select_error_handler!("no such history entry"))?;
+    if entry.student != target.id {
+        return Err(not_found!("no such history entry"));
+    }
+
+    let (is_curr, _) = session::get_session(&conn, entry.session)
+        .map_err(select_error_handler!("no such session"))?;
+    if !is_curr {
+        return Err(bad_request!("cannot restore history from an archived session"));
+    }
+
+    let before = selection::get_all_for_student(&conn, id, entry.session)
+        .map_err(select_error_handler!("unable to fetch existing selections"))?;
+    let restored = sels.into_iter()
+        .map(|it| (it.project, it.weight))
+        .collect::<Vec<(i32, BigDecimal)>>();
+    let new_sels = restored
+        .iter()
+        .map(|&(project, ref weight)| selection::NewStudentSelection {
+            student: id,
+            project,
+            weight: weight.clone(),
+        })
+        .collect::<Vec<selection::NewStudentSelection>>();
+
+    conn.raw()
+        .transaction::<_, result::Error, _>(|| {
+            selection::clear_all_for_student(&conn, id, entry.session)?;
+            selection::create_batch(&conn, &new_sels)?;
+            comment::create(
+                &conn,
+                &comment::NewStudentComment {
+                    student: id,
+                    session: entry.session,
+                    comment: entry.comment.clone(),
+                },
+            )?;
+            history::record(&conn, id, entry.session, entry.comment.clone(), &restored)?;
+            Ok(())
+        })
+        .map_err(|e| match e {
+            result::Error::DatabaseError(result::DatabaseErrorKind::ForeignKeyViolation, _) => {
+                bad_request!("history entry refers to a project which no longer exists")
+            }
+            _ => diesel_error_handler!(e),
+        })?;
+
+    audit::record(
+        &conn,
+        &usr.email,
+        "selection.restore",
+        "student",
+        Some(id.to_string()),
+        Some(audit::snapshot_selections(&before)),
+        Some(json!({ "version": version })),
+    );
+
+    Ok(generic_message!("ok"))
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/students/<id>/attributes")]
+fn get_student_attributes(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<attributes::StudentAttributes> {
+    let target = student::get(&conn, id).map_err(select_error_handler!("no such student"))?;
+    let attrs = attributes::get_for_student(&conn, target.id)
+        .map_err(select_error_handler!("unable to fetch student attributes"))?;
+
+    Ok(Json(attrs.unwrap_or_else(|| attributes::StudentAttributes {
+        student: target.id,
+        programme: None,
+        modules: Vec::new(),
+    })))
+}
+
+#[allow(needless_pass_by_value)]
+#[put("/students/<id>/attributes", data = "<body>")]
+fn set_student_attributes(
+    id: i32,
+    body: Json<StudentAttributesUpdate>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<attributes::StudentAttributes> {
+    let target = student::get(&conn, id).map_err(select_error_handler!("no such student"))?;
+    let before = attributes::get_for_student(&conn, target.id)
+        .map_err(select_error_handler!("unable to fetch student attributes"))?;
+
+    let body = body.into_inner();
+    let mut modules = body.modules;
+    normalise_list(&mut modules);
+    let attrs = attributes::create(
+        &conn,
+        &attributes::NewStudentAttributes {
+            student: target.id,
+            programme: body.programme
+                .map(|it| it.trim().to_string())
+                .and_then(|it| if it.is_empty() { None } else { Some(it) }),
+            modules,
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "student.attributes",
+        "student",
+        Some(id.to_string()),
+        audit::snapshot(&before),
+        audit::snapshot(&attrs),
+    );
+
+    Ok(Json(attrs))
+}
@@ -0,0 +1,94 @@
+v1_imports!();
+
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::Route;
+
+use db::{audit, staff, tag, user};
+
+pub fn get_routes() -> Vec<Route> {
+    routes![get_tags, new_tag, update_tag, rm_tag]
+}
+
+/// Tag names are unique, so a clash is reported as a bad request rather than a database error.
+fn tag_error_handler(e: DieselError) -> ErrorResponse {
+    match e {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => bad_request!("tag already exists"),
+        _ => diesel_error_handler!(e),
+    }
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/tags")]
+fn get_tags(_usr: user::User, conn: DatabaseConnection) -> V1Response<TagList> {
+    let tags = tag::get_all(&conn).map_err(select_error_handler!("no tags found"))?;
+    Ok(Json(TagList { tags }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/tags", data = "<body>")]
+fn new_tag(
+    body: Json<tag::NewTag>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<tag::Tag> {
+    if body.name.trim().is_empty() {
+        return Err(bad_request!("tag name cannot be empty"));
+    }
+    let t = tag::create(&conn, &body).map_err(tag_error_handler)?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "tag.create",
+        "tag",
+        Some(t.id.to_string()),
+        None,
+        audit::snapshot(&t),
+    );
+    Ok(Json(t))
+}
+
+#[allow(needless_pass_by_value)]
+#[put("/tags/<id>", data = "<body>")]
+fn update_tag(
+    id: i32,
+    body: Json<tag::Tag>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<tag::Tag> {
+    if body.id != id {
+        return Err(bad_request!("tag ID does not match ID in body"));
+    }
+    if body.name.trim().is_empty() {
+        return Err(bad_request!("tag name cannot be empty"));
+    }
+    let current = tag::get(&conn, id).map_err(select_error_handler!("no such tag"))?;
+    tag::update(&conn, &body).map_err(tag_error_handler)?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "tag.update",
+        "tag",
+        Some(id.to_string()),
+        audit::snapshot(&current),
+        audit::snapshot(&*body),
+    );
+    Ok(body)
+}
+
+/// Deletes a tag, removing it from any projects it was applied to.
+#[allow(needless_pass_by_value)]
+#[delete("/tags/<id>")]
+fn rm_tag(id: i32, usr: staff::Admin, conn: DatabaseConnection) -> V1Response<GenericMessage> {
+    let current = tag::get(&conn, id).map_err(select_error_handler!("no such tag"))?;
+    tag::delete(&conn, &current).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "tag.delete",
+        "tag",
+        Some(id.to_string()),
+        audit::snapshot(&current),
+        None,
+    );
+    Ok(generic_message!("ok"))
+}
@@ -1,12 +1,25 @@
 use std::collections::HashMap;
 
+use chrono::naive::NaiveDateTime;
+use num_traits::cast::ToPrimitive;
 use rocket::response::status;
 use rocket_contrib::Json;
 
-use db::project::{Project, ProjectWithStaff};
-use db::session::Session;
+use db::audit::AuditEvent;
+use db::demand::SelectionActivity;
+use db::notification::Notification;
+use db::allocation::Allocation;
+use db::project::attachment::ProjectAttachment;
+use db::project::{Eligibility, Project, ProjectWithStaff};
+use db::proposal::ProjectProposal;
+use db::session::{ProjectInterest, PurgeCounts, Session, StudentStats};
 use db::staff::{NewStaff, Staff};
 use db::student::Student;
+use db::student::history::{StudentHistory, StudentHistorySelection};
+use db::tag::Tag;
+use db::webhook::Webhook;
+use db::webhook::attempt::WebhookAttempt;
+use db::webhook::delivery::WebhookDelivery;
 
 pub type ErrorResponse = status::Custom<Json<GenericMessage>>;
 pub type V1Response<T> = Result<Json<T>, ErrorResponse>;
@@ -29,6 +42,19 @@ pub struct WhoAmIMessage {
     pub user_type: String,
 }
 
+/// Pagination details for list responses. Only included when a page was requested.
+#[derive(Serialize, Debug)]
+pub struct PageInfo {
+    /// Total number of results across all pages.
+    pub total: i64,
+    /// Unset if the whole list was returned.
+    pub limit: Option<i64>,
+    /// Sort key used, prefixed with `-` if descending.
+    pub sort: String,
+    /// Pass as `cursor` to fetch the next page. Unset on the last page.
+    pub next_cursor: Option<String>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct SessionEntry {
     pub session: Session,
@@ -39,16 +65,233 @@ pub struct SessionEntry {
 pub struct SessionFullList {
     pub sessions: Vec<SessionEntry>,
     pub projects: Vec<ProjectWithStaff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionPurgeSummary {
+    pub session: Session,
+    /// Rows removed (or that would be removed) from each table.
+    pub counts: PurgeCounts,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionCloneRequest {
+    pub name: String,
+    /// Defaults to the supervisor of the session being cloned.
+    pub supervisor_name: Option<String>,
+    pub supervisor_email: Option<String>,
+    /// Projects to copy. All projects in the session are copied if this is unset.
+    pub projects: Option<Vec<i32>>,
+    /// Deadline for selections in the new session. Deadlines aren't copied, as they're rarely the same.
+    pub deadline: Option<NaiveDateTime>,
+    /// Whether to copy supervisors and additional staff who are no longer staff members. If unset, such staff are
+    /// dropped from the copied projects, and projects whose main supervisor has gone are skipped entirely.
+    #[serde(default)]
+    pub keep_removed_staff: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionDeadline {
+    /// Leave unset to remove the deadline, which also stops reminders.
+    pub deadline: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionDemandVisibility {
+    /// One of `hidden`, `counts` or `named`.
+    pub supervisor_demand: String,
+    pub supervisor_marks: bool,
+}
+
+/// The deadline reminders which will be sent next for a session.
+#[derive(Serialize, Debug)]
+pub struct ReminderPreview {
+    pub session: Session,
+    /// The configured offset the reminders are for. Unset if there are no reminders left to send.
+    pub hours_before: Option<u32>,
+    /// When the reminders will be sent. This is in the past if they're due to go out on the next check.
+    pub send_at: Option<NaiveDateTime>,
+    pub students: Vec<ReminderPreviewStudent>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReminderPreviewStudent {
+    pub student: Student,
+    /// Selections made so far.
+    pub selections: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionCloneResult {
+    pub session: Session,
+    pub projects: Vec<ProjectWithStaff>,
+    /// Projects which weren't copied because their supervisor is no longer a staff member.
+    pub skipped: Vec<i32>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct ProjectList {
     pub projects: Vec<ProjectWithStaff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
+    /// Listed projects the calling student can't select. Only included for students.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ineligible: Option<Vec<IneligibleProject>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IneligibleProject {
+    pub project: i32,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectSearchList {
+    pub results: Vec<ProjectSearchResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectSearchResult {
+    pub project: ProjectWithStaff,
+    pub rank: f32,
+    /// HTML-escaped project name, with matches wrapped in `<mark>` tags.
+    pub name_highlight: String,
+    /// HTML-escaped extract of the description around matches, with matches wrapped in `<mark>` tags.
+    pub snippet: String,
+}
+
+/// Body for updating a project. Tags, eligibility rules and additional staff are left unchanged if unset.
+#[derive(Deserialize, Debug)]
+pub struct ProjectUpdate {
+    pub id: i32,
+    pub session: i32,
+    pub supervisor_name: String,
+    pub supervisor_email: String,
+    pub name: String,
+    pub description_md: String,
+    pub tags: Option<Vec<i32>>,
+    pub eligibility: Option<Eligibility>,
+    /// Emails of co-supervisors.
+    pub additional_staff: Option<Vec<String>>,
+}
+
+/// Demand for a project, as shown to its supervisors. Parts the session doesn't let supervisors see are left unset.
+#[derive(Serialize, Debug)]
+pub struct ProjectDemand {
+    pub project: i32,
+    /// One of `hidden`, `counts` or `named`.
+    pub visibility: String,
+    /// Number of students choosing the project at each rank, first choices first.
+    pub choices: Option<Vec<usize>>,
+    /// Number of students who have marked the project.
+    pub marks: Option<usize>,
+    /// The students choosing the project, only given when visibility is `named`.
+    pub students: Option<Vec<ProjectDemandStudent>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectDemandStudent {
+    pub student: Student,
+    pub rank: i32,
+    pub marked: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentList {
+    pub attachments: Vec<ProjectAttachment>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProjectRejection {
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotificationList {
+    pub notifications: Vec<Notification>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProposalList {
+    pub proposals: Vec<ProjectProposal>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewProposal {
+    pub name: String,
+    pub description_md: String,
+    /// Email of the staff member the student would like to supervise the project.
+    pub supervisor_email: String,
+    /// Session to propose the project in. Only required if multiple sessions are open.
+    pub session: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProposalRejection {
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProposalDecision {
+    pub proposal: ProjectProposal,
+    /// The project created from an accepted proposal.
+    pub project: Option<ProjectWithStaff>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WebhookList {
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookRequest {
+    /// Must be an `http` or `https` URL.
+    pub url: String,
+    /// Generated if unset.
+    pub secret: Option<String>,
+    pub events: Vec<String>,
+    pub active: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WebhookUpdate {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WebhookWithSecret {
+    pub webhook: Webhook,
+    /// The secret is only returned when it's set, and can't be fetched afterwards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WebhookDeliveryList {
+    pub deliveries: Vec<WebhookDeliveryLog>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WebhookDeliveryLog {
+    pub delivery: WebhookDelivery,
+    pub attempts: Vec<WebhookAttempt>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagList {
+    pub tags: Vec<Tag>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct StaffList {
     pub staff: Vec<Staff>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -59,11 +302,24 @@ pub struct NewStaffList {
 #[derive(Serialize, Debug)]
 pub struct StudentList {
     pub students: Vec<Student>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<PageInfo>,
+}
+
+/// Body for setting a student's attributes, which replace any existing ones.
+#[derive(Deserialize, Debug)]
+pub struct StudentAttributesUpdate {
+    pub programme: Option<String>,
+    /// Modules the student has completed.
+    #[serde(default)]
+    pub modules: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct NewStudentList {
     pub students: Vec<NewStudentEntry>,
+    /// Session to enrol the students in. Only required if multiple sessions are open.
+    pub session: Option<i32>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -72,6 +328,26 @@ pub struct NewStudentEntry {
     pub full_name: String,
 }
 
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    /// Whether this was a dry run, in which case nothing was written.
+    pub dry_run: bool,
+    pub accepted: usize,
+    pub rejected: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportRowReport {
+    /// Record number within the file, counting from 1 and excluding any header row.
+    pub row: usize,
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+    pub accepted: bool,
+    /// Why the row was rejected, if it was.
+    pub reason: Option<String>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MarkMessage {
     pub id: i32,
@@ -96,6 +372,65 @@ pub struct SelectionEntry {
 #[derive(Deserialize, Debug)]
 pub struct CommentMessage {
     pub comment: Option<String>,
+    /// Session to comment on. Only required if the student is enrolled in multiple open sessions.
+    pub session: Option<i32>,
+}
+
+/// Soft deleted entries which can still be restored.
+#[derive(Serialize, Debug)]
+pub struct DeletedList {
+    pub projects: Vec<Project>,
+    pub staff: Vec<Staff>,
+    pub students: Vec<Student>,
+    /// Number of days after deletion that entries are permanently removed.
+    pub retention_days: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StudentHistoryList {
+    pub student: Student,
+    /// History entries, newest first.
+    pub history: Vec<StudentHistoryEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StudentHistoryEntry {
+    pub id: i32,
+    pub session: i32,
+    pub created: NaiveDateTime,
+    pub comment: Option<String>,
+    /// Selections at this point in time, ordered from best to worst.
+    pub selections: Vec<HistorySelectionEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistorySelectionEntry {
+    pub project: i32,
+    pub weight: f64,
+}
+
+impl From<(StudentHistory, Vec<StudentHistorySelection>)> for StudentHistoryEntry {
+    fn from((entry, mut sels): (StudentHistory, Vec<StudentHistorySelection>)) -> Self {
+        sels.sort_unstable_by(|a, b| b.weight.cmp(&a.weight));
+        StudentHistoryEntry {
+            id: entry.id,
+            session: entry.session,
+            created: entry.created,
+            comment: entry.comment,
+            selections: sels.into_iter()
+                .map(|it| HistorySelectionEntry {
+                    project: it.project,
+                    weight: it.weight.to_f64().unwrap_or(0.0),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AuditEventList {
+    pub events: Vec<AuditEvent>,
+    pub page: PageInfo,
 }
 
 /// Version of the Project structure with excess material trimmed to save memory and bandwidth when generating reports.
@@ -118,6 +453,45 @@ impl From<Project> for ProjectStripped {
     }
 }
 
+#[derive(Serialize, Debug)]
+pub struct SessionStats {
+    pub session: i32,
+    pub students: StudentStats,
+    /// Number of approved projects.
+    pub projects: usize,
+    /// Approved projects nobody has chosen or marked.
+    pub zero_interest: Vec<ProjectInterest>,
+    /// Projects with more than one first choice, most first choices first.
+    pub oversubscribed: Vec<ProjectInterest>,
+}
+
+/// How popular each project in a session is, and how selections have come in over time.
+#[derive(Serialize, Debug)]
+pub struct SessionAnalytics {
+    pub session: i32,
+    /// Least popular first.
+    pub projects: Vec<ProjectAnalytics>,
+    /// Selection activity for each day there was any, oldest first.
+    pub activity: Vec<SelectionActivity>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProjectAnalytics {
+    pub project: i32,
+    pub name: String,
+    pub supervisor_name: String,
+    pub status: String,
+    /// Number of students who have marked the project.
+    pub marks: usize,
+    /// Number of students choosing the project at each rank, first choices first.
+    pub choices: Vec<usize>,
+    /// Number of students choosing the project at any rank.
+    pub selections: usize,
+    pub marked_and_selected: usize,
+    /// Fraction of the students marking the project who went on to choose it. Unset if nobody has marked it.
+    pub conversion: Option<f64>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct SessionReport {
     pub session: Session,
@@ -126,6 +500,17 @@ pub struct SessionReport {
     pub students: Vec<Student>,
     pub projects: Vec<ProjectStripped>,
     pub comments: HashMap<i32, String>,
+    /// Projects allocated to students, such as accepted proposals.
+    pub allocations: Vec<Allocation>,
+    /// Selections the student isn't eligible for, e.g. because the rules changed after they were made.
+    pub violations: Vec<EligibilityViolation>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EligibilityViolation {
+    pub student: i32,
+    pub project: i32,
+    pub reasons: Vec<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -0,0 +1,226 @@
+v1_imports!();
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rocket::Route;
+use url::Url;
+
+use db::webhook::{self, attempt, delivery};
+use db::{audit, staff};
+use util;
+use webhook::EVENTS;
+
+/// Number of recent deliveries shown in a webhook's delivery log.
+const DELIVERY_LOG_LIMIT: i64 = 50;
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_webhooks,
+        new_webhook,
+        update_webhook,
+        rm_webhook,
+        ping_webhook,
+        get_webhook_deliveries
+    ]
+}
+
+#[allow(needless_pass_by_value)]
+#[get("/webhooks")]
+fn get_webhooks(_usr: staff::Admin, conn: DatabaseConnection) -> V1Response<WebhookList> {
+    let webhooks = webhook::get_all(&conn).map_err(select_error_handler!("no webhooks found"))?;
+    Ok(Json(WebhookList { webhooks }))
+}
+
+#[allow(needless_pass_by_value)]
+#[post("/webhooks", data = "<body>")]
+fn new_webhook(
+    body: Json<WebhookRequest>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<WebhookWithSecret> {
+    let body = body.into_inner();
+    let url = check_url(&body.url)?;
+    let events = check_events(body.events)?;
+    let secret = check_secret(body.secret)?;
+
+    let hook = webhook::create(
+        &conn,
+        &webhook::NewWebhook {
+            url,
+            secret: secret.clone(),
+            events,
+            active: body.active.unwrap_or(true),
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "webhook.create",
+        "webhook",
+        Some(hook.id.to_string()),
+        None,
+        audit::snapshot(&hook),
+    );
+
+    Ok(Json(WebhookWithSecret {
+        webhook: hook,
+        secret: Some(secret),
+    }))
+}
+
+/// Updates a webhook. Unset fields are left as they are. The secret is only returned if it was changed.
+#[allow(needless_pass_by_value)]
+#[put("/webhooks/<id>", data = "<body>")]
+fn update_webhook(
+    id: i32,
+    body: Json<WebhookUpdate>,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<WebhookWithSecret> {
+    let body = body.into_inner();
+    let before = webhook::get(&conn, id).map_err(select_error_handler!("no such webhook"))?;
+    let mut hook = before.clone();
+    if let Some(ref url) = body.url {
+        hook.url = check_url(url)?;
+    }
+    if let Some(events) = body.events {
+        hook.events = check_events(events)?;
+    }
+    if body.secret.is_some() {
+        hook.secret = check_secret(body.secret)?;
+    }
+    if let Some(active) = body.active {
+        hook.active = active;
+    }
+
+    let res = webhook::update(&conn, &hook).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "webhook.update",
+        "webhook",
+        Some(id.to_string()),
+        audit::snapshot(&before),
+        audit::snapshot(&res),
+    );
+
+    let secret = if res.secret != before.secret {
+        Some(res.secret.clone())
+    } else {
+        None
+    };
+    Ok(Json(WebhookWithSecret {
+        webhook: res,
+        secret,
+    }))
+}
+
+/// Removes a webhook along with its queued deliveries and delivery log.
+#[allow(needless_pass_by_value)]
+#[delete("/webhooks/<id>")]
+fn rm_webhook(id: i32, usr: staff::Admin, conn: DatabaseConnection) -> V1Response<GenericMessage> {
+    let hook = webhook::get(&conn, id).map_err(select_error_handler!("no such webhook"))?;
+    webhook::delete(&conn, &hook).map_err(|e| diesel_error_handler!(e))?;
+    audit::record(
+        &conn,
+        &usr.email,
+        "webhook.delete",
+        "webhook",
+        Some(id.to_string()),
+        audit::snapshot(&hook),
+        None,
+    );
+    Ok(generic_message!("ok"))
+}
+
+/// Queues a `ping` event for a single webhook, whatever events it's subscribed to. Useful for checking a receiver,
+/// such as a local HTTP stand-in, before relying on it.
+#[allow(needless_pass_by_value)]
+#[post("/webhooks/<id>/ping")]
+fn ping_webhook(
+    id: i32,
+    usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<delivery::WebhookDelivery> {
+    let hook = webhook::get(&conn, id).map_err(select_error_handler!("no such webhook"))?;
+    let res = delivery::create(
+        &conn,
+        &delivery::NewWebhookDelivery {
+            webhook: hook.id,
+            event: "ping".to_string(),
+            payload: json!({
+                "event": "ping",
+                "created": Utc::now().naive_utc(),
+                "data": { "webhook": hook.id, "sender": usr.email },
+            }),
+        },
+    ).map_err(|e| diesel_error_handler!(e))?;
+    Ok(Json(res))
+}
+
+/// Lists a webhook's most recent deliveries, with every attempt made for each.
+#[allow(needless_pass_by_value)]
+#[get("/webhooks/<id>/deliveries")]
+fn get_webhook_deliveries(
+    id: i32,
+    _usr: staff::Admin,
+    conn: DatabaseConnection,
+) -> V1Response<WebhookDeliveryList> {
+    let hook = webhook::get(&conn, id).map_err(select_error_handler!("no such webhook"))?;
+    let deliveries = delivery::get_recent_for_webhook(&conn, hook.id, DELIVERY_LOG_LIMIT)
+        .map_err(select_error_handler!("unable to fetch deliveries"))?;
+    let ids = deliveries.iter().map(|it| it.id).collect::<Vec<i32>>();
+    let mut attempts: HashMap<i32, Vec<attempt::WebhookAttempt>> = HashMap::new();
+    for a in attempt::get_all_for_deliveries(&conn, &ids)
+        .map_err(select_error_handler!("unable to fetch delivery attempts"))?
+    {
+        attempts.entry(a.delivery).or_insert_with(Vec::new).push(a);
+    }
+
+    Ok(Json(WebhookDeliveryList {
+        deliveries: deliveries
+            .into_iter()
+            .map(|d| WebhookDeliveryLog {
+                attempts: attempts.remove(&d.id).unwrap_or_default(),
+                delivery: d,
+            })
+            .collect(),
+    }))
+}
+
+fn check_url(raw: &str) -> Result<String, ErrorResponse> {
+    let url = Url::parse(raw.trim()).map_err(|_| bad_request!("invalid webhook URL"))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(bad_request!("webhook URL must use http or https"));
+    }
+    Ok(url.into_string())
+}
+
+fn check_events(mut events: Vec<String>) -> Result<Vec<String>, ErrorResponse> {
+    events.sort();
+    events.dedup();
+    if events.is_empty() {
+        return Err(bad_request!("webhook must subscribe to at least one event"));
+    }
+    if let Some(ev) = events
+        .iter()
+        .find(|it| *it != webhook::ALL_EVENTS && !EVENTS.contains(&&it[..]))
+    {
+        return Err(bad_request!("unknown event {}; expected one of: {}", ev, EVENTS.join(", ")));
+    }
+    Ok(events)
+}
+
+/// Generates a secret unless one was given.
+fn check_secret(secret: Option<String>) -> Result<String, ErrorResponse> {
+    match secret {
+        Some(s) => {
+            if s.trim().len() < 16 {
+                return Err(bad_request!("webhook secret must be at least 16 characters"));
+            }
+            Ok(s.trim().to_string())
+        }
+        None => Ok(util::generate_rand_string(32)),
+    }
+}
@@ -0,0 +1,26 @@
+pub use super::models::Allocation;
+pub use super::models::new::Allocation as NewAllocation;
+
+use super::{DatabaseConnection, SelectError};
+
+// Enable upsert on the student and session, so allocating a student again replaces their project.
+generate_crud_fns!(allocations, NewAllocation, Allocation, (student, session -> project));
+
+pub fn get_all_for_session(conn: &DatabaseConnection, sess: i32) -> Result<Vec<Allocation>, SelectError> {
+    generate_select_body!(multi, conn, allocations, Allocation, (session, sess))
+}
+
+pub fn get_for_student(conn: &DatabaseConnection, id: i32, sess: i32) -> Result<Allocation, SelectError> {
+    generate_select_body!(single, conn, allocations, Allocation, (student, id), (session, sess))
+}
+
+/// Fetches the allocations of any of the given projects.
+pub fn get_all_for_projects(conn: &DatabaseConnection, ids: &[i32]) -> Result<Vec<Allocation>, SelectError> {
+    use diesel::prelude::*;
+    use schema::allocations;
+
+    let res = allocations::table
+        .filter(allocations::project.eq_any(ids))
+        .load::<Allocation>(conn.raw())?;
+    Ok(res)
+}
@@ -0,0 +1,779 @@
+//! Whole-session archives, for keeping offline copies of past years or moving sessions between deployments.
+//!
+//! Archives refer to projects and students by the IDs they had when exported. These are only meaningful within the
+//! archive, and are remapped to fresh IDs on import. Staff and students are matched to existing records by email.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use bigdecimal::BigDecimal;
+use chrono::naive::NaiveDateTime;
+use chrono::Utc;
+use diesel;
+
+use super::{allocation, project, session, staff, student, tag, DatabaseConnection, SelectError};
+use super::student::{comment, enrolment, mark, selection};
+
+/// Current archive format version. Bump this whenever the format changes incompatibly.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SessionArchive {
+    pub version: u32,
+    pub exported: NaiveDateTime,
+    pub session: ArchiveSession,
+    pub staff: Vec<ArchiveStaff>,
+    pub projects: Vec<ArchiveProject>,
+    pub students: Vec<ArchiveStudent>,
+    pub selections: Vec<ArchiveSelection>,
+    pub marks: Vec<ArchiveMark>,
+    pub comments: Vec<ArchiveComment>,
+    /// Missing from archives exported before allocations were stored.
+    #[serde(default)]
+    pub allocations: Vec<ArchiveAllocation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveSession {
+    pub name: String,
+    pub supervisor_name: String,
+    pub supervisor_email: String,
+    pub created: NaiveDateTime,
+    pub force_archive: bool,
+    /// Missing from archives exported before sessions had deadlines.
+    #[serde(default)]
+    pub deadline: Option<NaiveDateTime>,
+    /// Missing from archives exported before supervisors could see demand for their projects.
+    #[serde(default)]
+    pub supervisor_demand: Option<String>,
+    #[serde(default)]
+    pub supervisor_marks: Option<bool>,
+}
+
+/// Staff members supervising projects in the session.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveStaff {
+    pub email: String,
+    pub full_name: String,
+    pub is_admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveProject {
+    pub id: i32,
+    pub supervisor_name: String,
+    pub supervisor_email: String,
+    pub name: String,
+    pub description_md: String,
+    pub additional_staff: Vec<String>,
+    /// Tag names, since tag IDs differ between deployments.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub eligibility: project::Eligibility,
+    /// Review status. Projects in archives exported before projects were reviewed are treated as approved.
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveStudent {
+    pub id: i32,
+    pub email: String,
+    pub full_name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveSelection {
+    pub student: i32,
+    pub project: i32,
+    /// Stored as a string to avoid losing precision.
+    pub weight: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveMark {
+    pub student: i32,
+    pub project: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveComment {
+    pub student: i32,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ArchiveAllocation {
+    pub student: i32,
+    pub project: i32,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    NoSuchSession(),
+    UnsupportedVersion(u32),
+    /// The archive is internally inconsistent, e.g. a selection refers to a student not in the archive, or clashes with
+    /// existing data, e.g. its session name is taken.
+    Invalid(String),
+    DieselError(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ArchiveError {
+    fn from(err: diesel::result::Error) -> Self {
+        ArchiveError::DieselError(err)
+    }
+}
+
+impl From<SelectError> for ArchiveError {
+    fn from(err: SelectError) -> Self {
+        match err {
+            SelectError::NoSuchValue() => ArchiveError::DieselError(diesel::result::Error::NotFound),
+            SelectError::DieselError(e) => ArchiveError::DieselError(e),
+        }
+    }
+}
+
+/// What an import created, for reporting back to the admin.
+#[derive(Serialize, Debug)]
+pub struct ImportSummary {
+    pub session: session::Session,
+    pub projects: usize,
+    pub students: usize,
+    pub selections: usize,
+    pub marks: usize,
+    pub comments: usize,
+    pub allocations: usize,
+}
+
+/// Exports a session and everything belonging to it. Soft deleted projects and students are left out.
+pub fn export(conn: &DatabaseConnection, id: i32) -> Result<SessionArchive, ArchiveError> {
+    let (_, sess) = session::get_session(conn, id).map_err(|e| match e {
+        SelectError::NoSuchValue() => ArchiveError::NoSuchSession(),
+        SelectError::DieselError(e) => ArchiveError::DieselError(e),
+    })?;
+
+    let projects = project::attach_staff(conn, project::get_all_by_session(conn, id)?)?;
+    let tag_names = tag::get_all(conn)?
+        .into_iter()
+        .map(|it| (it.id, it.name))
+        .collect::<HashMap<i32, String>>();
+    let students = student::get_all_by_session(conn, id)?;
+    let student_ids = students.iter().map(|it| it.id).collect::<HashSet<i32>>();
+    let project_ids = projects.iter().map(|it| it.id).collect::<HashSet<i32>>();
+
+    // Only staff involved in the session are included, to keep archives self-contained without leaking the whole staff
+    // list.
+    let emails = projects
+        .iter()
+        .flat_map(|p| p.additional_staff.iter().chain(Some(&p.supervisor_email)))
+        .collect::<HashSet<&String>>();
+    let staff = staff::get_all(conn)?
+        .into_iter()
+        .filter(|it| emails.contains(&it.email))
+        .map(|it| ArchiveStaff {
+            email: it.email,
+            full_name: it.full_name,
+            is_admin: it.is_admin,
+        })
+        .collect();
+
+    let selections = selection::get_all_for_session(conn, id)?
+        .into_iter()
+        .map(|it| ArchiveSelection {
+            student: it.student,
+            project: it.project,
+            weight: it.weight.to_string(),
+        })
+        .collect();
+    let marks = mark::get_all_for_session(conn, id)?
+        .into_iter()
+        .map(|it| ArchiveMark {
+            student: it.student,
+            project: it.project,
+        })
+        .collect();
+    // Comments aren't tied to projects, so filter out those belonging to soft deleted students here.
+    let comments = comment::get_all_for_session(conn, id)?
+        .into_iter()
+        .filter(|it| student_ids.contains(&it.student))
+        .map(|it| ArchiveComment {
+            student: it.student,
+            comment: it.comment,
+        })
+        .collect();
+    // Allocations aren't filtered by the queries above, so skip any involving soft deleted students or projects.
+    let allocations = allocation::get_all_for_session(conn, id)?
+        .into_iter()
+        .filter(|it| student_ids.contains(&it.student) && project_ids.contains(&it.project))
+        .map(|it| ArchiveAllocation {
+            student: it.student,
+            project: it.project,
+        })
+        .collect();
+
+    Ok(SessionArchive {
+        version: ARCHIVE_VERSION,
+        exported: Utc::now().naive_utc(),
+        session: ArchiveSession {
+            name: sess.name,
+            supervisor_name: sess.supervisor_name,
+            supervisor_email: sess.supervisor_email,
+            created: sess.created,
+            force_archive: sess.force_archive,
+            deadline: sess.deadline,
+            supervisor_demand: Some(sess.supervisor_demand),
+            supervisor_marks: Some(sess.supervisor_marks),
+        },
+        staff,
+        projects: projects
+            .into_iter()
+            .map(|p| ArchiveProject {
+                id: p.id,
+                supervisor_name: p.supervisor_name,
+                supervisor_email: p.supervisor_email,
+                name: p.name,
+                description_md: p.description_md,
+                additional_staff: p.additional_staff,
+                tags: p.tags.iter().filter_map(|it| tag_names.get(it).cloned()).collect(),
+                eligibility: p.eligibility,
+                status: Some(p.status),
+            })
+            .collect(),
+        students: students
+            .into_iter()
+            .map(|s| ArchiveStudent {
+                id: s.id,
+                email: s.email,
+                full_name: s.full_name,
+            })
+            .collect(),
+        selections,
+        marks,
+        comments,
+        allocations,
+    })
+}
+
+/// Checks an archive can be imported: the version is supported, archive IDs are unique, and everything referred to
+/// is present in the archive.
+pub fn validate(archive: &SessionArchive) -> Result<(), ArchiveError> {
+    if archive.version != ARCHIVE_VERSION {
+        return Err(ArchiveError::UnsupportedVersion(archive.version));
+    }
+    if let Some(ref demand) = archive.session.supervisor_demand {
+        if !session::DEMAND_VISIBILITIES.contains(&&demand[..]) {
+            return Err(ArchiveError::Invalid(format!("invalid supervisor demand visibility '{}'", demand)));
+        }
+    }
+
+    let mut projects = HashSet::new();
+    for p in &archive.projects {
+        if !projects.insert(p.id) {
+            return Err(ArchiveError::Invalid(format!("duplicate project ID {}", p.id)));
+        }
+        if let Some(ref status) = p.status {
+            if ![project::DRAFT, project::SUBMITTED, project::APPROVED, project::REJECTED].contains(&&status[..]) {
+                return Err(ArchiveError::Invalid(format!("invalid project status '{}'", status)));
+            }
+        }
+    }
+    let mut students = HashSet::new();
+    let mut emails = HashSet::new();
+    for s in &archive.students {
+        if !students.insert(s.id) {
+            return Err(ArchiveError::Invalid(format!("duplicate student ID {}", s.id)));
+        }
+        if !emails.insert(&s.email) {
+            return Err(ArchiveError::Invalid(format!("duplicate student email {}", s.email)));
+        }
+    }
+
+    let check = |student: i32, project: Option<i32>| {
+        if !students.contains(&student) {
+            return Err(ArchiveError::Invalid(format!("unknown student ID {}", student)));
+        }
+        match project {
+            Some(p) if !projects.contains(&p) => {
+                Err(ArchiveError::Invalid(format!("unknown project ID {}", p)))
+            }
+            _ => Ok(()),
+        }
+    };
+    for sel in &archive.selections {
+        check(sel.student, Some(sel.project))?;
+        BigDecimal::from_str(&sel.weight)
+            .map_err(|_| ArchiveError::Invalid(format!("invalid selection weight '{}'", sel.weight)))?;
+    }
+    for m in &archive.marks {
+        check(m.student, Some(m.project))?;
+    }
+    for c in &archive.comments {
+        check(c.student, None)?;
+    }
+    let mut allocated = HashSet::new();
+    for a in &archive.allocations {
+        check(a.student, Some(a.project))?;
+        if !allocated.insert(a.student) {
+            return Err(ArchiveError::Invalid(format!("student ID {} is allocated twice", a.student)));
+        }
+    }
+    Ok(())
+}
+
+/// Imports an archive as a new session in a single transaction. Staff and students are upserted by email, though
+/// existing admins are never demoted.
+pub fn import(conn: &DatabaseConnection, archive: &SessionArchive) -> Result<ImportSummary, ArchiveError> {
+    use diesel::prelude::*;
+
+    validate(archive)?;
+
+    conn.raw().transaction(|| {
+        // Session names are unique, so report a clash as a problem with the archive rather than a database error.
+        match session::find_by_name(conn, &archive.session.name) {
+            Ok(_) => {
+                return Err(ArchiveError::Invalid(format!(
+                    "a session named '{}' already exists",
+                    archive.session.name
+                )))
+            }
+            Err(SelectError::NoSuchValue()) => {}
+            Err(e) => return Err(ArchiveError::from(e)),
+        }
+
+        let sess = session::create(
+            conn,
+            &session::NewSession {
+                name: archive.session.name.clone(),
+                supervisor_name: archive.session.supervisor_name.clone(),
+                supervisor_email: archive.session.supervisor_email.clone(),
+                created: Some(archive.session.created),
+                force_archive: Some(archive.session.force_archive),
+                deadline: archive.session.deadline,
+                supervisor_demand: archive.session.supervisor_demand.clone(),
+                supervisor_marks: archive.session.supervisor_marks,
+            },
+        )?;
+
+        let admins = staff::get_all(conn)?
+            .into_iter()
+            .filter(|it| it.is_admin)
+            .map(|it| it.email)
+            .collect::<HashSet<String>>();
+        let new_staff = archive
+            .staff
+            .iter()
+            .map(|s| staff::NewStaff {
+                email: s.email.clone(),
+                full_name: s.full_name.clone(),
+                is_admin: Some(s.is_admin || admins.contains(&s.email)),
+            })
+            .collect::<Vec<_>>();
+        if !new_staff.is_empty() {
+            staff::create_batch(conn, &new_staff)?;
+        }
+
+        let names = archive
+            .projects
+            .iter()
+            .flat_map(|p| p.tags.iter().cloned())
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect::<Vec<String>>();
+        let tag_ids = tag::get_or_create_by_name(conn, &names)?;
+
+        // Projects are returned in insertion order, so zip them back up with the archive IDs.
+        let new_projs = archive
+            .projects
+            .iter()
+            .map(|p| project::NewProjectWithStaff {
+                supervisor_name: p.supervisor_name.clone(),
+                supervisor_email: p.supervisor_email.clone(),
+                name: p.name.clone(),
+                description_md: p.description_md.clone(),
+                additional_staff: p.additional_staff.clone(),
+                session: None,
+                tags: p.tags.iter().map(|it| tag_ids[it]).collect(),
+                eligibility: p.eligibility.clone(),
+                status: Some(p.status.clone().unwrap_or_else(|| project::APPROVED.to_string())),
+            })
+            .collect();
+        let created = project::create_with_staff_batch(conn, new_projs, sess.id)?;
+        let project_ids = archive
+            .projects
+            .iter()
+            .map(|it| it.id)
+            .zip(created.iter().map(|it| it.id))
+            .collect::<HashMap<i32, i32>>();
+
+        let new_students = archive
+            .students
+            .iter()
+            .map(|s| student::NewStudent {
+                email: s.email.clone(),
+                full_name: s.full_name.clone(),
+            })
+            .collect::<Vec<_>>();
+        let mut student_ids = HashMap::with_capacity(new_students.len());
+        if !new_students.is_empty() {
+            student::create_batch(conn, &new_students)?;
+            let emails = archive.students.iter().map(|it| it.email.clone()).collect::<Vec<String>>();
+            let ids_by_email = student::get_id_map_by_email(conn, &emails)?;
+            for s in &archive.students {
+                student_ids.insert(s.id, ids_by_email[&s.email]);
+            }
+            enrolment::enrol_batch(conn, sess.id, &ids_by_email.values().cloned().collect::<Vec<i32>>())?;
+        }
+
+        let sels = archive
+            .selections
+            .iter()
+            .map(|it| selection::NewStudentSelection {
+                student: student_ids[&it.student],
+                project: project_ids[&it.project],
+                weight: BigDecimal::from_str(&it.weight).expect("weights checked by validate"),
+            })
+            .collect::<Vec<_>>();
+        let marks = archive
+            .marks
+            .iter()
+            .map(|it| mark::NewStudentMark {
+                student: student_ids[&it.student],
+                project: project_ids[&it.project],
+            })
+            .collect::<Vec<_>>();
+        let comments = archive
+            .comments
+            .iter()
+            .map(|it| comment::NewStudentComment {
+                student: student_ids[&it.student],
+                session: sess.id,
+                comment: it.comment.clone(),
+            })
+            .collect::<Vec<_>>();
+        let allocs = archive
+            .allocations
+            .iter()
+            .map(|it| allocation::NewAllocation {
+                student: student_ids[&it.student],
+                session: sess.id,
+                project: project_ids[&it.project],
+            })
+            .collect::<Vec<_>>();
+        if !sels.is_empty() {
+            selection::create_batch(conn, &sels)?;
+        }
+        if !marks.is_empty() {
+            mark::create_batch(conn, &marks)?;
+        }
+        if !comments.is_empty() {
+            comment::create_batch(conn, &comments)?;
+        }
+        if !allocs.is_empty() {
+            allocation::create_batch(conn, &allocs)?;
+        }
+
+        Ok(ImportSummary {
+            session: sess,
+            projects: created.len(),
+            students: student_ids.len(),
+            selections: sels.len(),
+            marks: marks.len(),
+            comments: comments.len(),
+            allocations: allocs.len(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+    use test_util;
+
+    fn new_staff(email: &str, full_name: &str, is_admin: bool) -> staff::NewStaff {
+        staff::NewStaff {
+            email: email.to_string(),
+            full_name: full_name.to_string(),
+            is_admin: Some(is_admin),
+        }
+    }
+
+    fn new_session(name: &str) -> session::NewSession {
+        session::NewSession {
+            name: name.to_string(),
+            supervisor_name: "Archive Supervisor".to_string(),
+            supervisor_email: "supervisor@archive.test".to_string(),
+            created: None,
+            force_archive: None,
+            deadline: None,
+            supervisor_demand: Some("counts".to_string()),
+            supervisor_marks: Some(true),
+        }
+    }
+
+    fn new_project(name: &str, additional_staff: Vec<String>, tags: Vec<i32>) -> project::NewProjectWithStaff {
+        project::NewProjectWithStaff {
+            supervisor_name: "Archive Supervisor".to_string(),
+            supervisor_email: "supervisor@archive.test".to_string(),
+            name: name.to_string(),
+            description_md: format!("About {}", name),
+            additional_staff,
+            session: None,
+            tags,
+            eligibility: project::Eligibility {
+                programmes: vec!["Computer Science".to_string()],
+                modules: vec![],
+            },
+            status: Some(project::APPROVED.to_string()),
+        }
+    }
+
+    /// Seeds a session with two projects, three students and some of everything that hangs off them. The student
+    /// `kept@archive.test` is also enrolled in a second session, so they survive the first being purged.
+    fn seed(conn: &DatabaseConnection) -> session::Session {
+        staff::create_batch(
+            conn,
+            &[
+                new_staff("supervisor@archive.test", "Archive Supervisor", false),
+                new_staff("second@archive.test", "Second Marker", false),
+            ],
+        ).unwrap();
+        let sess = session::create(conn, &new_session("Archived")).unwrap();
+        let other = session::create(conn, &new_session("Other")).unwrap();
+        let tags = tag::get_or_create_by_name(conn, &["archive-test".to_string()]).unwrap();
+
+        let first = project::create_with_staff(
+            conn,
+            &new_project("First", vec!["second@archive.test".to_string()], vec![tags["archive-test"]]),
+            sess.id,
+        ).unwrap();
+        let second = project::create_with_staff(conn, &new_project("Second", vec![], vec![]), sess.id).unwrap();
+
+        let emails = ["kept@archive.test", "moved@archive.test", "quiet@archive.test"]
+            .iter()
+            .map(|it| it.to_string())
+            .collect::<Vec<String>>();
+        let students = emails
+            .iter()
+            .map(|it| student::NewStudent {
+                email: it.clone(),
+                full_name: format!("Student {}", it),
+            })
+            .collect::<Vec<_>>();
+        student::create_batch(conn, &students).unwrap();
+        let ids = student::get_id_map_by_email(conn, &emails).unwrap();
+        let (kept, moved, quiet) = (ids[&emails[0]], ids[&emails[1]], ids[&emails[2]]);
+        enrolment::enrol_batch(conn, sess.id, &[kept, moved, quiet]).unwrap();
+        enrolment::enrol_batch(conn, other.id, &[kept]).unwrap();
+
+        let new_selection = |student, project, weight: &str| selection::NewStudentSelection {
+            student,
+            project,
+            weight: BigDecimal::from_str(weight).unwrap(),
+        };
+        selection::create_batch(
+            conn,
+            &[
+                new_selection(kept, first.id, "1.5"),
+                new_selection(kept, second.id, "0.25"),
+                new_selection(moved, second.id, "1"),
+            ],
+        ).unwrap();
+        mark::create_batch(
+            conn,
+            &[
+                mark::NewStudentMark {
+                    student: kept,
+                    project: second.id,
+                },
+                mark::NewStudentMark {
+                    student: quiet,
+                    project: first.id,
+                },
+            ],
+        ).unwrap();
+        comment::create_batch(
+            conn,
+            &[
+                comment::NewStudentComment {
+                    student: kept,
+                    session: sess.id,
+                    comment: Some("Keen on either".to_string()),
+                },
+                comment::NewStudentComment {
+                    student: moved,
+                    session: sess.id,
+                    comment: None,
+                },
+            ],
+        ).unwrap();
+        allocation::create_batch(
+            conn,
+            &[
+                allocation::NewAllocation {
+                    student: kept,
+                    session: sess.id,
+                    project: first.id,
+                },
+                allocation::NewAllocation {
+                    student: moved,
+                    session: sess.id,
+                    project: second.id,
+                },
+            ],
+        ).unwrap();
+
+        sess
+    }
+
+    /// Rewrites an archive's IDs using `projects` and `students`, and sorts everything so archives can be compared
+    /// whatever order rows came back from the database in.
+    fn remap(
+        archive: &SessionArchive,
+        projects: &HashMap<i32, i32>,
+        students: &HashMap<i32, i32>,
+    ) -> SessionArchive {
+        let mut res = archive.clone();
+        res.exported = NaiveDateTime::from_timestamp(0, 0);
+        for p in &mut res.projects {
+            p.id = projects[&p.id];
+            p.additional_staff.sort();
+        }
+        for s in &mut res.students {
+            s.id = students[&s.id];
+        }
+        for it in &mut res.selections {
+            it.student = students[&it.student];
+            it.project = projects[&it.project];
+        }
+        for it in &mut res.marks {
+            it.student = students[&it.student];
+            it.project = projects[&it.project];
+        }
+        for it in &mut res.comments {
+            it.student = students[&it.student];
+        }
+        for it in &mut res.allocations {
+            it.student = students[&it.student];
+            it.project = projects[&it.project];
+        }
+
+        res.staff.sort_by(|a, b| a.email.cmp(&b.email));
+        res.projects.sort_by_key(|it| it.id);
+        res.students.sort_by_key(|it| it.id);
+        res.selections.sort_by_key(|it| (it.student, it.project));
+        res.marks.sort_by_key(|it| (it.student, it.project));
+        res.comments.sort_by_key(|it| it.student);
+        res.allocations.sort_by_key(|it| it.student);
+        res
+    }
+
+    #[test]
+    fn exported_sessions_import_back_intact() {
+        let conn = match test_util::connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let sess = seed(&conn);
+
+        // Archives are kept as files, so make sure they survive being serialised too.
+        let exported = export(&conn, sess.id).unwrap();
+        let archive: SessionArchive = serde_json::from_str(&serde_json::to_string(&exported).unwrap()).unwrap();
+        assert_eq!(archive, exported);
+        assert_eq!(archive.projects.len(), 2);
+        assert_eq!(archive.students.len(), 3);
+        assert_eq!(archive.staff.len(), 2);
+
+        // Purging leaves the staff and the student enrolled elsewhere behind, so the import has to match them up by
+        // email rather than creating duplicates. Change them first to check they're updated from the archive, other
+        // than admins not being demoted.
+        session::purge(&conn, sess.id).unwrap();
+        let mut second = staff::get_all(&conn)
+            .unwrap()
+            .into_iter()
+            .find(|it| it.email == "second@archive.test")
+            .unwrap();
+        second.full_name = "Renamed".to_string();
+        second.is_admin = true;
+        staff::update(&conn, &second).unwrap();
+        let kept = archive.students.iter().find(|it| it.email == "kept@archive.test").unwrap();
+        let mut survivor = student::get_all_by_ids(&conn, &[kept.id]).unwrap().remove(0);
+        survivor.full_name = "Renamed".to_string();
+        student::update(&conn, &survivor).unwrap();
+
+        let summary = import(&conn, &archive).unwrap();
+        assert!(summary.session.id != sess.id);
+        assert_eq!(summary.session.name, "Archived");
+        assert_eq!(summary.projects, archive.projects.len());
+        assert_eq!(summary.students, archive.students.len());
+        assert_eq!(summary.selections, archive.selections.len());
+        assert_eq!(summary.marks, archive.marks.len());
+        assert_eq!(summary.comments, archive.comments.len());
+        assert_eq!(summary.allocations, archive.allocations.len());
+
+        let imported = export(&conn, summary.session.id).unwrap();
+        assert_eq!(imported.session, archive.session);
+
+        // Projects are recreated, so every one gets a new ID. Students are matched by email, so only those purged do.
+        let project_ids = archive
+            .projects
+            .iter()
+            .map(|old| {
+                let new = imported.projects.iter().find(|it| it.name == old.name).unwrap();
+                (old.id, new.id)
+            })
+            .collect::<HashMap<i32, i32>>();
+        let student_ids = archive
+            .students
+            .iter()
+            .map(|old| {
+                let new = imported.students.iter().find(|it| it.email == old.email).unwrap();
+                (old.id, new.id)
+            })
+            .collect::<HashMap<i32, i32>>();
+        assert_eq!(project_ids.len(), 2);
+        assert!(project_ids.iter().all(|(old, new)| old != new));
+        assert_eq!(student_ids.len(), 3);
+        for s in &archive.students {
+            if s.id == kept.id {
+                assert_eq!(student_ids[&s.id], s.id);
+            } else {
+                assert!(student_ids[&s.id] != s.id);
+            }
+        }
+
+        let staff = staff::get_all(&conn)
+            .unwrap()
+            .into_iter()
+            .filter(|it| it.email.ends_with("@archive.test"))
+            .collect::<Vec<_>>();
+        assert_eq!(staff.len(), 2);
+        let second = staff.iter().find(|it| it.email == "second@archive.test").unwrap();
+        assert_eq!(second.full_name, "Second Marker");
+        assert!(second.is_admin);
+
+        // Apart from the IDs and the admin flag kept above, the imported session should export exactly as before.
+        let mut expected = remap(&archive, &project_ids, &student_ids);
+        for s in &mut expected.staff {
+            if s.email == "second@archive.test" {
+                s.is_admin = true;
+            }
+        }
+        let identity = |ids: &HashMap<i32, i32>| ids.values().map(|it| (*it, *it)).collect::<HashMap<i32, i32>>();
+        let actual = remap(&imported, &identity(&project_ids), &identity(&student_ids));
+        assert_eq!(actual.staff, expected.staff);
+        assert_eq!(actual.projects, expected.projects);
+        assert_eq!(actual.students, expected.students);
+        assert_eq!(actual.selections, expected.selections);
+        assert_eq!(actual.marks, expected.marks);
+        assert_eq!(actual.comments, expected.comments);
+        assert_eq!(actual.allocations, expected.allocations);
+
+        match import(&conn, &archive) {
+            Err(ArchiveError::Invalid(msg)) => assert_eq!(msg, "a session named 'Archived' already exists"),
+            res => panic!("expected a clashing session name to be rejected, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use bigdecimal::BigDecimal;
+use chrono::naive::NaiveDateTime;
+use diesel::pg::Pg;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+pub use super::models::AuditEvent;
+pub use super::models::new::AuditEvent as NewAuditEvent;
+
+use super::{DatabaseConnection, Page, PageRequest, SelectError};
+use schema::audit_events;
+
+generate_crud_fns!(audit_events, NewAuditEvent, AuditEvent, noupdate);
+
+/// Filters for querying the audit log. Unset fields match everything.
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub target_type: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+/// Records an audit event. Failures are logged rather than returned, so a broken audit table never blocks the action
+/// being audited.
+pub fn record(
+    conn: &DatabaseConnection,
+    actor: &str,
+    action: &str,
+    target_type: &str,
+    target_id: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+) {
+    let ev = NewAuditEvent {
+        actor: actor.to_string(),
+        action: action.to_string(),
+        target_type: target_type.to_string(),
+        target_id,
+        data_before: before,
+        data_after: after,
+    };
+    if let Err(e) = create(conn, &ev) {
+        error!("Unable to record audit event {:?}: {}", ev, e);
+    }
+}
+
+/// Converts a value to JSON for storage in an audit event.
+pub fn snapshot<T: Serialize>(val: &T) -> Option<Value> {
+    serde_json::to_value(val)
+        .map_err(|e| warn!("Unable to serialise audit snapshot: {}", e))
+        .ok()
+}
+
+/// Converts (project, weight) selection pairs to JSON for storage in an audit event. Weights are stored as strings to
+/// avoid losing precision.
+pub fn snapshot_selections(sels: &[(i32, BigDecimal)]) -> Value {
+    Value::Array(
+        sels.iter()
+            .map(|&(project, ref weight)| json!({ "project": project, "weight": weight.to_string() }))
+            .collect(),
+    )
+}
+
+/// Columns the audit log can be sorted by.
+pub const SORT_KEYS: &[&str] = &["created", "actor", "action"];
+
+/// Fetches a page of audit events matching the filter, along with the total number of matches.
+pub fn get_filtered(
+    conn: &DatabaseConnection,
+    filter: &AuditFilter,
+    page: &PageRequest,
+) -> Result<Page<AuditEvent>, SelectError> {
+    generate_select_body!(
+        paged,
+        conn,
+        audit_events,
+        AuditEvent,
+        page,
+        filtered_query(filter),
+        [created: NaiveDateTime, actor: String, action: String]
+    )
+}
+
+fn filtered_query(filter: &AuditFilter) -> audit_events::BoxedQuery<Pg> {
+    use diesel::prelude::*;
+
+    let mut query = audit_events::table.into_boxed();
+    if let Some(ref actor) = filter.actor {
+        query = query.filter(audit_events::actor.eq(actor));
+    }
+    if let Some(ref target_type) = filter.target_type {
+        query = query.filter(audit_events::target_type.eq(target_type));
+    }
+    if let Some(from) = filter.from {
+        query = query.filter(audit_events::created.ge(from));
+    }
+    if let Some(to) = filter.to {
+        query = query.filter(audit_events::created.le(to));
+    }
+    query
+}
@@ -0,0 +1,154 @@
+//! Demand for projects, from students' selections and marks (bookmarks).
+
+use chrono::naive::NaiveDate;
+use diesel::sql_types::{BigInt, Date, Integer};
+
+use super::{DatabaseConnection, SelectError};
+
+/// Ranks each student's selections in a session, first choices first. Equally weighted selections share a rank and
+/// ranks have no gaps, as in the session report. Selections by or for soft deleted entries are skipped.
+pub const RANKED_SELECTIONS_SQL: &str = r#"
+    SELECT sel.student, sel.project,
+        CAST(DENSE_RANK() OVER (PARTITION BY sel.student ORDER BY sel.weight DESC) AS INTEGER) AS rank
+    FROM student_selections sel
+    JOIN students s ON s.id = sel.student
+    JOIN projects p ON p.id = sel.project
+    WHERE p.session = $1 AND s.deleted IS NULL AND p.deleted IS NULL
+"#;
+
+#[derive(QueryableByName, Clone, Debug)]
+pub struct RankedSelection {
+    #[sql_type = "Integer"]
+    pub student: i32,
+    #[sql_type = "Integer"]
+    pub project: i32,
+    /// 1 for a first choice, 2 for a second choice, and so on.
+    #[sql_type = "Integer"]
+    pub rank: i32,
+}
+
+pub fn get_ranked_selections(conn: &DatabaseConnection, sess: i32) -> Result<Vec<RankedSelection>, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let sels = sql_query(RANKED_SELECTIONS_SQL)
+        .bind::<Integer, _>(sess)
+        .load::<RankedSelection>(conn.raw())?;
+    Ok(sels)
+}
+
+/// Ranks the selections of one project ($1) as `RANKED_SELECTIONS_SQL` does, but only for the students who chose it.
+const PROJECT_RANKED_SELECTIONS_SQL: &str = r#"
+    SELECT student, project, rank FROM (
+        SELECT sel.student, sel.project,
+            CAST(DENSE_RANK() OVER (PARTITION BY sel.student ORDER BY sel.weight DESC) AS INTEGER) AS rank
+        FROM student_selections sel
+        JOIN students s ON s.id = sel.student
+        JOIN projects p ON p.id = sel.project
+        WHERE p.session = (SELECT session FROM projects WHERE id = $1)
+            AND sel.student IN (SELECT student FROM student_selections WHERE project = $1)
+            AND s.deleted IS NULL AND p.deleted IS NULL
+    ) ranked
+    WHERE project = $1
+    ORDER BY rank, student
+"#;
+
+/// Fetches the ranked selections of one project, first choices first.
+pub fn get_ranked_selections_for_project(
+    conn: &DatabaseConnection,
+    project: i32,
+) -> Result<Vec<RankedSelection>, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let sels = sql_query(PROJECT_RANKED_SELECTIONS_SQL)
+        .bind::<Integer, _>(project)
+        .load::<RankedSelection>(conn.raw())?;
+    Ok(sels)
+}
+
+/// Fetches the IDs of the students who have marked a project.
+pub fn get_marking_students(conn: &DatabaseConnection, project: i32) -> Result<Vec<i32>, SelectError> {
+    use diesel::prelude::*;
+    use schema::{student_marks, students};
+
+    let ids = student_marks::table
+        .inner_join(students::table)
+        .filter(student_marks::project.eq(project))
+        .filter(students::deleted.is_null())
+        .select(student_marks::student)
+        .load::<i32>(conn.raw())?;
+    Ok(ids)
+}
+
+/// Fetches every (student, project) mark for projects in a session. Marks by or for soft deleted entries are skipped.
+pub fn get_marks_for_session(conn: &DatabaseConnection, sess: i32) -> Result<Vec<(i32, i32)>, SelectError> {
+    use diesel::prelude::*;
+    use schema::{projects, student_marks, students};
+
+    let marks = student_marks::table
+        .inner_join(students::table)
+        .inner_join(projects::table)
+        .filter(projects::session.eq(sess))
+        .filter(students::deleted.is_null())
+        .filter(projects::deleted.is_null())
+        .select((student_marks::student, student_marks::project))
+        .load::<(i32, i32)>(conn.raw())?;
+    Ok(marks)
+}
+
+/// Counts changes to students' selections in a session for each day. Student history also records comment changes, so
+/// entries are only counted if their selections differ from the student's previous entry.
+const SELECTION_ACTIVITY_SQL: &str = r#"
+    WITH entries AS (
+        SELECT h.id, h.student, h.created,
+            string_agg(hs.project || ':' || hs.weight, ',' ORDER BY hs.project) AS sels
+        FROM student_history h
+        JOIN student_history_selections hs ON hs.history = h.id
+        JOIN students s ON s.id = h.student
+        WHERE h.session = $1 AND s.deleted IS NULL
+        GROUP BY h.id
+    ), changes AS (
+        SELECT CAST(created AS DATE) AS day,
+            LAG(sels) OVER (PARTITION BY student ORDER BY id) IS NULL AS first,
+            sels IS DISTINCT FROM LAG(sels) OVER (PARTITION BY student ORDER BY id) AS changed
+        FROM entries
+    )
+    SELECT day,
+        COUNT(*) AS changes,
+        COUNT(*) FILTER (WHERE first) AS new_students,
+        CAST(SUM(COUNT(*) FILTER (WHERE first)) OVER (ORDER BY day) AS BIGINT) AS total_students
+    FROM changes
+    WHERE changed
+    GROUP BY day
+    ORDER BY day
+"#;
+
+/// Selection activity in a session on a single day.
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+pub struct SelectionActivity {
+    #[sql_type = "Date"]
+    pub day: NaiveDate,
+    /// Number of times students changed their selections.
+    #[sql_type = "BigInt"]
+    pub changes: i64,
+    /// Number of students making selections for the first time.
+    #[sql_type = "BigInt"]
+    pub new_students: i64,
+    /// Number of students who had made selections by the end of the day.
+    #[sql_type = "BigInt"]
+    pub total_students: i64,
+}
+
+pub fn get_selection_activity(
+    conn: &DatabaseConnection,
+    sess: i32,
+) -> Result<Vec<SelectionActivity>, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let activity = sql_query(SELECTION_ACTIVITY_SQL)
+        .bind::<Integer, _>(sess)
+        .load::<SelectionActivity>(conn.raw())?;
+    Ok(activity)
+}
@@ -1,10 +1,12 @@
 use diesel;
 use diesel::pg::PgConnection;
+use diesel::sql_types::Text;
 use r2d2;
 use r2d2_diesel::ConnectionManager;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::{Outcome, Request, State};
+use serde_json::Value;
 use std::sync::Arc;
 
 use config::Config;
@@ -112,8 +114,121 @@ macro_rules! generate_crud_fns {
     )
 }
 
-/// Generates the body of a SELECT function.
+/// Generates soft delete functions for tables with a nullable `deleted` timestamp column. Must be used alongside
+/// `generate_crud_fns!`, which brings the required modules into scope.
+macro_rules! generate_soft_delete_fns {
+    ($table:ident, $model_type:ty) => (
+        /// Marks a row as deleted without removing it, so it can be restored later.
+        #[allow(dead_code)]
+        pub fn soft_delete(conn: &db::DatabaseConnection, id: i32) -> Result<(), diesel::result::Error> {
+            use chrono::Utc;
+            use diesel::prelude::*;
+            use schema::$table;
+
+            diesel::update($table::table.find(id))
+                .set($table::deleted.eq(Some(Utc::now().naive_utc())))
+                .execute(conn.raw())
+                .map(|_| ())
+        }
+
+        /// Restores a soft deleted row, provided it was deleted after the given cutoff.
+        #[allow(dead_code)]
+        pub fn restore(
+            conn: &db::DatabaseConnection,
+            id: i32,
+            cutoff: ::chrono::naive::NaiveDateTime,
+        ) -> Result<$model_type, db::SelectError> {
+            use chrono::naive::NaiveDateTime;
+            use diesel::prelude::*;
+            use schema::$table;
+
+            let res = diesel::update($table::table.find(id).filter($table::deleted.ge(cutoff)))
+                .set($table::deleted.eq(None::<NaiveDateTime>))
+                .get_result::<$model_type>(conn.raw())?;
+            Ok(res)
+        }
+
+        /// Fetches all soft deleted rows deleted after the given cutoff, most recently deleted first.
+        #[allow(dead_code)]
+        pub fn get_all_deleted(
+            conn: &db::DatabaseConnection,
+            cutoff: ::chrono::naive::NaiveDateTime,
+        ) -> Result<Vec<$model_type>, db::SelectError> {
+            use diesel::prelude::*;
+            use schema::$table;
+
+            let res = $table::table
+                .filter($table::deleted.ge(cutoff))
+                .order($table::deleted.desc())
+                .load::<$model_type>(conn.raw())?;
+            Ok(res)
+        }
+
+        /// Permanently removes rows soft deleted before the given cutoff. Returns the number of rows removed.
+        #[allow(dead_code)]
+        pub fn purge_deleted_before(
+            conn: &db::DatabaseConnection,
+            cutoff: ::chrono::naive::NaiveDateTime,
+        ) -> Result<usize, diesel::result::Error> {
+            use diesel::prelude::*;
+            use schema::$table;
+
+            diesel::delete($table::table.filter($table::deleted.lt(cutoff))).execute(conn.raw())
+        }
+    )
+}
+
+/// Generates the body of a SELECT function. The `_undeleted` variants exclude soft deleted rows, and can only be used on
+/// tables with a `deleted` column.
 macro_rules! generate_select_body {
+    (single_undeleted, $conn:ident, $table:ident, $model_type:ty $(, ($field:ident, $val:ident))*) => (
+        {
+            let vals = generate_select_body!(
+                multi_undeleted,
+                $conn,
+                $table,
+                $model_type,
+                $(
+                    ($field, $val)
+                ),*)?;
+            match vals.get(0).cloned() {
+                None => Err(SelectError::NoSuchValue()),
+                Some(e) => Ok(e.clone()),
+            }
+        }
+    );
+    (multi_undeleted, $conn:ident, $table:ident, $model_type:ty$(, ($field:ident, $val:ident))*) => (
+        {
+            use diesel;
+            let vals = generate_select_body!(
+                __in_undeleted,
+                $conn,
+                $table,
+                $model_type,
+                $(
+                    ($field, $val)
+                ),*);
+            vals
+                .map_err(|e| {
+                    match e {
+                        diesel::result::Error::NotFound => SelectError::NoSuchValue(),
+                        e => SelectError::DieselError(e),
+                    }
+                })
+        }
+    );
+    (__in_undeleted, $conn:ident, $table:ident, $model_type:ty, $(($field:ident, $val:ident)),*) => (
+        {
+            use diesel::prelude::*;
+            use schema::$table;
+            $table::table
+                .filter($table::deleted.is_null())
+            $(
+                .filter($table::$field.eq(&$val))
+            )*
+                .load::<$model_type>($conn.raw())
+        }
+    );
     (single, $conn:ident, $table:ident, $model_type:ty $(, ($field:ident, $val:ident))*) => (
         {
             let vals = generate_select_body!(
@@ -150,6 +265,73 @@ macro_rules! generate_select_body {
                 })
         }
     );
+    // Fetches one page of a boxed query, sorted by one of the listed columns (each given with its Rust type) with ties
+    // broken by ID. Pages start just after the request's cursor, if any, so they don't shift as rows are added or
+    // removed. Without a limit, everything after the cursor is returned. The query is evaluated twice, once to count
+    // the total rows and once to fetch the page, so it should be cheap to build. A cursor whose value doesn't fit the
+    // sort column matches nothing.
+    (paged, $conn:ident, $table:ident, $model_type:ty, $page:ident, $query:expr, [$($sort:ident: $sort_ty:ty),+]) => (
+        {
+            use diesel::prelude::*;
+            use schema::$table;
+
+            let total = $query.count().get_result::<i64>($conn.raw())?;
+            let mut query = $query;
+            $(
+                if $page.sort == stringify!($sort) {
+                    if let Some(ref after) = $page.after {
+                        let val = ::serde_json::from_value::<$sort_ty>(after.value.clone())
+                            .map_err(|_| ::db::SelectError::NoSuchValue())?;
+                        let tied = $table::$sort.eq(val.clone()).and($table::id.gt(after.id));
+                        query = if $page.descending {
+                            query.filter($table::$sort.lt(val).or(tied))
+                        } else {
+                            query.filter($table::$sort.gt(val).or(tied))
+                        };
+                    }
+                    query = if $page.descending {
+                        query.order(($table::$sort.desc(), $table::id.asc()))
+                    } else {
+                        query.order(($table::$sort.asc(), $table::id.asc()))
+                    };
+                } else
+            )+
+            {
+                if let Some(ref after) = $page.after {
+                    query = query.filter($table::id.gt(after.id));
+                }
+                query = query.order($table::id.asc());
+            }
+            // One extra row is fetched to find out whether there's another page.
+            let mut items = match $page.limit {
+                Some(limit) => query.limit(limit + 1).load::<$model_type>($conn.raw())?,
+                None => query.load::<$model_type>($conn.raw())?,
+            };
+            let mut next = None;
+            if let Some(limit) = $page.limit {
+                if items.len() as i64 > limit {
+                    items.truncate(limit as usize);
+                    if let Some(last) = items.last() {
+                        let value = $(
+                            if $page.sort == stringify!($sort) {
+                                ::serde_json::to_value(&last.$sort)
+                            } else
+                        )+
+                        {
+                            ::serde_json::to_value(&last.id)
+                        };
+                        next = Some(::db::Cursor {
+                            sort: $page.sort.clone(),
+                            descending: $page.descending,
+                            value: value.unwrap_or(::serde_json::Value::Null),
+                            id: last.id,
+                        });
+                    }
+                }
+            }
+            Ok::<_, ::db::SelectError>(::db::Page { items, total, next })
+        }
+    );
     (__in, $conn:ident, $table:ident, $model_type:ty, $(($field:ident, $val:ident)),*) => (
         {
             // Note that we do NOT use the DSL. This causes naming conflicts in some cases (like filtering on `id`...)
@@ -164,6 +346,40 @@ macro_rules! generate_select_body {
     );
 }
 
+/// Which page of a list to fetch, and how to sort it. See `generate_select_body!(paged, ...)`.
+#[derive(Clone, Debug)]
+pub struct PageRequest {
+    /// Where the page starts. Unset for the first page.
+    pub after: Option<Cursor>,
+    /// Most rows to return. Unset to return everything after the cursor.
+    pub limit: Option<i64>,
+    /// Column to sort by. Unknown columns fall back to sorting by ID, so check against the module's `SORT_KEYS` first.
+    pub sort: String,
+    pub descending: bool,
+}
+
+/// Position in a sorted list, just after the row with this ID and value of the sort column. The sort order is kept so
+/// a cursor can't be used with a different one.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Cursor {
+    pub sort: String,
+    pub descending: bool,
+    pub value: Value,
+    pub id: i32,
+}
+
+/// A page of results, along with the total number of rows across all pages.
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    /// Where the next page starts. Unset on the last page.
+    pub next: Option<Cursor>,
+}
+
+// Used to compare emails case-insensitively, as they may have been entered differently in different places.
+sql_function!(lower, lower_t, (x: Text) -> Text);
+
 #[derive(Debug)]
 pub enum SelectError {
     NoSuchValue(),
@@ -179,12 +395,22 @@ impl From<diesel::result::Error> for SelectError {
     }
 }
 
+pub mod allocation;
+pub mod archive;
+pub mod audit;
+pub mod demand;
 pub mod models;
+pub mod notification;
+pub mod outbox;
 pub mod project;
+pub mod proposal;
+pub mod reminder;
 pub mod session;
 pub mod staff;
 pub mod student;
+pub mod tag;
 pub mod user;
+pub mod webhook;
 
 // The following is based on Rocket's guide on integrating DB connection pooling.
 // https://rocket.rs/guide/state/#databases
@@ -1,6 +1,8 @@
 use bigdecimal::BigDecimal;
 use chrono::naive::NaiveDateTime;
+use markdown;
 use schema::*;
+use serde_json::Value;
 
 // Models for returned table rows and updates.
 #[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
@@ -10,20 +12,45 @@ pub struct Staff {
     pub email: String,
     pub full_name: String,
     pub is_admin: bool,
+    pub deleted: Option<NaiveDateTime>,
 }
 
-#[derive(Serialize, Identifiable, Queryable, Associations, AsChangeset, Clone, PartialEq, Debug)]
-#[belongs_to(Session, foreign_key = "last_session")]
+#[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
 #[table_name = "students"]
 pub struct Student {
     pub id: i32,
     pub email: String,
     pub full_name: String,
-    pub last_session: Option<i32>,
+    pub deleted: Option<NaiveDateTime>,
 }
 
+// This doesn't implement AsChangeset - Diesel requires it only be used on types with non-PK fields.
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Student, foreign_key = "student")]
+#[belongs_to(Session, foreign_key = "session")]
+#[table_name = "student_sessions"]
+#[primary_key(student, session)]
+pub struct StudentSession {
+    pub student: i32,
+    pub session: i32,
+}
+
+// This doesn't implement AsChangeset - attributes are replaced wholesale by upserting.
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Student, foreign_key = "student")]
+#[table_name = "student_attributes"]
+#[primary_key(student)]
+pub struct StudentAttributes {
+    pub student: i32,
+    pub programme: Option<String>,
+    /// Modules the student has completed.
+    pub modules: Vec<String>,
+}
+
+// Sessions are always updated as a whole, so clearing the deadline must be written as a NULL.
 #[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
 #[table_name = "sessions"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Session {
     pub id: i32,
     pub name: String,
@@ -31,6 +58,26 @@ pub struct Session {
     pub supervisor_email: String,
     pub created: NaiveDateTime,
     pub force_archive: bool,
+    /// When selections close. Students with incomplete selections are reminded ahead of it.
+    pub deadline: Option<NaiveDateTime>,
+    /// What supervisors can see of the demand for their projects: `hidden`, `counts` or `named`.
+    pub supervisor_demand: String,
+    /// Whether supervisors can see how many students have marked their projects.
+    pub supervisor_marks: bool,
+}
+
+// This doesn't implement AsChangeset - reminders are only ever recorded.
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Session, foreign_key = "session")]
+#[belongs_to(Student, foreign_key = "student")]
+#[table_name = "session_reminders"]
+#[primary_key(session, student, hours_before)]
+pub struct SessionReminder {
+    pub session: i32,
+    pub student: i32,
+    /// The configured offset the reminder was sent for.
+    pub hours_before: i32,
+    pub sent: NaiveDateTime,
 }
 
 #[derive(Serialize, Deserialize, Identifiable, Queryable, Associations, AsChangeset, Clone,
@@ -44,6 +91,11 @@ pub struct Project {
     pub supervisor_email: String,
     pub name: String,
     pub description_md: String,
+    pub deleted: Option<NaiveDateTime>,
+    /// One of `draft`, `submitted`, `approved` or `rejected`. Students only see approved projects.
+    pub status: String,
+    /// Why the project was rejected.
+    pub status_reason: Option<String>,
 }
 
 // This is ugly, but it's the only way to do this cleanly until/if Rust adds delegation properly.
@@ -55,7 +107,55 @@ pub struct ProjectWithStaff {
     pub supervisor_email: String,
     pub name: String,
     pub description_md: String,
+    /// The description rendered from Markdown and sanitised, ready to display.
+    #[serde(skip_deserializing)]
+    pub description_html: String,
     pub additional_staff: Vec<String>,
+    /// IDs of the tags applied to this project.
+    pub tags: Vec<i32>,
+    pub eligibility: Eligibility,
+    pub status: String,
+    pub status_reason: Option<String>,
+}
+
+/// Rules a student must meet to select a project. Empty lists don't restrict anything.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+pub struct Eligibility {
+    /// Programmes the student must be on, any one of which will do.
+    #[serde(default)]
+    pub programmes: Vec<String>,
+    /// Modules the student must have completed, all of which are required.
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+// This doesn't implement AsChangeset - rules are replaced wholesale by upserting.
+// This doesn't implement AsChangeset - attachments are replaced rather than edited.
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "project_attachments"]
+pub struct ProjectAttachment {
+    pub id: i32,
+    pub project: i32,
+    pub filename: String,
+    pub content_type: String,
+    /// Size in bytes.
+    pub size: i64,
+    /// Where the file is kept in attachment storage. Not exposed, since it's meaningless to clients.
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub uploaded_by: String,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "project_eligibility"]
+#[primary_key(project)]
+pub struct ProjectEligibility {
+    pub project: i32,
+    pub programmes: Vec<String>,
+    pub modules: Vec<String>,
 }
 
 // This doesn't implement AsChangeset - Diesel requires it only be used on types with non-PK fields.
@@ -68,6 +168,58 @@ pub struct ProjectStaff {
     pub staff: String,
 }
 
+#[derive(Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "tags"]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+// This doesn't implement AsChangeset - Diesel requires it only be used on types with non-PK fields.
+#[derive(Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Project, foreign_key = "project")]
+#[belongs_to(Tag, foreign_key = "tag")]
+#[table_name = "project_tags"]
+#[primary_key(project, tag)]
+pub struct ProjectTag {
+    pub project: i32,
+    pub tag: i32,
+}
+
+#[derive(Serialize, Identifiable, Queryable, Associations, AsChangeset, Clone, PartialEq, Debug)]
+#[belongs_to(Student, foreign_key = "student")]
+#[belongs_to(Session, foreign_key = "session")]
+#[table_name = "project_proposals"]
+pub struct ProjectProposal {
+    pub id: i32,
+    pub student: i32,
+    pub session: i32,
+    pub name: String,
+    pub description_md: String,
+    /// Email of the staff member asked to supervise the project.
+    pub supervisor_email: String,
+    /// One of `pending`, `accepted` or `rejected`.
+    pub status: String,
+    /// Why the proposal was rejected, if the supervisor gave a reason.
+    pub reason: Option<String>,
+    /// The project created when the proposal was accepted.
+    pub project: Option<i32>,
+    pub created: NaiveDateTime,
+    pub decided: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Identifiable, Queryable, Associations, AsChangeset, Clone, PartialEq, Debug)]
+#[belongs_to(Student, foreign_key = "student")]
+#[belongs_to(Session, foreign_key = "session")]
+#[belongs_to(Project, foreign_key = "project")]
+#[table_name = "allocations"]
+#[primary_key(student, session)]
+pub struct Allocation {
+    pub student: i32,
+    pub session: i32,
+    pub project: i32,
+}
+
 #[derive(Serialize, Identifiable, Queryable, Associations, AsChangeset, Clone, PartialEq, Debug)]
 #[belongs_to(Student, foreign_key = "student")]
 #[belongs_to(Session, foreign_key = "session")]
@@ -101,11 +253,125 @@ pub struct StudentSelection {
     pub weight: BigDecimal,
 }
 
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(Student, foreign_key = "student")]
+#[belongs_to(Session, foreign_key = "session")]
+#[table_name = "student_history"]
+pub struct StudentHistory {
+    pub id: i32,
+    pub student: i32,
+    pub session: i32,
+    pub created: NaiveDateTime,
+    pub comment: Option<String>,
+}
+
+// This doesn't implement AsChangeset - history entries are never modified once written.
+#[derive(Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(StudentHistory, foreign_key = "history")]
+#[table_name = "student_history_selections"]
+#[primary_key(history, project)]
+pub struct StudentHistorySelection {
+    pub history: i32,
+    pub project: i32,
+    pub weight: BigDecimal,
+}
+
+#[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "notifications"]
+pub struct Notification {
+    pub id: i32,
+    /// Email of the user the notification is for.
+    pub recipient: String,
+    pub created: NaiveDateTime,
+    pub kind: String,
+    pub message: String,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub read: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "email_outbox"]
+pub struct OutboxEmail {
+    pub id: i32,
+    pub recipient: String,
+    /// Name of the template the email was rendered from.
+    pub template: String,
+    pub subject: String,
+    pub body: String,
+    pub created: NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub sent: Option<NaiveDateTime>,
+    /// Set once the email has failed too many times to keep retrying.
+    pub abandoned: Option<NaiveDateTime>,
+}
+
+// This doesn't implement AsChangeset - audit events are append-only.
+#[derive(Serialize, Identifiable, Queryable, Clone, PartialEq, Debug)]
+#[table_name = "audit_events"]
+pub struct AuditEvent {
+    pub id: i32,
+    pub created: NaiveDateTime,
+    pub actor: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<String>,
+    pub data_before: Option<Value>,
+    pub data_after: Option<Value>,
+}
+
+#[derive(Serialize, Identifiable, Queryable, AsChangeset, Clone, PartialEq, Debug)]
+#[table_name = "webhooks"]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// Only shown when the webhook is created.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Events delivered to the webhook. `*` subscribes to every event.
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created: NaiveDateTime,
+}
+
+#[derive(Serialize, Identifiable, Queryable, Associations, AsChangeset, Clone, PartialEq, Debug)]
+#[belongs_to(Webhook, foreign_key = "webhook")]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook: i32,
+    pub event: String,
+    pub payload: Value,
+    pub created: NaiveDateTime,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub delivered: Option<NaiveDateTime>,
+    /// Set once delivery has failed too many times to keep retrying.
+    pub abandoned: Option<NaiveDateTime>,
+}
+
+// This doesn't implement AsChangeset - attempts are append-only.
+#[derive(Serialize, Identifiable, Queryable, Associations, Clone, PartialEq, Debug)]
+#[belongs_to(WebhookDelivery, foreign_key = "delivery")]
+#[table_name = "webhook_attempts"]
+pub struct WebhookAttempt {
+    pub id: i32,
+    pub delivery: i32,
+    pub attempted: NaiveDateTime,
+    /// HTTP status returned, if the request got a response at all.
+    pub status: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
 // Models for insertions.
 pub mod new {
     use bigdecimal::BigDecimal;
     use chrono::naive::NaiveDateTime;
     use schema::*;
+    use serde_json::Value;
 
     #[derive(Deserialize, Insertable, PartialEq, Debug)]
     #[table_name = "staff"]
@@ -120,7 +386,21 @@ pub mod new {
     pub struct Student {
         pub email: String,
         pub full_name: String,
-        pub last_session: i32,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "student_sessions"]
+    pub struct StudentSession {
+        pub student: i32,
+        pub session: i32,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "student_attributes"]
+    pub struct StudentAttributes {
+        pub student: i32,
+        pub programme: Option<String>,
+        pub modules: Vec<String>,
     }
 
     #[derive(Deserialize, Insertable, PartialEq, Debug)]
@@ -131,6 +411,18 @@ pub mod new {
         pub supervisor_email: String,
         pub created: Option<NaiveDateTime>,
         pub force_archive: Option<bool>,
+        pub deadline: Option<NaiveDateTime>,
+        /// Defaults to `hidden` if unset.
+        pub supervisor_demand: Option<String>,
+        pub supervisor_marks: Option<bool>,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "session_reminders"]
+    pub struct SessionReminder {
+        pub session: i32,
+        pub student: i32,
+        pub hours_before: i32,
     }
 
     #[derive(Deserialize, Insertable, PartialEq, Debug)]
@@ -141,6 +433,8 @@ pub mod new {
         pub supervisor_email: String,
         pub name: String,
         pub description_md: String,
+        /// Defaults to `draft` if unset.
+        pub status: Option<String>,
     }
 
     #[derive(Deserialize, Clone, Debug)]
@@ -150,6 +444,16 @@ pub mod new {
         pub name: String,
         pub description_md: String,
         pub additional_staff: Vec<String>,
+        /// Session to create the project in. Only required if multiple sessions are open.
+        pub session: Option<i32>,
+        /// IDs of tags to apply to the project.
+        #[serde(default)]
+        pub tags: Vec<i32>,
+        #[serde(default)]
+        pub eligibility: super::Eligibility,
+        /// Set by the server depending on who is creating the project.
+        #[serde(skip_deserializing)]
+        pub status: Option<String>,
     }
 
     #[derive(Insertable, PartialEq, Debug)]
@@ -159,6 +463,56 @@ pub mod new {
         pub staff: String,
     }
 
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "project_attachments"]
+    pub struct ProjectAttachment {
+        pub project: i32,
+        pub filename: String,
+        pub content_type: String,
+        pub size: i64,
+        pub storage_key: String,
+        pub uploaded_by: String,
+    }
+
+    #[derive(Deserialize, Insertable, PartialEq, Debug)]
+    #[table_name = "tags"]
+    pub struct Tag {
+        pub name: String,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "project_tags"]
+    pub struct ProjectTag {
+        pub project: i32,
+        pub tag: i32,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "project_eligibility"]
+    pub struct ProjectEligibility {
+        pub project: i32,
+        pub programmes: Vec<String>,
+        pub modules: Vec<String>,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "project_proposals"]
+    pub struct ProjectProposal {
+        pub student: i32,
+        pub session: i32,
+        pub name: String,
+        pub description_md: String,
+        pub supervisor_email: String,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "allocations"]
+    pub struct Allocation {
+        pub student: i32,
+        pub session: i32,
+        pub project: i32,
+    }
+
     #[derive(Insertable, PartialEq, Debug)]
     #[table_name = "student_comments"]
     pub struct StudentComment {
@@ -181,22 +535,157 @@ pub mod new {
         pub project: i32,
         pub weight: BigDecimal,
     }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "student_history"]
+    pub struct StudentHistory {
+        pub student: i32,
+        pub session: i32,
+        pub comment: Option<String>,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "student_history_selections"]
+    pub struct StudentHistorySelection {
+        pub history: i32,
+        pub project: i32,
+        pub weight: BigDecimal,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "notifications"]
+    pub struct Notification {
+        pub recipient: String,
+        pub kind: String,
+        pub message: String,
+        pub target_type: Option<String>,
+        pub target_id: Option<String>,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "email_outbox"]
+    pub struct OutboxEmail {
+        pub recipient: String,
+        pub template: String,
+        pub subject: String,
+        pub body: String,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "audit_events"]
+    pub struct AuditEvent {
+        pub actor: String,
+        pub action: String,
+        pub target_type: String,
+        pub target_id: Option<String>,
+        pub data_before: Option<Value>,
+        pub data_after: Option<Value>,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "webhooks"]
+    pub struct Webhook {
+        pub url: String,
+        pub secret: String,
+        pub events: Vec<String>,
+        pub active: bool,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "webhook_deliveries"]
+    pub struct WebhookDelivery {
+        pub webhook: i32,
+        pub event: String,
+        pub payload: Value,
+    }
+
+    #[derive(Insertable, PartialEq, Debug)]
+    #[table_name = "webhook_attempts"]
+    pub struct WebhookAttempt {
+        pub delivery: i32,
+        pub status: Option<i32>,
+        pub error: Option<String>,
+        pub duration_ms: i32,
+    }
 }
 
 impl ProjectWithStaff {
-    pub fn from_project(p: Project, s: Vec<ProjectStaff>) -> ProjectWithStaff {
+    pub fn from_project(
+        p: Project,
+        s: Vec<ProjectStaff>,
+        t: Vec<ProjectTag>,
+        e: Vec<ProjectEligibility>,
+    ) -> ProjectWithStaff {
         ProjectWithStaff {
             id: p.id,
             session: p.session,
             supervisor_name: p.supervisor_name,
             supervisor_email: p.supervisor_email,
             name: p.name,
+            description_html: markdown::render(&p.description_md),
             description_md: p.description_md,
+            status: p.status,
+            status_reason: p.status_reason,
             additional_staff: s.into_iter().map(|it| it.staff).collect(),
+            tags: t.into_iter().map(|it| it.tag).collect(),
+            eligibility: e.into_iter()
+                .next()
+                .map(|it| Eligibility {
+                    programmes: it.programmes,
+                    modules: it.modules,
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Eligibility {
+    pub fn is_empty(&self) -> bool {
+        self.programmes.is_empty() && self.modules.is_empty()
+    }
+
+    /// Trims entries and drops blank or duplicate ones.
+    pub fn normalise(&mut self) {
+        normalise_list(&mut self.programmes);
+        normalise_list(&mut self.modules);
+    }
+
+    /// Checks a student against these rules, returning why they're ineligible. Programmes and modules are compared
+    /// case-insensitively. Students without any attributes only meet unrestricted rules.
+    pub fn check(&self, attrs: Option<&StudentAttributes>) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let programme = attrs.and_then(|it| it.programme.as_ref());
+        if !self.programmes.is_empty()
+            && !programme.map_or(false, |p| self.programmes.iter().any(|it| eq_fold(it, p)))
+        {
+            reasons.push(format!("requires programme: {}", self.programmes.join(" or ")));
+        }
+        let completed = attrs.map(|it| &it.modules[..]).unwrap_or(&[]);
+        for module in &self.modules {
+            if !completed.iter().any(|it| eq_fold(it, module)) {
+                reasons.push(format!("requires module: {}", module));
+            }
         }
+        reasons
     }
 }
 
+/// Trims entries and drops blank or duplicate ones, keeping the first of each.
+pub fn normalise_list(list: &mut Vec<String>) {
+    let mut seen = Vec::with_capacity(list.len());
+    for it in list.drain(..) {
+        let it = it.trim().to_string();
+        if !it.is_empty() && !seen.iter().any(|s: &String| eq_fold(s, &it)) {
+            seen.push(it);
+        }
+    }
+    *list = seen;
+}
+
+fn eq_fold(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
 impl new::Project {
     pub fn from_with_staff(p: new::ProjectWithStaff, session: i32) -> Self {
         new::Project {
@@ -205,6 +694,7 @@ impl new::Project {
             supervisor_email: p.supervisor_email,
             name: p.name,
             description_md: p.description_md,
+            status: p.status,
         }
     }
 }
@@ -0,0 +1,68 @@
+//! In-app notifications telling users about things that happened to them, such as a project being reviewed.
+
+use chrono::Utc;
+
+pub use super::models::Notification;
+pub use super::models::new::Notification as NewNotification;
+
+use super::{DatabaseConnection, SelectError};
+
+generate_crud_fns!(notifications, NewNotification, Notification);
+
+/// Notifies a user. Like audit events, failures are logged rather than returned, so that the action being notified
+/// about still goes ahead.
+pub fn notify(
+    conn: &DatabaseConnection,
+    recipient: &str,
+    kind: &str,
+    message: String,
+    target_type: &str,
+    target_id: Option<String>,
+) {
+    let n = NewNotification {
+        recipient: recipient.to_string(),
+        kind: kind.to_string(),
+        message,
+        target_type: Some(target_type.to_string()),
+        target_id,
+    };
+    if let Err(e) = create(conn, &n) {
+        error!("Unable to record notification {:?}: {}", n, e);
+    }
+}
+
+/// Fetches a user's notifications, newest first.
+pub fn get_all_for_recipient(
+    conn: &DatabaseConnection,
+    email: &str,
+    unread_only: bool,
+) -> Result<Vec<Notification>, SelectError> {
+    use diesel::prelude::*;
+    use schema::notifications;
+
+    let mut query = notifications::table
+        .filter(notifications::recipient.eq(email))
+        .into_boxed();
+    if unread_only {
+        query = query.filter(notifications::read.is_null());
+    }
+    let res = query
+        .order((notifications::created.desc(), notifications::id.desc()))
+        .load::<Notification>(conn.raw())?;
+    Ok(res)
+}
+
+/// Marks one of a user's notifications as read. Notifications belonging to other users are treated as missing.
+pub fn mark_read(conn: &DatabaseConnection, email: &str, id: i32) -> Result<Notification, SelectError> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::notifications;
+
+    let res = diesel::update(
+        notifications::table
+            .filter(notifications::id.eq(id))
+            .filter(notifications::recipient.eq(email)),
+    ).set(notifications::read.eq(Utc::now().naive_utc()))
+        .get_result::<Notification>(conn.raw())?;
+    Ok(res)
+}
@@ -0,0 +1,57 @@
+//! Queued emails waiting to be sent by the mail thread. See `mail` for rendering and delivery.
+
+use chrono::naive::NaiveDateTime;
+use chrono::Utc;
+
+pub use super::models::OutboxEmail;
+pub use super::models::new::OutboxEmail as NewOutboxEmail;
+
+use super::{DatabaseConnection, SelectError};
+
+generate_crud_fns!(email_outbox, NewOutboxEmail, OutboxEmail);
+
+/// Fetches emails which are due to be sent or retried, oldest first.
+pub fn get_due(conn: &DatabaseConnection, limit: i64) -> Result<Vec<OutboxEmail>, SelectError> {
+    use diesel::prelude::*;
+    use schema::email_outbox;
+
+    let res = email_outbox::table
+        .filter(email_outbox::sent.is_null())
+        .filter(email_outbox::abandoned.is_null())
+        .filter(email_outbox::next_attempt.le(Utc::now().naive_utc()))
+        .order(email_outbox::next_attempt.asc())
+        .limit(limit)
+        .load::<OutboxEmail>(conn.raw())?;
+    Ok(res)
+}
+
+pub fn mark_sent(conn: &DatabaseConnection, email: &OutboxEmail) -> Result<OutboxEmail, diesel::result::Error> {
+    update(
+        conn,
+        &OutboxEmail {
+            attempts: email.attempts + 1,
+            sent: Some(Utc::now().naive_utc()),
+            ..email.clone()
+        },
+    )
+}
+
+/// Records a failed attempt to send an email. It's retried at `retry_at` if given, or abandoned otherwise.
+pub fn mark_failed(
+    conn: &DatabaseConnection,
+    email: &OutboxEmail,
+    error: String,
+    retry_at: Option<NaiveDateTime>,
+) -> Result<OutboxEmail, diesel::result::Error> {
+    let now = Utc::now().naive_utc();
+    update(
+        conn,
+        &OutboxEmail {
+            attempts: email.attempts + 1,
+            last_error: Some(error),
+            next_attempt: retry_at.unwrap_or(now),
+            abandoned: if retry_at.is_none() { Some(now) } else { None },
+            ..email.clone()
+        },
+    )
+}
@@ -1,14 +1,32 @@
+use std::collections::HashMap;
+
 pub use super::models::Project;
 pub use super::models::ProjectWithStaff;
+pub use super::models::Eligibility;
 pub use super::models::new::Project as NewProject;
 pub use super::models::new::ProjectWithStaff as NewProjectWithStaff;
 
-use super::models::ProjectStaff;
+use super::models::new::ProjectEligibility as NewProjectEligibility;
 use super::models::new::ProjectStaff as NewProjectStaff;
+use super::models::new::ProjectTag as NewProjectTag;
+use super::models::{ProjectEligibility, ProjectStaff, ProjectTag};
+
+use diesel::pg::Pg;
+use diesel::sql_types::{Array, BigInt, Bool, Float, Integer, Nullable, Text};
+use schema::projects;
 
-use super::{session, DatabaseConnection, SelectError};
+use super::{lower, session, tag, DatabaseConnection, Page, PageRequest, SelectError};
 
 generate_crud_fns!(projects, NewProject, Project);
+generate_soft_delete_fns!(projects, Project);
+
+/// Projects start as drafts, which only staff can see.
+pub const DRAFT: &str = "draft";
+/// Submitted projects are waiting for an admin to review them.
+pub const SUBMITTED: &str = "submitted";
+/// Approved projects are visible to students.
+pub const APPROVED: &str = "approved";
+pub const REJECTED: &str = "rejected";
 
 pub fn attach_staff(
     conn: &DatabaseConnection,
@@ -18,72 +36,101 @@ pub fn attach_staff(
     let staff_ents = ProjectStaff::belonging_to(&projs)
         .load::<ProjectStaff>(conn.raw())?
         .grouped_by(&projs);
+    let tag_ents = ProjectTag::belonging_to(&projs)
+        .load::<ProjectTag>(conn.raw())?
+        .grouped_by(&projs);
+    let rule_ents = ProjectEligibility::belonging_to(&projs)
+        .load::<ProjectEligibility>(conn.raw())?
+        .grouped_by(&projs);
     Ok(projs
         .into_iter()
         .zip(staff_ents)
-        .map(move |(p, s)| ProjectWithStaff::from_project(p, s))
+        .zip(tag_ents)
+        .zip(rule_ents)
+        .map(move |(((p, s), t), e)| ProjectWithStaff::from_project(p, s, t, e))
         .collect())
 }
 
 pub fn create_with_staff(
     conn: &DatabaseConnection,
     ps: &NewProjectWithStaff,
+    sess: i32,
 ) -> Result<ProjectWithStaff, diesel::result::Error> {
     use diesel::insert_into;
     use diesel::prelude::*;
     use schema::{project_staff, projects};
 
-    let sess = session::get_latest_session(conn).map_err(|e| match e {
-        SelectError::DieselError(e) => e,
-        SelectError::NoSuchValue() => diesel::result::Error::NotFound,
-    })?;
-
-    // Insert projects - this works like the macro, but we need the ID back!
-    let res = insert_into(projects::table)
-        .values(&NewProject::from_with_staff(ps.clone(), sess.id))
-        .get_result::<Project>(conn.raw())?;
+    // One transaction, so a failure part way through never leaves a project without its staff, tags or rules.
+    conn.raw().transaction(|| {
+        // Insert projects - this works like the macro, but we need the ID back!
+        let res = insert_into(projects::table)
+            .values(&NewProject::from_with_staff(ps.clone(), sess))
+            .get_result::<Project>(conn.raw())?;
 
-    // Merge the new project ID with its staff members, and insert all of them into project_staff.
-    let staff = ps.additional_staff
-        .iter()
-        .map(|s| NewProjectStaff {
-            project: res.id,
-            staff: s.clone(),
-        })
-        .collect();
+        // Merge the new project ID with its staff members, and insert all of them into project_staff.
+        let staff = ps.additional_staff
+            .iter()
+            .map(|s| NewProjectStaff {
+                project: res.id,
+                staff: s.clone(),
+            })
+            .collect();
 
-    let staff_res = insert_into(project_staff::table)
-        .values::<&Vec<NewProjectStaff>>(&staff)
-        .get_results::<ProjectStaff>(conn.raw())?;
+        let staff_res = insert_into(project_staff::table)
+            .values::<&Vec<NewProjectStaff>>(&staff)
+            .get_results::<ProjectStaff>(conn.raw())?;
+        let tag_res = tag::set_for_project(conn, res.id, &ps.tags)?;
+        let rule_res = set_eligibility(conn, res.id, &ps.eligibility)?;
 
-    Ok(ProjectWithStaff::from_project(res, staff_res))
+        Ok(ProjectWithStaff::from_project(res, staff_res, tag_res, rule_res))
+    })
 }
 
-//#[allow(dead_code)]
-pub fn _create_with_staff_batch(
+/// Creates several projects and their staff in one go. This doesn't open a transaction itself, so callers wanting
+/// all-or-nothing behaviour should wrap it in one.
+pub fn create_with_staff_batch(
     conn: &DatabaseConnection,
     ps: Vec<NewProjectWithStaff>,
-) -> Result<(), diesel::result::Error> {
+    sess: i32,
+) -> Result<Vec<ProjectWithStaff>, diesel::result::Error> {
     use diesel::insert_into;
     use diesel::prelude::*;
-    use schema::{project_staff, projects};
+    use schema::{project_eligibility, project_staff, project_tags, projects};
 
-    let sess = session::get_latest_session(conn).map_err(|e| match e {
-        SelectError::DieselError(e) => e,
-        SelectError::NoSuchValue() => diesel::result::Error::NotFound,
-    })?;
+    if ps.is_empty() {
+        return Ok(Vec::new());
+    }
 
     // Insert projects - this works like the macro, but we need the IDs back!
     let projs: Vec<NewProject> = ps.iter()
-        .map(|it| NewProject::from_with_staff(it.clone(), sess.id))
+        .map(|it| NewProject::from_with_staff(it.clone(), sess))
         .collect();
     let res = insert_into(projects::table)
         .values::<&Vec<NewProject>>(&projs)
-        .returning(projects::id)
-        .get_results(conn.raw())?;
+        .get_results::<Project>(conn.raw())?;
 
-    // Merge the new project IDs with their staff members, and insert all of them into project_staff.
-    let staff = res.into_iter()
+    // Merge the new project IDs with their tags, eligibility rules and staff members, and insert all of them.
+    let rules = res.iter()
+        .map(|it| it.id)
+        .zip(&ps)
+        .filter(|&(_, p)| !p.eligibility.is_empty())
+        .map(|(id, p)| NewProjectEligibility {
+            project: id,
+            programmes: p.eligibility.programmes.clone(),
+            modules: p.eligibility.modules.clone(),
+        })
+        .collect::<Vec<NewProjectEligibility>>();
+    let tags = res.iter()
+        .map(|it| it.id)
+        .zip(&ps)
+        .flat_map(|(id, p)| {
+            p.tags
+                .iter()
+                .map(move |&tag| NewProjectTag { project: id, tag })
+        })
+        .collect();
+    let staff = res.iter()
+        .map(|it| it.id)
         .zip(ps)
         .flat_map(|(id, p)| {
             p.additional_staff
@@ -95,29 +142,390 @@ pub fn _create_with_staff_batch(
         })
         .collect();
 
-    insert_into(project_staff::table)
+    let staff_res = insert_into(project_staff::table)
         .values::<&Vec<NewProjectStaff>>(&staff)
+        .get_results::<ProjectStaff>(conn.raw())?
+        .grouped_by(&res);
+    let tag_res = if tags.is_empty() {
+        Vec::new()
+    } else {
+        insert_into(project_tags::table)
+            .values::<&Vec<NewProjectTag>>(&tags)
+            .on_conflict_do_nothing()
+            .get_results::<ProjectTag>(conn.raw())?
+    }.grouped_by(&res);
+    let rule_res = if rules.is_empty() {
+        Vec::new()
+    } else {
+        insert_into(project_eligibility::table)
+            .values(&rules)
+            .get_results::<ProjectEligibility>(conn.raw())?
+    }.grouped_by(&res);
+
+    Ok(res.into_iter()
+        .zip(staff_res)
+        .zip(tag_res)
+        .zip(rule_res)
+        .map(move |(((p, s), t), e)| ProjectWithStaff::from_project(p, s, t, e))
+        .collect())
+}
+
+/// Fetches the eligibility rules for several projects, keyed by project ID. Projects open to everyone are left out.
+pub fn get_eligibility(
+    conn: &DatabaseConnection,
+    ids: &[i32],
+) -> Result<HashMap<i32, Eligibility>, SelectError> {
+    use diesel::prelude::*;
+    use schema::project_eligibility;
+
+    let rows = project_eligibility::table
+        .filter(project_eligibility::project.eq_any(ids))
+        .load::<ProjectEligibility>(conn.raw())?;
+    Ok(rows.into_iter()
+        .map(|it| {
+            (
+                it.project,
+                Eligibility {
+                    programmes: it.programmes,
+                    modules: it.modules,
+                },
+            )
+        })
+        .collect())
+}
+
+/// Replaces the eligibility rules on a project. Empty rules are removed entirely, so the project is open to everyone.
+pub fn set_eligibility(
+    conn: &DatabaseConnection,
+    project: i32,
+    rules: &Eligibility,
+) -> Result<Vec<ProjectEligibility>, diesel::result::Error> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::project_eligibility;
+
+    diesel::delete(project_eligibility::table.filter(project_eligibility::project.eq(project)))
         .execute(conn.raw())?;
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+    diesel::insert_into(project_eligibility::table)
+        .values(&NewProjectEligibility {
+            project,
+            programmes: rules.programmes.clone(),
+            modules: rules.modules.clone(),
+        })
+        .get_results::<ProjectEligibility>(conn.raw())
+}
 
-    Ok(())
+/// Replaces a project's additional staff.
+pub fn set_staff(
+    conn: &DatabaseConnection,
+    project: i32,
+    staff: &[String],
+) -> Result<Vec<ProjectStaff>, diesel::result::Error> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::project_staff;
+
+    diesel::delete(project_staff::table.filter(project_staff::project.eq(project))).execute(conn.raw())?;
+    if staff.is_empty() {
+        return Ok(Vec::new());
+    }
+    let new = staff
+        .iter()
+        .map(|s| NewProjectStaff {
+            project,
+            staff: s.clone(),
+        })
+        .collect::<Vec<NewProjectStaff>>();
+    diesel::insert_into(project_staff::table)
+        .values(&new)
+        .get_results::<ProjectStaff>(conn.raw())
 }
 
-pub fn get_all_current(conn: &DatabaseConnection) -> Result<Vec<Project>, SelectError> {
-    let sess = session::get_latest_session(conn)?;
-    let id: i32 = sess.id;
-    let projs = generate_select_body!(multi, conn, projects, Project, (session, id))?;
+/// Fetches all projects in the open sessions a student is enrolled in.
+pub fn get_all_current_for_student(
+    conn: &DatabaseConnection,
+    student: i32,
+) -> Result<Vec<Project>, SelectError> {
+    use diesel::prelude::*;
+    use schema::projects;
+
+    let sessions = session::get_open_sessions_for_student(conn, student)?
+        .into_iter()
+        .map(|it| it.id)
+        .collect::<Vec<i32>>();
+    let projs = projects::table
+        .filter(projects::session.eq_any(sessions))
+        .filter(projects::deleted.is_null())
+        .filter(projects::status.eq(APPROVED))
+        .load::<Project>(conn.raw())?;
 
     Ok(projs)
 }
 
+/// Filters for listing projects. Unset fields match everything.
+#[derive(Default, Debug)]
+pub struct ProjectFilter {
+    pub tag: Option<i32>,
+    /// Matches both main supervisors and additional staff, by email.
+    pub supervisor: Option<String>,
+    pub session: Option<i32>,
+    /// Limits results to these sessions, e.g. to those visible to a student.
+    pub visible_sessions: Option<Vec<i32>>,
+    /// Only include approved projects, e.g. for students.
+    pub approved_only: bool,
+    pub status: Option<String>,
+}
+
+/// Columns project lists can be sorted by.
+pub const SORT_KEYS: &[&str] = &["id", "name", "supervisor_name", "session"];
+/// Fields of each project in lists, which can be picked out with `fields`.
+pub const FIELDS: &[&str] = &[
+    "id",
+    "session",
+    "supervisor_name",
+    "supervisor_email",
+    "name",
+    "description_md",
+    "description_html",
+    "additional_staff",
+    "tags",
+    "eligibility",
+    "status",
+    "status_reason",
+];
+
+/// Fetches all projects matching the filter.
+pub fn get_filtered(conn: &DatabaseConnection, filter: &ProjectFilter) -> Result<Vec<Project>, SelectError> {
+    use diesel::prelude::*;
+
+    Ok(filtered_query(conn, filter)?.load::<Project>(conn.raw())?)
+}
+
+/// Fetches one page of the projects matching the filter.
+pub fn get_filtered_page(
+    conn: &DatabaseConnection,
+    filter: &ProjectFilter,
+    page: &PageRequest,
+) -> Result<Page<Project>, SelectError> {
+    generate_select_body!(
+        paged,
+        conn,
+        projects,
+        Project,
+        page,
+        filtered_query(conn, filter)?,
+        [id: i32, name: String, supervisor_name: String, session: i32]
+    )
+}
+
+fn filtered_query<'a>(
+    conn: &DatabaseConnection,
+    filter: &ProjectFilter,
+) -> Result<projects::BoxedQuery<'a, Pg>, SelectError> {
+    use diesel::prelude::*;
+    use schema::project_staff;
+
+    let mut query = projects::table
+        .filter(projects::deleted.is_null())
+        .into_boxed();
+    if let Some(ref sessions) = filter.visible_sessions {
+        query = query.filter(projects::session.eq_any(sessions.clone()));
+    }
+    if let Some(sess) = filter.session {
+        query = query.filter(projects::session.eq(sess));
+    }
+    if filter.approved_only {
+        query = query.filter(projects::status.eq(APPROVED));
+    }
+    if let Some(ref status) = filter.status {
+        query = query.filter(projects::status.eq(status.clone()));
+    }
+    if let Some(ref email) = filter.supervisor {
+        let co_supervised = project_staff::table
+            .filter(project_staff::staff.eq(email))
+            .select(project_staff::project)
+            .load::<i32>(conn.raw())?;
+        query = query.filter(
+            projects::supervisor_email
+                .eq(email.clone())
+                .or(projects::id.eq_any(co_supervised)),
+        );
+    }
+    if let Some(t) = filter.tag {
+        query = query.filter(projects::id.eq_any(tag::get_project_ids(conn, t)?));
+    }
+
+    Ok(query)
+}
+
 pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<Project>, SelectError> {
-    generate_select_body!(multi, conn, projects, Project)
+    generate_select_body!(multi_undeleted, conn, projects, Project)
 }
 
 pub fn get_all_by_session(conn: &DatabaseConnection, id: i32) -> Result<Vec<Project>, SelectError> {
-    generate_select_body!(multi, conn, projects, Project, (session, id))
+    generate_select_body!(multi_undeleted, conn, projects, Project, (session, id))
 }
 
 pub fn get_project(conn: &DatabaseConnection, id: i32) -> Result<Project, SelectError> {
-    generate_select_body!(single, conn, projects, Project, (id, id))
+    generate_select_body!(single_undeleted, conn, projects, Project, (id, id))
+}
+
+/// Marks the start of a highlighted search term in snippets. Control characters are used so that they can't clash with
+/// project text, and can be swapped for HTML after escaping.
+pub const HIGHLIGHT_START: char = '\u{2}';
+/// Marks the end of a highlighted search term in snippets.
+pub const HIGHLIGHT_END: char = '\u{3}';
+
+// The search vector expression must match the one in the `projects_search_index` index exactly.
+const SEARCH_SQL: &str = r#"
+    SELECT id,
+        ts_rank(
+            setweight(to_tsvector('english', name), 'A') ||
+            setweight(to_tsvector('english', supervisor_name), 'B') ||
+            setweight(to_tsvector('english', description_md), 'C'),
+            query
+        ) AS rank,
+        ts_headline('english', name, query, 'HighlightAll=TRUE, StartSel=' || chr(2) || ', StopSel=' || chr(3))
+            AS name_highlight,
+        ts_headline('english', description_md, query,
+            'MaxFragments=2, MinWords=5, MaxWords=20, StartSel=' || chr(2) || ', StopSel=' || chr(3)) AS snippet
+    FROM projects, plainto_tsquery('english', $1) query
+    WHERE deleted IS NULL
+        AND (
+            setweight(to_tsvector('english', name), 'A') ||
+            setweight(to_tsvector('english', supervisor_name), 'B') ||
+            setweight(to_tsvector('english', description_md), 'C')
+        ) @@ query
+        AND ($2 IS NULL OR session = ANY($2))
+        AND (NOT $3 OR status = 'approved')
+    ORDER BY rank DESC, id
+    LIMIT $4
+"#;
+
+/// A project matching a search, with matched terms wrapped in `HIGHLIGHT_START` and `HIGHLIGHT_END`.
+#[derive(QueryableByName, Debug)]
+pub struct SearchHit {
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Float"]
+    pub rank: f32,
+    #[sql_type = "Text"]
+    pub name_highlight: String,
+    #[sql_type = "Text"]
+    pub snippet: String,
+}
+
+/// Searches project names, supervisor names and descriptions, best matches first. If `visible_sessions` is set, only
+/// projects in those sessions are searched, and if `approved_only` is set, only approved projects are.
+pub fn search(
+    conn: &DatabaseConnection,
+    query: &str,
+    visible_sessions: Option<Vec<i32>>,
+    approved_only: bool,
+    limit: i64,
+) -> Result<Vec<SearchHit>, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let hits = sql_query(SEARCH_SQL)
+        .bind::<Text, _>(query)
+        .bind::<Nullable<Array<Integer>>, _>(visible_sessions)
+        .bind::<Bool, _>(approved_only)
+        .bind::<BigInt, _>(limit)
+        .load::<SearchHit>(conn.raw())?;
+    Ok(hits)
+}
+
+/// Sets a project's review status. The reason is cleared unless one is given.
+pub fn set_status(
+    conn: &DatabaseConnection,
+    id: i32,
+    status: &str,
+    reason: Option<&str>,
+) -> Result<Project, diesel::result::Error> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::projects;
+
+    diesel::update(projects::table.find(id))
+        .set((projects::status.eq(status), projects::status_reason.eq(reason)))
+        .get_result::<Project>(conn.raw())
+}
+
+/// Fetches projects by ID, in no particular order.
+pub fn get_all_by_ids(conn: &DatabaseConnection, ids: &[i32]) -> Result<Vec<Project>, SelectError> {
+    use diesel::prelude::*;
+    use schema::projects;
+
+    let projs = projects::table
+        .filter(projects::id.eq_any(ids))
+        .filter(projects::deleted.is_null())
+        .load::<Project>(conn.raw())?;
+    Ok(projs)
+}
+
+/// Whether a staff member supervises a project, either as its main supervisor or as additional staff. Emails are
+/// compared case-insensitively, as they may have been entered differently in different places.
+pub fn is_supervised_by(conn: &DatabaseConnection, proj: &Project, email: &str) -> Result<bool, SelectError> {
+    use diesel::dsl::count_star;
+    use diesel::prelude::*;
+    use schema::project_staff;
+
+    let email = email.to_lowercase();
+    if proj.supervisor_email.to_lowercase() == email {
+        return Ok(true);
+    }
+    let n = project_staff::table
+        .filter(project_staff::project.eq(proj.id))
+        .filter(lower(project_staff::staff).eq(email))
+        .select(count_star())
+        .first::<i64>(conn.raw())?;
+    Ok(n > 0)
+}
+
+/// Metadata for files attached to projects. The files themselves are kept in attachment storage; see `storage`.
+pub mod attachment {
+    pub use super::super::models::ProjectAttachment;
+    pub use super::super::models::new::ProjectAttachment as NewProjectAttachment;
+    use super::super::{DatabaseConnection, SelectError};
+
+    generate_crud_fns!(project_attachments, NewProjectAttachment, ProjectAttachment, noupdate);
+
+    pub fn get(conn: &DatabaseConnection, project: i32, id: i32) -> Result<ProjectAttachment, SelectError> {
+        generate_select_body!(
+            single,
+            conn,
+            project_attachments,
+            ProjectAttachment,
+            (project, project),
+            (id, id)
+        )
+    }
+
+    pub fn get_all_for_project(
+        conn: &DatabaseConnection,
+        project: i32,
+    ) -> Result<Vec<ProjectAttachment>, SelectError> {
+        generate_select_body!(
+            multi,
+            conn,
+            project_attachments,
+            ProjectAttachment,
+            (project, project)
+        )
+    }
+
+    /// Fetches the storage keys of every attachment, for finding stored files which no longer have any metadata.
+    pub fn get_all_keys(conn: &DatabaseConnection) -> Result<Vec<String>, SelectError> {
+        use diesel::prelude::*;
+        use schema::project_attachments;
+
+        let keys = project_attachments::table
+            .select(project_attachments::storage_key)
+            .load::<String>(conn.raw())?;
+        Ok(keys)
+    }
 }
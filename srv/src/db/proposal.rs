@@ -0,0 +1,99 @@
+//! Projects proposed by students, which become normal projects allocated to the student once the staff member named
+//! as supervisor accepts them.
+
+use chrono::Utc;
+use diesel::result::Error;
+
+pub use super::models::ProjectProposal;
+pub use super::models::new::ProjectProposal as NewProjectProposal;
+
+use super::{allocation, project, DatabaseConnection, SelectError};
+
+pub const PENDING: &str = "pending";
+pub const ACCEPTED: &str = "accepted";
+pub const REJECTED: &str = "rejected";
+
+generate_crud_fns!(project_proposals, NewProjectProposal, ProjectProposal);
+
+pub fn get(conn: &DatabaseConnection, id: i32) -> Result<ProjectProposal, SelectError> {
+    generate_select_body!(single, conn, project_proposals, ProjectProposal, (id, id))
+}
+
+pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<ProjectProposal>, SelectError> {
+    generate_select_body!(multi, conn, project_proposals, ProjectProposal)
+}
+
+pub fn get_all_for_student(conn: &DatabaseConnection, id: i32) -> Result<Vec<ProjectProposal>, SelectError> {
+    generate_select_body!(multi, conn, project_proposals, ProjectProposal, (student, id))
+}
+
+/// Fetches the proposals naming a staff member as supervisor.
+pub fn get_all_for_supervisor(
+    conn: &DatabaseConnection,
+    email: &str,
+) -> Result<Vec<ProjectProposal>, SelectError> {
+    generate_select_body!(multi, conn, project_proposals, ProjectProposal, (supervisor_email, email))
+}
+
+/// Accepts a proposal in a single transaction: the proposal becomes a project supervised by the named staff member,
+/// and the proposing student is allocated to it.
+pub fn accept(
+    conn: &DatabaseConnection,
+    prop: &ProjectProposal,
+    supervisor_name: &str,
+) -> Result<(ProjectProposal, project::ProjectWithStaff), Error> {
+    use diesel::prelude::*;
+
+    conn.raw().transaction(|| {
+        let proj = project::create_with_staff(
+            conn,
+            &project::NewProjectWithStaff {
+                supervisor_name: supervisor_name.to_string(),
+                supervisor_email: prop.supervisor_email.clone(),
+                name: prop.name.clone(),
+                description_md: prop.description_md.clone(),
+                additional_staff: Vec::new(),
+                session: None,
+                tags: Vec::new(),
+                eligibility: Default::default(),
+                status: Some(project::APPROVED.to_string()),
+            },
+            prop.session,
+        )?;
+        allocation::create(
+            conn,
+            &allocation::NewAllocation {
+                student: prop.student,
+                session: prop.session,
+                project: proj.id,
+            },
+        )?;
+        let res = update(
+            conn,
+            &ProjectProposal {
+                status: ACCEPTED.to_string(),
+                project: Some(proj.id),
+                decided: Some(Utc::now().naive_utc()),
+                ..prop.clone()
+            },
+        )?;
+        Ok((res, proj))
+    })
+}
+
+/// Rejects a proposal, optionally saying why.
+pub fn reject(
+    conn: &DatabaseConnection,
+    prop: &ProjectProposal,
+    reason: Option<String>,
+) -> Result<ProjectProposal, Error> {
+    update(
+        conn,
+        &ProjectProposal {
+            status: REJECTED.to_string(),
+            reason,
+            decided: Some(Utc::now().naive_utc()),
+            ..prop.clone()
+        },
+    )
+}
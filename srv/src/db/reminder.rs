@@ -0,0 +1,62 @@
+//! Deadline reminders sent to students who haven't finished their selections. See `reminder` for the scheduler.
+
+use std::collections::HashMap;
+
+pub use super::models::SessionReminder;
+pub use super::models::new::SessionReminder as NewSessionReminder;
+
+use super::student::{selection, Student};
+use super::{DatabaseConnection, SelectError};
+
+generate_crud_fns!(session_reminders, NewSessionReminder, SessionReminder, noupdate);
+
+/// Fetches the IDs of students already sent the reminder for the given offset.
+pub fn get_reminded(conn: &DatabaseConnection, sess: i32, hours: i32) -> Result<Vec<i32>, SelectError> {
+    use diesel::prelude::*;
+    use schema::session_reminders;
+
+    let res = session_reminders::table
+        .filter(session_reminders::session.eq(sess))
+        .filter(session_reminders::hours_before.eq(hours))
+        .select(session_reminders::student)
+        .load::<i32>(conn.raw())?;
+    Ok(res)
+}
+
+/// Fetches students enrolled in a session who have made fewer than the required number of selections, along with
+/// how many they have made. Students who already have a project allocated don't need to select any.
+pub fn get_incomplete(conn: &DatabaseConnection, sess: i32) -> Result<Vec<(Student, usize)>, SelectError> {
+    use diesel::prelude::*;
+    use schema::{allocations, projects, student_selections, student_sessions, students};
+
+    let enrolled = students::table
+        .inner_join(student_sessions::table)
+        .filter(student_sessions::session.eq(sess))
+        .filter(students::deleted.is_null())
+        .order(students::id.asc())
+        .select(students::table::all_columns())
+        .load::<Student>(conn.raw())?;
+    let allocated = allocations::table
+        .filter(allocations::session.eq(sess))
+        .select(allocations::student)
+        .load::<i32>(conn.raw())?;
+    let selected = student_selections::table
+        .inner_join(projects::table)
+        .filter(projects::session.eq(sess))
+        .select(student_selections::student)
+        .load::<i32>(conn.raw())?;
+
+    let mut counts: HashMap<i32, usize> = HashMap::new();
+    for id in selected {
+        *counts.entry(id).or_insert(0) += 1;
+    }
+    Ok(enrolled
+        .into_iter()
+        .filter(|it| !allocated.contains(&it.id))
+        .map(|it| {
+            let count = counts.get(&it.id).cloned().unwrap_or(0);
+            (it, count)
+        })
+        .filter(|&(_, count)| count < selection::REQUIRED)
+        .collect())
+}
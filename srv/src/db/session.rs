@@ -1,31 +1,63 @@
 pub use super::models::Session;
 pub use super::models::new::Session as NewSession;
 
-use db::{DatabaseConnection, SelectError};
+use chrono::naive::NaiveDateTime;
+use diesel::sql_types::{BigInt, Integer, Text};
+
+use db::demand::RANKED_SELECTIONS_SQL;
+use db::project::{self, NewProjectWithStaff, ProjectWithStaff};
+use db::student::selection;
+use db::{DatabaseConnection, Page, PageRequest, SelectError};
 
 generate_crud_fns!(sessions, NewSession, Session);
 
-/// Fetches a session from the database along with whether it's the current session.
-pub fn get_session(conn: &DatabaseConnection, id: i32) -> Result<(bool, Session), SelectError> {
-    match get_latest_session(conn) {
-        Ok(ref s) if s.id == id => return Ok((true, s.clone())),
-        Ok(_) => (),
-        Err(SelectError::NoSuchValue()) => (), // Pass to try below
-        Err(e @ SelectError::DieselError(_)) => return Err(e),
-    }
+/// Supervisors can't see any demand for their projects.
+pub const DEMAND_HIDDEN: &str = "hidden";
+/// Supervisors can see how many students chose their projects at each rank, but not who.
+pub const DEMAND_COUNTS: &str = "counts";
+/// Supervisors can see which students chose their projects.
+pub const DEMAND_NAMED: &str = "named";
+pub const DEMAND_VISIBILITIES: &[&str] = &[DEMAND_HIDDEN, DEMAND_COUNTS, DEMAND_NAMED];
 
+/// Fetches a session from the database along with whether it's currently open. Sessions stay open until archived.
+pub fn get_session(conn: &DatabaseConnection, id: i32) -> Result<(bool, Session), SelectError> {
     let res = generate_select_body!(single, conn, sessions, Session, (id, id))?;
-    Ok((false, res))
+    Ok((!res.force_archive, res))
 }
 
-pub fn get_latest_session(conn: &DatabaseConnection) -> Result<Session, SelectError> {
+/// Fetches a session by its name, which is unique.
+pub fn find_by_name(conn: &DatabaseConnection, session_name: &str) -> Result<Session, SelectError> {
+    generate_select_body!(single, conn, sessions, Session, (name, session_name))
+}
+
+/// Fetches all open sessions, newest first.
+pub fn get_open_sessions(conn: &DatabaseConnection) -> Result<Vec<Session>, SelectError> {
     use diesel::prelude::*;
     use schema::sessions::dsl::*;
 
     let res = sessions
         .filter(force_archive.eq(false))
         .order(created.desc())
-        .first::<Session>(conn.raw())?;
+        .load::<Session>(conn.raw())?;
+
+    Ok(res)
+}
+
+/// Fetches the open sessions a student is enrolled in, newest first.
+pub fn get_open_sessions_for_student(
+    conn: &DatabaseConnection,
+    student: i32,
+) -> Result<Vec<Session>, SelectError> {
+    use diesel::prelude::*;
+    use schema::{sessions, student_sessions};
+
+    let res = sessions::table
+        .inner_join(student_sessions::table)
+        .filter(student_sessions::student.eq(student))
+        .filter(sessions::force_archive.eq(false))
+        .order(sessions::created.desc())
+        .select(sessions::table::all_columns())
+        .load::<Session>(conn.raw())?;
 
     Ok(res)
 }
@@ -36,16 +68,408 @@ pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<(bool, Session)>, Select
 
     let res = sessions.order(created.desc()).load::<Session>(conn.raw())?;
 
-    let mut first = true;
-    Ok(res.into_iter()
-        .map(|it| {
-            let out = if first && !it.force_archive {
-                (true, it)
-            } else {
-                (false, it)
-            };
-            first = false;
-            out
+    Ok(res.into_iter().map(|it| (!it.force_archive, it)).collect())
+}
+
+/// Columns session lists can be sorted by.
+pub const SORT_KEYS: &[&str] = &["id", "name", "created"];
+
+/// Fetches one page of sessions along with whether each is open, optionally limited to the given session IDs.
+pub fn get_page(
+    conn: &DatabaseConnection,
+    ids: Option<&[i32]>,
+    page: &PageRequest,
+) -> Result<Page<(bool, Session)>, SelectError> {
+    let res = generate_select_body!(
+        paged,
+        conn,
+        sessions,
+        Session,
+        page,
+        {
+            let mut query = sessions::table.into_boxed();
+            if let Some(ids) = ids {
+                query = query.filter(sessions::id.eq_any(ids.to_vec()));
+            }
+            query
+        },
+        [id: i32, name: String, created: NaiveDateTime]
+    )?;
+
+    Ok(Page {
+        items: res.items
+            .into_iter()
+            .map(|it| (!it.force_archive, it))
+            .collect(),
+        total: res.total,
+        next: res.next,
+    })
+}
+
+/// Number of rows in each table belonging to a session, as removed by `purge`.
+#[derive(Serialize, Default, Debug)]
+pub struct PurgeCounts {
+    pub projects: i64,
+    pub project_attachments: i64,
+    pub project_staff: i64,
+    pub project_tags: i64,
+    pub project_eligibility: i64,
+    pub project_proposals: i64,
+    pub allocations: i64,
+    pub student_selections: i64,
+    pub student_marks: i64,
+    pub student_comments: i64,
+    pub student_history: i64,
+    pub student_sessions: i64,
+    pub student_attributes: i64,
+    pub session_reminders: i64,
+    pub students: i64,
+}
+
+/// IDs of the projects belonging to a session, and of the students enrolled only in that session. Soft deleted entries
+/// are included.
+fn get_dependent_ids(
+    conn: &DatabaseConnection,
+    id: i32,
+) -> Result<(Vec<i32>, Vec<i32>), diesel::result::Error> {
+    use diesel::prelude::*;
+    use schema::{projects, student_sessions};
+
+    let projs = projects::table
+        .filter(projects::session.eq(id))
+        .select(projects::id)
+        .load::<i32>(conn.raw())?;
+    let enrolled = student_sessions::table
+        .filter(student_sessions::session.eq(id))
+        .select(student_sessions::student)
+        .load::<i32>(conn.raw())?;
+    let enrolled_elsewhere = student_sessions::table
+        .filter(student_sessions::student.eq_any(&enrolled))
+        .filter(student_sessions::session.ne(id))
+        .select(student_sessions::student)
+        .load::<i32>(conn.raw())?;
+    let studs = enrolled
+        .into_iter()
+        .filter(|it| !enrolled_elsewhere.contains(it))
+        .collect();
+
+    Ok((projs, studs))
+}
+
+/// Counts the rows which would be removed by purging a session, without changing anything.
+pub fn count_purge(conn: &DatabaseConnection, id: i32) -> Result<PurgeCounts, diesel::result::Error> {
+    use diesel::dsl::count_star;
+    use diesel::prelude::*;
+    use schema::{allocations, project_attachments, project_eligibility, project_proposals, project_staff,
+                 project_tags, session_reminders, student_attributes, student_comments, student_history,
+                 student_marks, student_selections, student_sessions};
+
+    let (projs, studs) = get_dependent_ids(conn, id)?;
+
+    Ok(PurgeCounts {
+        projects: projs.len() as i64,
+        project_attachments: project_attachments::table
+            .filter(project_attachments::project.eq_any(&projs))
+            .select(count_star())
+            .first(conn.raw())?,
+        project_staff: project_staff::table
+            .filter(project_staff::project.eq_any(&projs))
+            .select(count_star())
+            .first(conn.raw())?,
+        project_tags: project_tags::table
+            .filter(project_tags::project.eq_any(&projs))
+            .select(count_star())
+            .first(conn.raw())?,
+        project_eligibility: project_eligibility::table
+            .filter(project_eligibility::project.eq_any(&projs))
+            .select(count_star())
+            .first(conn.raw())?,
+        project_proposals: project_proposals::table
+            .filter(
+                project_proposals::session
+                    .eq(id)
+                    .or(project_proposals::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        allocations: allocations::table
+            .filter(
+                allocations::session
+                    .eq(id)
+                    .or(allocations::project.eq_any(&projs))
+                    .or(allocations::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        student_selections: student_selections::table
+            .filter(
+                student_selections::project
+                    .eq_any(&projs)
+                    .or(student_selections::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        student_marks: student_marks::table
+            .filter(
+                student_marks::project
+                    .eq_any(&projs)
+                    .or(student_marks::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        student_comments: student_comments::table
+            .filter(student_comments::session.eq(id))
+            .select(count_star())
+            .first(conn.raw())?,
+        student_history: student_history::table
+            .filter(
+                student_history::session
+                    .eq(id)
+                    .or(student_history::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        student_sessions: student_sessions::table
+            .filter(student_sessions::session.eq(id))
+            .select(count_star())
+            .first(conn.raw())?,
+        student_attributes: student_attributes::table
+            .filter(student_attributes::student.eq_any(&studs))
+            .select(count_star())
+            .first(conn.raw())?,
+        session_reminders: session_reminders::table
+            .filter(
+                session_reminders::session
+                    .eq(id)
+                    .or(session_reminders::student.eq_any(&studs)),
+            )
+            .select(count_star())
+            .first(conn.raw())?,
+        students: studs.len() as i64,
+    })
+}
+
+/// Emails of the students who purging a session would remove, so their logins can be revoked.
+pub fn get_purged_student_emails(conn: &DatabaseConnection, id: i32) -> Result<Vec<String>, diesel::result::Error> {
+    use diesel::prelude::*;
+    use schema::students;
+
+    let (_, studs) = get_dependent_ids(conn, id)?;
+    students::table
+        .filter(students::id.eq_any(&studs))
+        .select(students::email)
+        .load(conn.raw())
+}
+
+/// Removes a session and everything belonging to it in a single transaction. Rows are removed explicitly rather than
+/// relying on cascades, so the returned counts are accurate.
+pub fn purge(conn: &DatabaseConnection, id: i32) -> Result<PurgeCounts, diesel::result::Error> {
+    use diesel::prelude::*;
+    use schema::{allocations, project_attachments, project_eligibility, project_proposals, project_staff,
+                 project_tags, projects, session_reminders, sessions, student_attributes, student_comments,
+                 student_history, student_marks, student_selections, student_sessions, students};
+
+    conn.raw().transaction(|| {
+        let (projs, studs) = get_dependent_ids(conn, id)?;
+
+        let student_selections = diesel::delete(student_selections::table.filter(
+            student_selections::project
+                .eq_any(&projs)
+                .or(student_selections::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        let student_marks = diesel::delete(student_marks::table.filter(
+            student_marks::project
+                .eq_any(&projs)
+                .or(student_marks::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        let student_comments =
+            diesel::delete(student_comments::table.filter(student_comments::session.eq(id)))
+                .execute(conn.raw())?;
+        let student_history = diesel::delete(student_history::table.filter(
+            student_history::session
+                .eq(id)
+                .or(student_history::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        let project_proposals = diesel::delete(project_proposals::table.filter(
+            project_proposals::session
+                .eq(id)
+                .or(project_proposals::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        let allocations = diesel::delete(allocations::table.filter(
+            allocations::session
+                .eq(id)
+                .or(allocations::project.eq_any(&projs))
+                .or(allocations::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        // Only metadata is removed here. The stored files are cleaned up by the retention thread.
+        let project_attachments = diesel::delete(
+            project_attachments::table.filter(project_attachments::project.eq_any(&projs)),
+        ).execute(conn.raw())?;
+        let project_staff =
+            diesel::delete(project_staff::table.filter(project_staff::project.eq_any(&projs)))
+                .execute(conn.raw())?;
+        let project_tags =
+            diesel::delete(project_tags::table.filter(project_tags::project.eq_any(&projs)))
+                .execute(conn.raw())?;
+        let project_eligibility = diesel::delete(
+            project_eligibility::table.filter(project_eligibility::project.eq_any(&projs)),
+        ).execute(conn.raw())?;
+        let projects = diesel::delete(projects::table.filter(projects::id.eq_any(&projs)))
+            .execute(conn.raw())?;
+        let student_sessions =
+            diesel::delete(student_sessions::table.filter(student_sessions::session.eq(id)))
+                .execute(conn.raw())?;
+        let student_attributes = diesel::delete(
+            student_attributes::table.filter(student_attributes::student.eq_any(&studs)),
+        ).execute(conn.raw())?;
+        let session_reminders = diesel::delete(session_reminders::table.filter(
+            session_reminders::session
+                .eq(id)
+                .or(session_reminders::student.eq_any(&studs)),
+        )).execute(conn.raw())?;
+        let students = diesel::delete(students::table.filter(students::id.eq_any(&studs)))
+            .execute(conn.raw())?;
+        diesel::delete(sessions::table.find(id)).execute(conn.raw())?;
+
+        Ok(PurgeCounts {
+            projects: projects as i64,
+            project_attachments: project_attachments as i64,
+            project_staff: project_staff as i64,
+            project_tags: project_tags as i64,
+            project_eligibility: project_eligibility as i64,
+            project_proposals: project_proposals as i64,
+            allocations: allocations as i64,
+            student_selections: student_selections as i64,
+            student_marks: student_marks as i64,
+            student_comments: student_comments as i64,
+            student_history: student_history as i64,
+            student_sessions: student_sessions as i64,
+            student_attributes: student_attributes as i64,
+            session_reminders: session_reminders as i64,
+            students: students as i64,
         })
-        .collect())
+    })
+}
+
+/// Creates a new session and copies the given projects (with their staff) into it in a single transaction.
+pub fn clone_with_projects(
+    conn: &DatabaseConnection,
+    sess: &NewSession,
+    projs: Vec<NewProjectWithStaff>,
+) -> Result<(Session, Vec<ProjectWithStaff>), diesel::result::Error> {
+    use diesel::prelude::*;
+
+    conn.raw().transaction(|| {
+        let new_sess = create(conn, sess)?;
+        let projects = project::create_with_staff_batch(conn, projs, new_sess.id)?;
+        Ok((new_sess, projects))
+    })
+}
+
+/// Counts students enrolled in a session by how far they've got with their selections, along with their comments.
+/// `$2` is the number of selections required.
+const STUDENT_STATS_SQL: &str = r#"
+    WITH enrolled AS (
+        SELECT ss.student
+        FROM student_sessions ss
+        JOIN students s ON s.id = ss.student
+        WHERE ss.session = $1 AND s.deleted IS NULL
+    ), counts AS (
+        SELECT e.student, COUNT(p.id) AS selections
+        FROM enrolled e
+        LEFT JOIN student_selections sel ON sel.student = e.student
+        LEFT JOIN projects p ON p.id = sel.project AND p.session = $1 AND p.deleted IS NULL
+        GROUP BY e.student
+    )
+    SELECT COUNT(*) AS enrolled,
+        COUNT(*) FILTER (WHERE selections >= $2) AS complete,
+        COUNT(*) FILTER (WHERE selections > 0 AND selections < $2) AS partial,
+        COUNT(*) FILTER (WHERE selections = 0) AS no_selections,
+        (
+            SELECT COUNT(*)
+            FROM student_comments c
+            JOIN enrolled e ON e.student = c.student
+            WHERE c.session = $1 AND c.comment IS NOT NULL AND c.comment <> ''
+        ) AS comments
+    FROM counts
+"#;
+
+lazy_static! {
+    /// Counts the interest in each approved project in a session, most first choices first.
+    static ref PROJECT_INTEREST_SQL: String = format!(
+        r#"
+        WITH ranked AS ({})
+        SELECT p.id AS project, p.name, p.supervisor_name,
+            COUNT(r.student) FILTER (WHERE r.rank = 1) AS first_choices,
+            COUNT(r.student) AS selections,
+            (
+                SELECT COUNT(*)
+                FROM student_marks m
+                JOIN students s ON s.id = m.student
+                WHERE m.project = p.id AND s.deleted IS NULL
+            ) AS marks
+        FROM projects p
+        LEFT JOIN ranked r ON r.project = p.id
+        WHERE p.session = $1 AND p.deleted IS NULL AND p.status = $2
+        GROUP BY p.id
+        ORDER BY first_choices DESC, selections DESC, p.id
+        "#,
+        RANKED_SELECTIONS_SQL
+    );
+}
+
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+pub struct StudentStats {
+    /// Enrolled students, excluding soft deleted ones.
+    #[sql_type = "BigInt"]
+    pub enrolled: i64,
+    /// Students who have made all their selections.
+    #[sql_type = "BigInt"]
+    pub complete: i64,
+    #[sql_type = "BigInt"]
+    pub partial: i64,
+    #[sql_type = "BigInt"]
+    pub no_selections: i64,
+    /// Students who have left a comment.
+    #[sql_type = "BigInt"]
+    pub comments: i64,
+}
+
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+pub struct ProjectInterest {
+    #[sql_type = "Integer"]
+    pub project: i32,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Text"]
+    pub supervisor_name: String,
+    #[sql_type = "BigInt"]
+    pub first_choices: i64,
+    /// Students choosing the project at any rank.
+    #[sql_type = "BigInt"]
+    pub selections: i64,
+    #[sql_type = "BigInt"]
+    pub marks: i64,
+}
+
+pub fn get_student_stats(conn: &DatabaseConnection, id: i32) -> Result<StudentStats, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let stats = sql_query(STUDENT_STATS_SQL)
+        .bind::<Integer, _>(id)
+        .bind::<BigInt, _>(selection::REQUIRED as i64)
+        .get_result::<StudentStats>(conn.raw())?;
+    Ok(stats)
+}
+
+pub fn get_project_interest(conn: &DatabaseConnection, id: i32) -> Result<Vec<ProjectInterest>, SelectError> {
+    use diesel::prelude::*;
+    use diesel::sql_query;
+
+    let interest = sql_query(&PROJECT_INTEREST_SQL[..])
+        .bind::<Integer, _>(id)
+        .bind::<Text, _>(project::APPROVED)
+        .load::<ProjectInterest>(conn.raw())?;
+    Ok(interest)
 }
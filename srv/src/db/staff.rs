@@ -7,22 +7,51 @@ use rocket::{Outcome, Request};
 pub use super::models::Staff;
 pub use super::models::new::Staff as NewStaff;
 
-use super::{DatabaseConnection, SelectError};
+use super::{lower, DatabaseConnection, Page, PageRequest, SelectError};
 use session::Session;
 
-// Enable upsert on the email field.
-generate_crud_fns!(staff, NewStaff, Staff, (email -> full_name, is_admin));
+// Enable upsert on the email field. Re-adding a soft deleted staff member restores them.
+generate_crud_fns!(staff, NewStaff, Staff, (email -> full_name, is_admin, deleted));
+generate_soft_delete_fns!(staff, Staff);
 
 pub fn get(conn: &DatabaseConnection, id: i32) -> Result<Staff, SelectError> {
-    generate_select_body!(single, conn, staff, Staff, (id, id))
+    generate_select_body!(single_undeleted, conn, staff, Staff, (id, id))
 }
 
 pub fn find_email(conn: &DatabaseConnection, staff_email: &str) -> Result<Staff, SelectError> {
-    generate_select_body!(single, conn, staff, Staff, (email, staff_email))
+    generate_select_body!(single_undeleted, conn, staff, Staff, (email, staff_email))
+}
+
+/// Like `find_email`, but ignores the case of the stored email.
+pub fn find_email_ignoring_case(conn: &DatabaseConnection, staff_email: &str) -> Result<Staff, SelectError> {
+    use diesel::prelude::*;
+    use schema::staff;
+
+    Ok(staff::table
+        .filter(lower(staff::email).eq(staff_email.to_lowercase()))
+        .filter(staff::deleted.is_null())
+        .first::<Staff>(conn.raw())?)
 }
 
 pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<Staff>, SelectError> {
-    generate_select_body!(multi, conn, staff, Staff)
+    generate_select_body!(multi_undeleted, conn, staff, Staff)
+}
+
+/// Columns staff lists can be sorted by.
+pub const SORT_KEYS: &[&str] = &["id", "email", "full_name"];
+/// Fields of each staff member in lists, which can be picked out with `fields`.
+pub const FIELDS: &[&str] = &["id", "email", "full_name", "is_admin", "deleted"];
+
+pub fn get_page(conn: &DatabaseConnection, page: &PageRequest) -> Result<Page<Staff>, SelectError> {
+    generate_select_body!(
+        paged,
+        conn,
+        staff,
+        Staff,
+        page,
+        staff::table.filter(staff::deleted.is_null()).into_boxed(),
+        [id: i32, email: String, full_name: String]
+    )
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for Staff {
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use rocket::http::Status;
 use rocket::request::{self, FromRequest};
 use rocket::{Outcome, Request};
@@ -6,34 +8,118 @@ pub use super::models::Student;
 pub use super::models::new::Student as NewStudent;
 use super::session;
 
-use super::{DatabaseConnection, SelectError};
+use super::{DatabaseConnection, Page, PageRequest, SelectError};
 use session::Session;
 
-// Enable upsert on the email field.
-generate_crud_fns!(students, NewStudent, Student, (email -> full_name, last_session));
+// Enable upsert on the email field. Re-adding a soft deleted student restores them.
+generate_crud_fns!(students, NewStudent, Student, (email -> full_name, deleted));
+generate_soft_delete_fns!(students, Student);
 
 pub fn get(conn: &DatabaseConnection, id: i32) -> Result<Student, SelectError> {
-    generate_select_body!(single, conn, students, Student, (id, id))
+    generate_select_body!(single_undeleted, conn, students, Student, (id, id))
 }
 
 pub fn find_email(conn: &DatabaseConnection, student_email: &str) -> Result<Student, SelectError> {
-    generate_select_body!(single, conn, students, Student, (email, student_email))
+    generate_select_body!(single_undeleted, conn, students, Student, (email, student_email))
+}
+
+/// Looks up the IDs of students by email. Emails without a matching student are skipped.
+pub fn get_ids_by_email(
+    conn: &DatabaseConnection,
+    emails: &[String],
+) -> Result<Vec<i32>, diesel::result::Error> {
+    use diesel::prelude::*;
+    use schema::students;
+
+    students::table
+        .filter(students::email.eq_any(emails))
+        .select(students::id)
+        .load::<i32>(conn.raw())
+}
+
+/// Looks up students by email, returning a map of emails to IDs. Emails without a matching student are skipped.
+pub fn get_id_map_by_email(
+    conn: &DatabaseConnection,
+    emails: &[String],
+) -> Result<HashMap<String, i32>, diesel::result::Error> {
+    use diesel::prelude::*;
+    use schema::students;
+
+    let rows = students::table
+        .filter(students::email.eq_any(emails))
+        .select((students::email, students::id))
+        .load::<(String, i32)>(conn.raw())?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Fetches the students with the given IDs. Soft deleted students are skipped.
+pub fn get_all_by_ids(conn: &DatabaseConnection, ids: &[i32]) -> Result<Vec<Student>, SelectError> {
+    use diesel::prelude::*;
+    use schema::students;
+
+    let res = students::table
+        .filter(students::id.eq_any(ids))
+        .filter(students::deleted.is_null())
+        .load::<Student>(conn.raw())?;
+    Ok(res)
 }
 
 pub fn get_all_by_session(
     conn: &DatabaseConnection,
-    session: i32,
+    sess: i32,
 ) -> Result<Vec<Student>, SelectError> {
-    generate_select_body!(multi, conn, students, Student, (last_session, session))
+    use diesel::prelude::*;
+    use schema::{student_sessions, students};
+
+    let res = students::table
+        .inner_join(student_sessions::table)
+        .filter(student_sessions::session.eq(sess))
+        .filter(students::deleted.is_null())
+        .select(students::table::all_columns())
+        .load::<Student>(conn.raw())?;
+
+    Ok(res)
 }
 
+/// Fetches all students enrolled in at least one open session.
 pub fn get_all_current(conn: &DatabaseConnection) -> Result<Vec<Student>, SelectError> {
-    let sess = session::get_latest_session(conn)?;
-    get_all_by_session(conn, sess.id)
+    use diesel::prelude::*;
+    use schema::{student_sessions, students};
+
+    let open = session::get_open_sessions(conn)?
+        .into_iter()
+        .map(|it| it.id)
+        .collect::<Vec<i32>>();
+    let res = students::table
+        .inner_join(student_sessions::table)
+        .filter(student_sessions::session.eq_any(open))
+        .filter(students::deleted.is_null())
+        .select(students::table::all_columns())
+        .distinct()
+        .load::<Student>(conn.raw())?;
+
+    Ok(res)
 }
 
 pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<Student>, SelectError> {
-    generate_select_body!(multi, conn, students, Student)
+    generate_select_body!(multi_undeleted, conn, students, Student)
+}
+
+/// Columns student lists can be sorted by.
+pub const SORT_KEYS: &[&str] = &["id", "email", "full_name"];
+/// Fields of each student in lists, which can be picked out with `fields`.
+pub const FIELDS: &[&str] = &["id", "email", "full_name", "deleted"];
+
+pub fn get_page(conn: &DatabaseConnection, page: &PageRequest) -> Result<Page<Student>, SelectError> {
+    generate_select_body!(
+        paged,
+        conn,
+        students,
+        Student,
+        page,
+        students::table.filter(students::deleted.is_null()).into_boxed(),
+        [id: i32, email: String, full_name: String]
+    )
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for Student {
@@ -43,9 +129,16 @@ impl<'a, 'r> FromRequest<'a, 'r> for Student {
         let sess = request.guard::<Session>()?;
         let conn = request.guard::<DatabaseConnection>()?;
 
-        match find_email(&conn, &sess.email) {
-            Ok(s) => Outcome::Success(s),
-            Err(SelectError::NoSuchValue()) => Outcome::Failure((Status::Forbidden, ())),
+        // Students may only act while enrolled in at least one open session.
+        let res = find_email(&conn, &sess.email).and_then(|s| {
+            session::get_open_sessions_for_student(&conn, s.id)
+                .map(|open| (s, !open.is_empty()))
+        });
+        match res {
+            Ok((s, true)) => Outcome::Success(s),
+            Ok((_, false)) | Err(SelectError::NoSuchValue()) => {
+                Outcome::Failure((Status::Forbidden, ()))
+            }
             Err(SelectError::DieselError(e)) => {
                 error!("Diesel error fetching Student record: {}", e);
                 debug!("Detailed error: {:?}", e);
@@ -55,6 +148,35 @@ impl<'a, 'r> FromRequest<'a, 'r> for Student {
     }
 }
 
+pub mod enrolment {
+    use diesel::result::Error;
+
+    pub use super::super::models::StudentSession;
+    pub use super::super::models::new::StudentSession as NewStudentSession;
+    use super::super::DatabaseConnection;
+
+    generate_crud_fns!(student_sessions, NewStudentSession, StudentSession, noupdate);
+
+    /// Enrols students in a session. Existing enrolments are left as they are.
+    pub fn enrol_batch(conn: &DatabaseConnection, sess: i32, students: &[i32]) -> Result<usize, Error> {
+        use diesel::insert_into;
+        use diesel::prelude::*;
+        use schema::student_sessions;
+
+        let vals = students
+            .iter()
+            .map(|&student| NewStudentSession {
+                student,
+                session: sess,
+            })
+            .collect::<Vec<NewStudentSession>>();
+        insert_into(student_sessions::table)
+            .values(&vals)
+            .on_conflict_do_nothing()
+            .execute(conn.raw())
+    }
+}
+
 pub mod selection {
     use bigdecimal::BigDecimal;
     use diesel::result::Error;
@@ -66,26 +188,42 @@ pub mod selection {
 
     generate_crud_fns!(student_selections, NewStudentSelection, StudentSelection, (student, project -> weight));
 
-    pub fn _get_all_for_student(
+    /// Number of projects each student must select in a session.
+    pub const REQUIRED: usize = 3;
+
+    /// Fetches a student's selections for projects in the given session.
+    pub fn get_all_for_student(
         conn: &DatabaseConnection,
         id: i32,
+        sess: i32,
     ) -> Result<Vec<(i32, BigDecimal)>, SelectError> {
-        let vals = generate_select_body!(
-            multi,
-            conn,
-            student_selections,
-            StudentSelection,
-            (student, id)
-        )?;
+        use diesel::prelude::*;
+        use schema::{projects, student_selections};
+
+        let vals = student_selections::table
+            .inner_join(projects::table)
+            .filter(student_selections::student.eq(id))
+            .filter(projects::session.eq(sess))
+            .select(student_selections::table::all_columns())
+            .load::<StudentSelection>(conn.raw())?;
         Ok(vals.into_iter().map(|it| (it.project, it.weight)).collect())
     }
 
-    pub fn clear_all_for_student(conn: &DatabaseConnection, id: i32) -> Result<(), Error> {
+    /// Clears a student's selections for projects in the given session, leaving other sessions untouched.
+    pub fn clear_all_for_student(conn: &DatabaseConnection, id: i32, sess: i32) -> Result<(), Error> {
         use diesel;
         use diesel::prelude::*;
-        use schema::student_selections;
-        diesel::delete(student_selections::table.filter(student_selections::student.eq(id)))
-            .execute(conn.raw())?;
+        use schema::{projects, student_selections};
+
+        let projs = projects::table
+            .filter(projects::session.eq(sess))
+            .select(projects::id)
+            .load::<i32>(conn.raw())?;
+        diesel::delete(
+            student_selections::table
+                .filter(student_selections::student.eq(id))
+                .filter(student_selections::project.eq_any(projs)),
+        ).execute(conn.raw())?;
         Ok(())
     }
 
@@ -99,6 +237,7 @@ pub mod selection {
         let students = student_selections::table
             .inner_join(students::table)
             .filter(student_selections::project.eq(proj))
+            .filter(students::deleted.is_null())
             .select(students::table::all_columns())
             .load::<student::Student>(conn.raw())?;
 
@@ -110,11 +249,15 @@ pub mod selection {
         sess: i32,
     ) -> Result<Vec<StudentSelection>, SelectError> {
         use diesel::prelude::*;
-        use schema::{student_selections, students};
+        use schema::{projects, student_selections, students};
 
+        // Skip selections by or for soft deleted entries; they're retained only so they can be restored.
         let sels = student_selections::table
             .inner_join(students::table)
-            .filter(students::last_session.eq(sess))
+            .inner_join(projects::table)
+            .filter(projects::session.eq(sess))
+            .filter(students::deleted.is_null())
+            .filter(projects::deleted.is_null())
             .select(student_selections::table::all_columns())
             .load::<StudentSelection>(conn.raw())?;
 
@@ -136,21 +279,40 @@ pub mod mark {
         let vals = generate_select_body!(multi, conn, student_marks, StudentMark, (student, id))?;
         Ok(vals.into_iter().map(|it| it.project).collect())
     }
+
+    /// Fetches all marks on projects in a session, skipping soft deleted students and projects.
+    pub fn get_all_for_session(
+        conn: &DatabaseConnection,
+        sess: i32,
+    ) -> Result<Vec<StudentMark>, SelectError> {
+        use diesel::prelude::*;
+        use schema::{projects, student_marks, students};
+
+        let marks = student_marks::table
+            .inner_join(students::table)
+            .inner_join(projects::table)
+            .filter(projects::session.eq(sess))
+            .filter(students::deleted.is_null())
+            .filter(projects::deleted.is_null())
+            .select(student_marks::table::all_columns())
+            .load::<StudentMark>(conn.raw())?;
+
+        Ok(marks)
+    }
 }
 
 pub mod comment {
     pub use super::super::models::StudentComment;
     pub use super::super::models::new::StudentComment as NewStudentComment;
     use super::super::{DatabaseConnection, SelectError};
-    use db::session;
 
     generate_crud_fns!(student_comments, NewStudentComment, StudentComment, (student, session -> comment));
 
-    pub fn _get_current_for_student(
+    pub fn get_for_student(
         conn: &DatabaseConnection,
         id: i32,
+        s: i32,
     ) -> Result<Option<String>, SelectError> {
-        let s = session::get_latest_session(conn)?.id;
         let comm = generate_select_body!(
             single,
             conn,
@@ -169,3 +331,123 @@ pub mod comment {
         generate_select_body!(multi, conn, student_comments, StudentComment, (session, id))
     }
 }
+
+pub mod attributes {
+    use std::collections::HashMap;
+
+    pub use super::super::models::StudentAttributes;
+    pub use super::super::models::new::StudentAttributes as NewStudentAttributes;
+    use super::super::{DatabaseConnection, SelectError};
+
+    // Enable upsert on the student, so setting attributes replaces any existing ones.
+    generate_crud_fns!(
+        student_attributes,
+        NewStudentAttributes,
+        StudentAttributes,
+        (student -> programme, modules),
+        noupdate
+    );
+
+    /// Fetches a student's attributes, if any have been set.
+    pub fn get_for_student(
+        conn: &DatabaseConnection,
+        id: i32,
+    ) -> Result<Option<StudentAttributes>, SelectError> {
+        match generate_select_body!(single, conn, student_attributes, StudentAttributes, (student, id)) {
+            Ok(v) => Ok(Some(v)),
+            Err(SelectError::NoSuchValue()) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetches the attributes of several students, keyed by student ID. Students without attributes are left out.
+    pub fn get_for_students(
+        conn: &DatabaseConnection,
+        ids: &[i32],
+    ) -> Result<HashMap<i32, StudentAttributes>, SelectError> {
+        use diesel::prelude::*;
+        use schema::student_attributes;
+
+        let vals = student_attributes::table
+            .filter(student_attributes::student.eq_any(ids))
+            .load::<StudentAttributes>(conn.raw())?;
+        Ok(vals.into_iter().map(|it| (it.student, it)).collect())
+    }
+}
+
+pub mod history {
+    use bigdecimal::BigDecimal;
+    use diesel::result::Error;
+
+    pub use super::super::models::new::StudentHistory as NewStudentHistory;
+    pub use super::super::models::new::StudentHistorySelection as NewStudentHistorySelection;
+    pub use super::super::models::{StudentHistory, StudentHistorySelection};
+    use super::super::{DatabaseConnection, SelectError};
+
+    /// Records a snapshot of a student's selections and comment for a session. Each call creates a new version.
+    pub fn record(
+        conn: &DatabaseConnection,
+        student: i32,
+        session: i32,
+        comment: Option<String>,
+        sels: &[(i32, BigDecimal)],
+    ) -> Result<StudentHistory, Error> {
+        use diesel::insert_into;
+        use diesel::prelude::*;
+        use schema::{student_history, student_history_selections};
+
+        let entry = insert_into(student_history::table)
+            .values(&NewStudentHistory {
+                student,
+                session,
+                comment,
+            })
+            .get_result::<StudentHistory>(conn.raw())?;
+
+        let entry_sels = sels.iter()
+            .map(|&(project, ref weight)| NewStudentHistorySelection {
+                history: entry.id,
+                project,
+                weight: weight.clone(),
+            })
+            .collect::<Vec<NewStudentHistorySelection>>();
+        insert_into(student_history_selections::table)
+            .values(&entry_sels)
+            .execute(conn.raw())?;
+
+        Ok(entry)
+    }
+
+    /// Fetches all history entries for a student (newest first) with their selections.
+    pub fn get_all_for_student(
+        conn: &DatabaseConnection,
+        id: i32,
+    ) -> Result<Vec<(StudentHistory, Vec<StudentHistorySelection>)>, SelectError> {
+        use diesel::prelude::*;
+        use schema::student_history;
+
+        let entries = student_history::table
+            .filter(student_history::student.eq(id))
+            .order(student_history::id.desc())
+            .load::<StudentHistory>(conn.raw())?;
+        let sels = StudentHistorySelection::belonging_to(&entries)
+            .load::<StudentHistorySelection>(conn.raw())?
+            .grouped_by(&entries);
+
+        Ok(entries.into_iter().zip(sels).collect())
+    }
+
+    pub fn get(
+        conn: &DatabaseConnection,
+        id: i32,
+    ) -> Result<(StudentHistory, Vec<StudentHistorySelection>), SelectError> {
+        use diesel::prelude::*;
+
+        let entry =
+            generate_select_body!(single, conn, student_history, StudentHistory, (id, id))?;
+        let sels = StudentHistorySelection::belonging_to(&entry)
+            .load::<StudentHistorySelection>(conn.raw())?;
+
+        Ok((entry, sels))
+    }
+}
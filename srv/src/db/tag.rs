@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+pub use super::models::Tag;
+pub use super::models::new::Tag as NewTag;
+
+use super::models::ProjectTag;
+use super::models::new::ProjectTag as NewProjectTag;
+
+use super::{DatabaseConnection, SelectError};
+
+generate_crud_fns!(tags, NewTag, Tag);
+
+pub fn get(conn: &DatabaseConnection, id: i32) -> Result<Tag, SelectError> {
+    generate_select_body!(single, conn, tags, Tag, (id, id))
+}
+
+pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<Tag>, SelectError> {
+    generate_select_body!(multi, conn, tags, Tag)
+}
+
+/// Finds which of the given tag IDs don't exist.
+pub fn find_unknown(conn: &DatabaseConnection, ids: &[i32]) -> Result<Vec<i32>, SelectError> {
+    let known = get_all(conn)?.into_iter().map(|it| it.id).collect::<Vec<i32>>();
+    Ok(ids.iter().filter(|it| !known.contains(it)).cloned().collect())
+}
+
+/// Looks up tags by name, creating any which don't exist yet. Returns a map of names to IDs.
+pub fn get_or_create_by_name(
+    conn: &DatabaseConnection,
+    names: &[String],
+) -> Result<HashMap<String, i32>, diesel::result::Error> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::tags;
+
+    if names.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let new = names
+        .iter()
+        .map(|name| NewTag { name: name.clone() })
+        .collect::<Vec<NewTag>>();
+    diesel::insert_into(tags::table)
+        .values(&new)
+        .on_conflict_do_nothing()
+        .execute(conn.raw())?;
+    let rows = tags::table
+        .filter(tags::name.eq_any(names))
+        .load::<Tag>(conn.raw())?;
+    Ok(rows.into_iter().map(|it| (it.name, it.id)).collect())
+}
+
+/// Replaces the tags on a project.
+pub fn set_for_project(
+    conn: &DatabaseConnection,
+    project: i32,
+    tags: &[i32],
+) -> Result<Vec<ProjectTag>, diesel::result::Error> {
+    use diesel;
+    use diesel::prelude::*;
+    use schema::project_tags;
+
+    diesel::delete(project_tags::table.filter(project_tags::project.eq(project)))
+        .execute(conn.raw())?;
+    if tags.is_empty() {
+        return Ok(Vec::new());
+    }
+    let new = tags.iter()
+        .map(|&tag| NewProjectTag { project, tag })
+        .collect::<Vec<NewProjectTag>>();
+    diesel::insert_into(project_tags::table)
+        .values(&new)
+        .on_conflict_do_nothing()
+        .get_results::<ProjectTag>(conn.raw())
+}
+
+/// Fetches the IDs of all projects with a tag.
+pub fn get_project_ids(conn: &DatabaseConnection, tag: i32) -> Result<Vec<i32>, SelectError> {
+    use diesel::prelude::*;
+    use schema::project_tags;
+
+    let ids = project_tags::table
+        .filter(project_tags::tag.eq(tag))
+        .select(project_tags::project)
+        .load::<i32>(conn.raw())?;
+    Ok(ids)
+}
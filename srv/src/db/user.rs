@@ -29,6 +29,15 @@ pub fn find_user(conn: &DatabaseConnection, email: &str) -> Option<User> {
     }
 }
 
+/// Whether a user may log in, whichever authentication provider they used. Students from past sessions remain in the
+/// database, but can only log in while enrolled in an open session.
+pub fn can_log_in(conn: &DatabaseConnection, usr: &User) -> Result<bool, SelectError> {
+    match *usr {
+        User::Staff(_) => Ok(true),
+        User::Student(ref s) => Ok(!super::session::get_open_sessions_for_student(conn, s.id)?.is_empty()),
+    }
+}
+
 impl User {
     pub fn email(&self) -> String {
         match *self {
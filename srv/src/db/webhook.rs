@@ -0,0 +1,159 @@
+//! Webhook subscriptions, and the queue of payloads waiting to be delivered to them. See `webhook` for delivery.
+
+use chrono::Utc;
+use serde_json::Value;
+
+pub use super::models::Webhook;
+pub use super::models::new::Webhook as NewWebhook;
+
+use super::{DatabaseConnection, SelectError};
+
+generate_crud_fns!(webhooks, NewWebhook, Webhook);
+
+/// Subscribing to this event receives every event.
+pub const ALL_EVENTS: &str = "*";
+
+pub fn get(conn: &DatabaseConnection, id: i32) -> Result<Webhook, SelectError> {
+    generate_select_body!(single, conn, webhooks, Webhook, (id, id))
+}
+
+pub fn get_all(conn: &DatabaseConnection) -> Result<Vec<Webhook>, SelectError> {
+    generate_select_body!(multi, conn, webhooks, Webhook)
+}
+
+/// Queues an event for delivery to every active webhook subscribed to it. Like audit events, failures are logged rather
+/// than returned, so a broken webhook never blocks the action being reported.
+pub fn emit(conn: &DatabaseConnection, event: &str, data: Option<Value>) {
+    let hooks = match get_all(conn) {
+        Ok(hooks) => hooks,
+        Err(e) => {
+            error!("Unable to fetch webhooks for event {}: {:?}", event, e);
+            return;
+        }
+    };
+    let payload = json!({
+        "event": event,
+        "created": Utc::now().naive_utc(),
+        "data": data,
+    });
+    let deliveries = hooks
+        .iter()
+        .filter(|it| it.active && it.events.iter().any(|ev| ev == event || ev == ALL_EVENTS))
+        .map(|it| delivery::NewWebhookDelivery {
+            webhook: it.id,
+            event: event.to_string(),
+            payload: payload.clone(),
+        })
+        .collect::<Vec<delivery::NewWebhookDelivery>>();
+    if deliveries.is_empty() {
+        return;
+    }
+    if let Err(e) = delivery::create_batch(conn, &deliveries) {
+        error!("Unable to queue webhook deliveries for event {}: {}", event, e);
+    }
+}
+
+pub mod delivery {
+    use chrono::Utc;
+    use chrono::naive::NaiveDateTime;
+
+    pub use super::super::models::WebhookDelivery;
+    pub use super::super::models::new::WebhookDelivery as NewWebhookDelivery;
+    use super::super::{DatabaseConnection, SelectError};
+    use super::Webhook;
+
+    generate_crud_fns!(webhook_deliveries, NewWebhookDelivery, WebhookDelivery);
+
+    /// Fetches deliveries which are due to be sent or retried, oldest first, along with their webhook. Deliveries to
+    /// inactive webhooks wait until the webhook is reactivated.
+    pub fn get_due(
+        conn: &DatabaseConnection,
+        limit: i64,
+    ) -> Result<Vec<(WebhookDelivery, Webhook)>, SelectError> {
+        use diesel::prelude::*;
+        use schema::{webhook_deliveries, webhooks};
+
+        let res = webhook_deliveries::table
+            .inner_join(webhooks::table)
+            .filter(webhooks::active.eq(true))
+            .filter(webhook_deliveries::delivered.is_null())
+            .filter(webhook_deliveries::abandoned.is_null())
+            .filter(webhook_deliveries::next_attempt.le(Utc::now().naive_utc()))
+            .order(webhook_deliveries::next_attempt.asc())
+            .limit(limit)
+            .load::<(WebhookDelivery, Webhook)>(conn.raw())?;
+        Ok(res)
+    }
+
+    /// Fetches a webhook's most recent deliveries, newest first.
+    pub fn get_recent_for_webhook(
+        conn: &DatabaseConnection,
+        webhook: i32,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, SelectError> {
+        use diesel::prelude::*;
+        use schema::webhook_deliveries;
+
+        let res = webhook_deliveries::table
+            .filter(webhook_deliveries::webhook.eq(webhook))
+            .order(webhook_deliveries::id.desc())
+            .limit(limit)
+            .load::<WebhookDelivery>(conn.raw())?;
+        Ok(res)
+    }
+
+    pub fn mark_delivered(
+        conn: &DatabaseConnection,
+        delivery: &WebhookDelivery,
+    ) -> Result<WebhookDelivery, diesel::result::Error> {
+        update(
+            conn,
+            &WebhookDelivery {
+                attempts: delivery.attempts + 1,
+                delivered: Some(Utc::now().naive_utc()),
+                ..delivery.clone()
+            },
+        )
+    }
+
+    /// Records a failed delivery. It's retried at `retry_at` if given, or abandoned otherwise.
+    pub fn mark_failed(
+        conn: &DatabaseConnection,
+        delivery: &WebhookDelivery,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<WebhookDelivery, diesel::result::Error> {
+        let now = Utc::now().naive_utc();
+        update(
+            conn,
+            &WebhookDelivery {
+                attempts: delivery.attempts + 1,
+                next_attempt: retry_at.unwrap_or(now),
+                abandoned: if retry_at.is_none() { Some(now) } else { None },
+                ..delivery.clone()
+            },
+        )
+    }
+}
+
+pub mod attempt {
+    pub use super::super::models::WebhookAttempt;
+    pub use super::super::models::new::WebhookAttempt as NewWebhookAttempt;
+    use super::super::{DatabaseConnection, SelectError};
+
+    generate_crud_fns!(webhook_attempts, NewWebhookAttempt, WebhookAttempt, noupdate);
+
+    /// Fetches the attempts made for the given deliveries, oldest first.
+    pub fn get_all_for_deliveries(
+        conn: &DatabaseConnection,
+        deliveries: &[i32],
+    ) -> Result<Vec<WebhookAttempt>, SelectError> {
+        use diesel::prelude::*;
+        use schema::webhook_attempts;
+
+        let res = webhook_attempts::table
+            .filter(webhook_attempts::delivery.eq_any(deliveries))
+            .order(webhook_attempts::id.asc())
+            .load::<WebhookAttempt>(conn.raw())?;
+        Ok(res)
+    }
+}
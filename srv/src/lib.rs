@@ -36,9 +36,16 @@ extern crate serde_json;
 extern crate base64;
 extern crate openssl;
 extern crate toml;
+extern crate csv;
+extern crate simple_excel_writer;
+extern crate ammonia;
+extern crate pulldown_cmark;
 extern crate url;
 
 extern crate ldap3;
+extern crate lettre;
+extern crate lettre_email;
+extern crate native_tls;
 extern crate reqwest;
 extern crate ring_pwhash;
 extern crate rocket;
@@ -56,9 +63,17 @@ mod config;
 mod controller;
 mod db;
 mod fairing;
+mod mail;
+mod markdown;
 mod migrate;
+mod reminder;
+mod retention;
 mod schema;
 mod session;
+mod storage;
+#[cfg(test)]
+mod test_util;
+mod webhook;
 
 #[cfg(feature = "insecure")]
 fn get_rocket_config(conf: &config::Config) -> Config {
@@ -100,6 +115,21 @@ fn get_authn_provider(
     }
 }
 
+fn get_storage_provider(conf: &config::Config) -> Arc<storage::Storage> {
+    match conf.get_attachment_storage().as_str() {
+        "local" => {
+            let dir = conf.get_attachment_dir();
+            let local = storage::local::LocalStorage::new(&dir)
+                .unwrap_or_else(|e| panic!("Unable to use attachment directory {}: {}", dir, e));
+            Arc::new(local)
+        }
+        s => {
+            error!("No such attachment storage: {}", s);
+            panic!("No such attachment storage: {}", s);
+        }
+    }
+}
+
 fn get_conf(conf_loc: &str) -> config::Config {
     // TODO: More nuanced config error handling (like logging what keys had to be defaulted)
     config::load_config(conf_loc).unwrap_or_else(|_| config::default_config())
@@ -128,6 +158,11 @@ pub fn run(conf_loc: &str) -> Result<(), String> {
     let pool = Arc::new(db::init_pool(&conf));
     let auth_provider = get_authn_provider(conf_loc, &conf, Arc::clone(&pool));
     let session_provider = session::SessionManager::new(&conf, Arc::clone(&auth_provider));
+    let storage_provider = get_storage_provider(&conf);
+    retention::start_purge_thread(&conf, Arc::clone(&pool), Arc::clone(&storage_provider));
+    mail::start_outbox_thread(&conf, Arc::clone(&pool));
+    reminder::start_reminder_thread(&conf, Arc::clone(&pool));
+    webhook::start_delivery_thread(Arc::clone(&pool));
 
     rocket::custom(get_rocket_config(&conf), true)
         .attach(fairing::ServerHeader())
@@ -135,6 +170,7 @@ pub fn run(conf_loc: &str) -> Result<(), String> {
         .mount("/api/v1", controller::v1::get_routes(&conf))
         .mount("/api/authn", auth_provider.get_rocket_routes())
         .manage(authn::AuthnHolder(Arc::clone(&auth_provider)))
+        .manage(storage::StorageHolder(storage_provider))
         .manage(pool)
         .manage(session_provider)
         .manage(conf)
@@ -0,0 +1,500 @@
+//! Email notifications. Emails are rendered from templates and queued in the outbox table, then sent over SMTP by a
+//! background thread, which retries failures with exponential backoff. Queueing is a no-op unless the `[mail]` config
+//! section is present.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use lettre::EmailTransport;
+use lettre::smtp::authentication::Credentials;
+use lettre::smtp::client::net::ClientTlsParameters;
+use lettre::smtp::{ClientSecurity, SmtpTransport};
+use lettre_email::{EmailBuilder, Mailbox};
+use native_tls::TlsConnector;
+
+use config::{Config, MailConfig};
+use db::{outbox, DatabaseConnection, Pool};
+use util;
+
+lazy_static! {
+    static ref OUTBOX_SLEEP: Duration = Duration::from_secs(30);
+}
+
+/// Emails sent in each pass over the outbox.
+const BATCH_SIZE: i64 = 50;
+const DEFAULT_SMTP_PORT: u16 = 25;
+const DEFAULT_MAX_ATTEMPTS: i32 = 8;
+
+/// Events which users are emailed about.
+#[derive(Clone, Copy, Debug)]
+pub enum Template {
+    /// Sent to a student when their selections are saved.
+    SelectionsConfirmed,
+    /// Sent to a supervisor when their project is approved or rejected.
+    ProjectStatusChanged,
+    /// Sent to a staff member when a student proposes a project for them to supervise.
+    ProposalSubmitted,
+    /// Sent to a student when their proposal is accepted or rejected.
+    ProposalDecided,
+    /// Sent to a student who hasn't finished their selections as the deadline approaches.
+    DeadlineReminder,
+    /// Sent to a supervisor when a student is allocated to their project.
+    AllocationPublished,
+}
+
+impl Template {
+    /// Name of the template, used for override files and recorded in the outbox.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Template::SelectionsConfirmed => "selections_confirmed",
+            Template::ProjectStatusChanged => "project_status_changed",
+            Template::ProposalSubmitted => "proposal_submitted",
+            Template::ProposalDecided => "proposal_decided",
+            Template::DeadlineReminder => "deadline_reminder",
+            Template::AllocationPublished => "allocation_published",
+        }
+    }
+
+    /// Built-in subject and body.
+    fn default_text(&self) -> (&'static str, &'static str) {
+        match *self {
+            Template::SelectionsConfirmed => (
+                "Your project selections for {{session}}",
+                "Hi {{name}},\n\n\
+                 Your project selections for {{session}} have been saved:\n\n\
+                 {{selections}}\n\n\
+                 You can change them at {{server_address}} until the session closes.\n",
+            ),
+            Template::ProjectStatusChanged => (
+                "Your project \"{{project}}\" was {{status}}",
+                "Hi {{name}},\n\n\
+                 Your project \"{{project}}\" was {{status}}.\n\n\
+                 {{reason}}\n\n\
+                 See {{server_address}} for details.\n",
+            ),
+            Template::ProposalSubmitted => (
+                "New project proposal: {{project}}",
+                "Hi {{name}},\n\n\
+                 {{student}} has proposed the project \"{{project}}\" and would like you to supervise it.\n\n\
+                 You can accept or reject it at {{server_address}}.\n",
+            ),
+            Template::ProposalDecided => (
+                "Your project proposal was {{status}}",
+                "Hi {{name}},\n\n\
+                 Your proposal \"{{project}}\" was {{status}}.\n\n\
+                 {{reason}}\n\n\
+                 See {{server_address}} for details.\n",
+            ),
+            Template::DeadlineReminder => (
+                "Reminder: project selections for {{session}} close on {{deadline}}",
+                "Hi {{name}},\n\n\
+                 Project selections for {{session}} close on {{deadline}}, and you have made {{selections}} of the \
+                 {{required}} required.\n\n\
+                 You can make your selections at {{server_address}}.\n",
+            ),
+            Template::AllocationPublished => (
+                "{{student}} has been allocated to {{project}}",
+                "Hi {{name}},\n\n\
+                 {{student}} has been allocated to your project \"{{project}}\" for {{session}}.\n\n\
+                 See {{server_address}} for details.\n",
+            ),
+        }
+    }
+}
+
+/// Replaces `{{key}}` placeholders with their values. Unknown placeholders are left as they are. The text is scanned
+/// once, so placeholders inside substituted values (which are often user-supplied) are never expanded.
+pub fn render(text: &str, vars: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let key = &after[..end];
+        match vars.iter().find(|&&(k, _)| k == key) {
+            Some(&(_, ref val)) => out.push_str(val),
+            None => out.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Loads a template's subject and body, preferring an override from the configured templates directory. Override files
+/// have the subject on the first line, followed by the body.
+fn load_template(conf: &MailConfig, template: Template) -> (String, String) {
+    let (subject, body) = template.default_text();
+    let dir = match conf.templates_dir {
+        Some(ref dir) => dir,
+        None => return (subject.to_string(), body.to_string()),
+    };
+
+    let path = Path::new(dir).join(format!("{}.txt", template.name()));
+    let mut contents = String::new();
+    match File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => {
+            let mut parts = contents.splitn(2, '\n');
+            let subject = parts.next().unwrap_or("").trim().to_string();
+            let body = parts.next().unwrap_or("").trim_left_matches('\n').to_string();
+            (subject, body)
+        }
+        Err(_) => (subject.to_string(), body.to_string()),
+    }
+}
+
+/// Renders a template and queues it for sending. Like audit events, failures are logged rather than returned, so an
+/// email problem never blocks the action being emailed about.
+pub fn queue(
+    conn: &DatabaseConnection,
+    conf: &Config,
+    template: Template,
+    recipient: &str,
+    vars: &[(&str, String)],
+) {
+    let mail_conf = match conf.get_mail() {
+        Some(c) => c,
+        None => return,
+    };
+
+    let mut vars = vars.to_vec();
+    vars.push(("server_address", conf.get_server_address()));
+    let (subject, body) = load_template(mail_conf, template);
+    let email = outbox::NewOutboxEmail {
+        recipient: recipient.to_string(),
+        template: template.name().to_string(),
+        subject: render(&subject, &vars),
+        body: render(&body, &vars),
+    };
+    if let Err(e) = outbox::create(conn, &email) {
+        error!("Unable to queue email {:?}: {}", email, e);
+    }
+}
+
+/// Whether STARTTLS is required, which it is unless explicitly turned off.
+fn uses_tls(conf: &MailConfig) -> bool {
+    conf.starttls.unwrap_or(true)
+}
+
+/// Checks the settings make sense before anything is sent. Credentials are never sent over an unencrypted connection.
+fn check_config(conf: &MailConfig) -> Result<(), String> {
+    if !uses_tls(conf) && (conf.username.is_some() || conf.password.is_some()) {
+        return Err("SMTP credentials won't be sent unencrypted, so starttls can't be turned off".to_string());
+    }
+    Ok(())
+}
+
+fn build_transport(conf: &MailConfig) -> Result<SmtpTransport, String> {
+    check_config(conf)?;
+    let security = if uses_tls(conf) {
+        let connector = TlsConnector::builder()
+            .and_then(|b| b.build())
+            .map_err(|e| format!("unable to set up TLS: {}", e))?;
+        ClientSecurity::Required(ClientTlsParameters::new(conf.smtp_host.clone(), connector))
+    } else {
+        ClientSecurity::None
+    };
+    let port = conf.smtp_port.unwrap_or(DEFAULT_SMTP_PORT);
+    let mut builder = SmtpTransport::builder((&conf.smtp_host[..], port), security)
+        .map_err(|e| format!("unable to connect to SMTP server: {}", e))?;
+    if let (Some(user), Some(pass)) = (conf.username.as_ref(), conf.password.as_ref()) {
+        builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+    }
+    Ok(builder.build())
+}
+
+fn send(transport: &mut SmtpTransport, conf: &MailConfig, email: &outbox::OutboxEmail) -> Result<(), String> {
+    let from: Mailbox = match conf.from_name {
+        Some(ref name) => (conf.from_address.clone(), name.clone()).into(),
+        None => conf.from_address.clone().into(),
+    };
+    let msg = EmailBuilder::new()
+        .to(email.recipient.clone())
+        .from(from)
+        .subject(email.subject.clone())
+        .text(email.body.clone())
+        .build()
+        .map_err(|e| format!("unable to build email: {}", e))?;
+    transport
+        .send(&msg)
+        .map(|_| ())
+        .map_err(|e| format!("unable to send email: {}", e))
+}
+
+/// Sends every email which is due, recording the outcome of each.
+fn process_outbox(conn: &DatabaseConnection, conf: &MailConfig) {
+    let due = match outbox::get_due(conn, BATCH_SIZE) {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Unable to fetch outbox: {:?}", e);
+            return;
+        }
+    };
+    if due.is_empty() {
+        return;
+    }
+
+    let max_attempts = conf.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS);
+    let mut transport = build_transport(conf);
+    for email in due {
+        let res = match transport {
+            Ok(ref mut t) => send(t, conf, &email),
+            Err(ref e) => Err(e.clone()),
+        };
+        let update = match res {
+            Ok(()) => outbox::mark_sent(conn, &email),
+            Err(e) => {
+                let attempts = email.attempts + 1;
+                let retry_at = if attempts < max_attempts {
+                    Some((Utc::now() + util::retry_delay(attempts)).naive_utc())
+                } else {
+                    None
+                };
+                match retry_at {
+                    Some(at) => warn!("Email {} failed (attempt {}), retrying at {}: {}", email.id, attempts, at, e),
+                    None => error!("Email {} failed {} times, giving up: {}", email.id, attempts, e),
+                }
+                outbox::mark_failed(conn, &email, e, retry_at)
+            }
+        };
+        if let Err(e) = update {
+            error!("Unable to update outbox entry {}: {}", email.id, e);
+        }
+    }
+}
+
+/// Spawns a thread which sends queued emails. Does nothing if email isn't configured.
+pub fn start_outbox_thread(conf: &Config, pool: Arc<Pool>) {
+    let mail_conf = match conf.get_mail() {
+        Some(c) => c.clone(),
+        None => {
+            info!("No [mail] configuration found, so email is disabled.");
+            return;
+        }
+    };
+    if let Err(e) = check_config(&mail_conf) {
+        error!("Invalid [mail] configuration, so email is disabled: {}", e);
+        return;
+    }
+    thread::Builder::new()
+        .name("email-outbox".to_string())
+        .spawn(move || loop {
+            thread::sleep(*OUTBOX_SLEEP);
+
+            let conn = match pool.get() {
+                Ok(conn) => DatabaseConnection(conn),
+                Err(e) => {
+                    warn!("Unable to get database worker for email outbox: {}", e);
+                    continue;
+                }
+            };
+            process_outbox(&conn, &mail_conf);
+        })
+        .expect("outbox thread creation");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use chrono::{Duration as ChronoDuration, Utc};
+
+    use super::*;
+    use test_util;
+
+    /// Starts an SMTP server on a local port, which records each message it receives. If `reject` is set, it refuses
+    /// every recipient instead.
+    fn start_smtp_sink(reject: bool) -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind SMTP sink");
+        let port = listener.local_addr().expect("SMTP sink address").port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    handle_smtp(stream, reject, &sink);
+                }
+            }
+        });
+        (port, received)
+    }
+
+    fn handle_smtp(stream: TcpStream, reject: bool, received: &Mutex<Vec<String>>) {
+        let mut reader = BufReader::new(stream.try_clone().expect("clone SMTP stream"));
+        let mut writer = stream;
+        let _ = writer.write_all(b"220 localhost ESMTP sink\r\n");
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let cmd = line.to_uppercase();
+            let reply: &[u8] = if cmd.starts_with("EHLO") || cmd.starts_with("HELO") {
+                b"250 localhost\r\n"
+            } else if cmd.starts_with("RCPT") && reject {
+                b"550 no such user\r\n"
+            } else if cmd.starts_with("DATA") {
+                let _ = writer.write_all(b"354 go ahead\r\n");
+                let mut msg = String::new();
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == ".\r\n" {
+                        break;
+                    }
+                    msg.push_str(&line);
+                }
+                received.lock().unwrap().push(msg);
+                b"250 queued\r\n"
+            } else if cmd.starts_with("QUIT") {
+                let _ = writer.write_all(b"221 bye\r\n");
+                return;
+            } else {
+                b"250 ok\r\n"
+            };
+            if writer.write_all(reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn sink_config(port: u16) -> MailConfig {
+        MailConfig {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: Some(port),
+            starttls: Some(false),
+            username: None,
+            password: None,
+            from_address: "hpas@example.com".to_string(),
+            from_name: None,
+            templates_dir: None,
+            max_attempts: Some(2),
+        }
+    }
+
+    fn queue_test_email(conn: &DatabaseConnection) -> outbox::OutboxEmail {
+        outbox::create(
+            conn,
+            &outbox::NewOutboxEmail {
+                recipient: "student@example.com".to_string(),
+                template: Template::SelectionsConfirmed.name().to_string(),
+                subject: "Your project selections".to_string(),
+                body: "They have been saved.".to_string(),
+            },
+        ).expect("queue email")
+    }
+
+    fn fetch(conn: &DatabaseConnection, id: i32) -> outbox::OutboxEmail {
+        use diesel::prelude::*;
+        use schema::email_outbox;
+
+        email_outbox::table
+            .find(id)
+            .first(conn.raw())
+            .expect("fetch email")
+    }
+
+    #[test]
+    fn render_replaces_placeholders() {
+        let vars = vec![("name", "Ada".to_string()), ("session", "2018".to_string())];
+        assert_eq!(
+            render("Hi {{name}}, {{session}} {{unknown}} {{name", &vars),
+            "Hi Ada, 2018 {{unknown}} {{name"
+        );
+    }
+
+    #[test]
+    fn render_does_not_expand_substituted_values() {
+        let vars = vec![
+            ("name", "{{secret}}".to_string()),
+            ("secret", "leaked".to_string()),
+        ];
+        assert_eq!(render("Hi {{name}}", &vars), "Hi {{secret}}");
+    }
+
+    #[test]
+    fn credentials_require_tls() {
+        let mut conf = sink_config(DEFAULT_SMTP_PORT);
+        conf.username = Some("user".to_string());
+        conf.password = Some("pass".to_string());
+        assert!(check_config(&conf).is_err());
+        conf.starttls = None;
+        assert!(check_config(&conf).is_ok());
+    }
+
+    #[test]
+    fn delivers_queued_email() {
+        let conn = match test_util::connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let (port, received) = start_smtp_sink(false);
+        let email = queue_test_email(&conn);
+
+        process_outbox(&conn, &sink_config(port));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].contains("Subject: Your project selections"));
+        assert!(received[0].contains("They have been saved."));
+        let res = fetch(&conn, email.id);
+        assert_eq!(res.attempts, 1);
+        assert!(res.sent.is_some());
+        assert!(res.abandoned.is_none());
+    }
+
+    #[test]
+    fn retries_with_backoff_then_gives_up() {
+        let conn = match test_util::connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let (port, received) = start_smtp_sink(true);
+        let conf = sink_config(port);
+        let email = queue_test_email(&conn);
+
+        let before = Utc::now().naive_utc();
+        process_outbox(&conn, &conf);
+        let res = fetch(&conn, email.id);
+        assert_eq!(res.attempts, 1);
+        assert!(res.sent.is_none());
+        assert!(res.abandoned.is_none());
+        assert!(res.last_error.is_some());
+        // The first retry is a minute later.
+        assert!(res.next_attempt >= before + util::retry_delay(1));
+        assert!(res.next_attempt <= Utc::now().naive_utc() + util::retry_delay(1));
+
+        // Nothing is sent again until the retry is due.
+        process_outbox(&conn, &conf);
+        assert_eq!(fetch(&conn, email.id).attempts, 1);
+
+        outbox::update(
+            &conn,
+            &outbox::OutboxEmail {
+                next_attempt: (Utc::now() - ChronoDuration::seconds(1)).naive_utc(),
+                ..res
+            },
+        ).expect("make email due");
+        process_outbox(&conn, &conf);
+        let res = fetch(&conn, email.id);
+        assert_eq!(res.attempts, 2);
+        assert!(res.sent.is_none());
+        assert!(res.abandoned.is_some());
+        assert!(received.lock().unwrap().is_empty());
+    }
+}
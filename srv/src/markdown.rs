@@ -0,0 +1,118 @@
+//! Project descriptions are CommonMark. They're rendered to HTML on the server and sanitised, so raw HTML in a
+//! description can never run scripts in a browser.
+
+use ammonia;
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+/// Longest description accepted, in characters.
+pub const MAX_LENGTH: usize = 20_000;
+
+/// Raw HTML elements rejected outright, rather than being quietly stripped when rendering.
+const BLOCKED_TAGS: &[&str] = &[
+    "base", "embed", "form", "iframe", "input", "link", "meta", "object", "script", "style",
+];
+/// URL schemes which can run code when followed.
+const BLOCKED_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
+/// Raw HTML attributes holding URLs which are followed or loaded.
+const URL_ATTRIBUTES: &[&str] = &["href", "src"];
+
+/// Renders Markdown to sanitised HTML.
+pub fn render(md: &str) -> String {
+    let mut out = String::with_capacity(md.len() * 3 / 2);
+    html::push_html(&mut out, Parser::new(md));
+    ammonia::clean(&out)
+}
+
+/// Checks a description is short enough and free of scripts and the like, returning why it isn't acceptable if not.
+pub fn validate(md: &str) -> Result<(), String> {
+    if md.chars().count() > MAX_LENGTH {
+        return Err(format!("description must be at most {} characters", MAX_LENGTH));
+    }
+
+    for ev in Parser::new(md) {
+        match ev {
+            Event::Html(ref raw) | Event::InlineHtml(ref raw) => {
+                let lower = raw.to_lowercase();
+                if let Some(tag) = find_blocked_tag(&lower) {
+                    return Err(format!("description must not contain <{}> elements", tag));
+                }
+                if find_urls(&lower).into_iter().any(is_blocked_url) {
+                    return Err(blocked_scheme_error());
+                }
+            }
+            Event::Start(Tag::Link(ref dest, _)) | Event::Start(Tag::Image(ref dest, _)) => {
+                if is_blocked_url(&dest.to_lowercase()) {
+                    return Err(blocked_scheme_error());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Finds the first blocked element opened or closed in a lowercase fragment of HTML.
+fn find_blocked_tag(html: &str) -> Option<&'static str> {
+    html.split('<')
+        .skip(1)
+        .filter_map(|it| {
+            let name = it.trim_left_matches('/')
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or("");
+            BLOCKED_TAGS.iter().find(|tag| **tag == name).cloned()
+        })
+        .next()
+}
+
+/// Finds the values of URL attributes in a lowercase fragment of HTML.
+fn find_urls(html: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    for tag in html.split('<').skip(1) {
+        // Skip the element name, then read attributes up to the end of the tag.
+        let mut rest = tag.trim_left_matches(|c: char| !c.is_whitespace() && c != '>');
+        loop {
+            rest = rest.trim_left_matches(|c: char| c.is_whitespace() || c == '/');
+            if rest.is_empty() || rest.starts_with('>') {
+                break;
+            }
+            let name_len = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .unwrap_or_else(|| rest.len());
+            let name = &rest[..name_len];
+            rest = rest[name_len..].trim_left();
+            if !rest.starts_with('=') {
+                continue;
+            }
+            rest = rest[1..].trim_left();
+
+            let value = if rest.starts_with('"') || rest.starts_with('\'') {
+                let end = rest[1..].find(&rest[..1]).map_or(rest.len(), |it| it + 1);
+                let value = &rest[1..end];
+                rest = &rest[(end + 1).min(rest.len())..];
+                value
+            } else {
+                let end = rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or_else(|| rest.len());
+                let value = &rest[..end];
+                rest = &rest[end..];
+                value
+            };
+            if URL_ATTRIBUTES.contains(&name) {
+                res.push(value);
+            }
+        }
+    }
+    res
+}
+
+/// Whether a lowercase URL uses a blocked scheme. Browsers ignore whitespace and control characters in schemes, so
+/// they're ignored here too.
+fn is_blocked_url(url: &str) -> bool {
+    let url = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>();
+    BLOCKED_SCHEMES.iter().any(|it| url.starts_with(it))
+}
+
+fn blocked_scheme_error() -> String {
+    format!("description links must not use {} URLs", BLOCKED_SCHEMES.join(", "))
+}
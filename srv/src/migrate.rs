@@ -6,6 +6,10 @@ embed_migrations!();
 
 pub fn run_pending_migrations(conf: &Config) -> Result<(), ConnectionError> {
     let conn = PgConnection::establish(&conf.get_database_str())?;
-    embedded_migrations::run(&conn).expect("apply bundled migrations");
+    run_on(&conn);
     Ok(())
 }
+
+pub fn run_on(conn: &PgConnection) {
+    embedded_migrations::run(conn).expect("apply bundled migrations");
+}
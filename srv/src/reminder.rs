@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::naive::NaiveDateTime;
+use chrono::{Duration as ChronoDuration, Utc};
+use diesel;
+
+use config::Config;
+use db::reminder::{self, NewSessionReminder};
+use db::session::{self, Session};
+use db::student::{selection, Student};
+use db::{notification, DatabaseConnection, Pool, SelectError};
+use mail;
+
+lazy_static! {
+    static ref REMINDER_SLEEP: Duration = Duration::from_secs(15 * 60); // 15 minutes
+}
+
+/// The next batch of deadline reminders for a session.
+#[derive(Debug)]
+pub struct ReminderPlan {
+    /// The configured offset the reminders are for.
+    pub hours_before: u32,
+    /// When the reminders are due to be sent. This may be in the past if they're due now.
+    pub send_at: NaiveDateTime,
+    /// Students who will be reminded, along with how many selections they have made so far.
+    pub students: Vec<(Student, usize)>,
+}
+
+/// Works out which reminders for a session are sent next, given the configured offsets in ascending order. Once
+/// several offsets have passed only the latest is sent, so students aren't sent a burst of reminders at once. If
+/// everyone has already had that reminder, the plan is for the next upcoming offset instead. Returns `None` if the
+/// session is archived, has no deadline, or its deadline has passed.
+pub fn plan(
+    conn: &DatabaseConnection,
+    hours: &[u32],
+    sess: &Session,
+    now: NaiveDateTime,
+) -> Result<Option<ReminderPlan>, SelectError> {
+    let deadline = match sess.deadline {
+        Some(d) if !sess.force_archive && d > now => d,
+        _ => return Ok(None),
+    };
+    let send_at = |h: u32| deadline - ChronoDuration::hours(i64::from(h));
+    let due = hours.iter().cloned().find(|&h| send_at(h) <= now);
+    let upcoming = hours.iter().cloned().rev().find(|&h| send_at(h) > now);
+
+    let incomplete = reminder::get_incomplete(conn, sess.id)?;
+    let pending = |h: u32| -> Result<ReminderPlan, SelectError> {
+        let reminded = reminder::get_reminded(conn, sess.id, h as i32)?;
+        Ok(ReminderPlan {
+            hours_before: h,
+            send_at: send_at(h),
+            students: incomplete
+                .iter()
+                .filter(|&&(ref s, _)| !reminded.contains(&s.id))
+                .cloned()
+                .collect(),
+        })
+    };
+
+    if let Some(h) = due {
+        let res = pending(h)?;
+        if !res.students.is_empty() || upcoming.is_none() {
+            return Ok(Some(res));
+        }
+    }
+    match upcoming {
+        Some(h) => Ok(Some(pending(h)?)),
+        None => Ok(None),
+    }
+}
+
+/// Sends the reminders for a session which are due now, and records them so they aren't sent again.
+fn send_due(conn: &DatabaseConnection, conf: &Config, hours: &[u32], sess: &Session) -> Result<(), SelectError> {
+    use diesel::prelude::*;
+
+    let now = Utc::now().naive_utc();
+    let plan = match plan(conn, hours, sess, now)? {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    if plan.send_at > now || plan.students.is_empty() {
+        return Ok(());
+    }
+
+    info!(
+        "Reminding {} students about the deadline for session {}.",
+        plan.students.len(),
+        sess.id
+    );
+    let deadline = sess.deadline
+        .expect("planned sessions have deadlines")
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string();
+    // Each reminder is recorded before its messages are queued, all in one transaction, so a failure part way through
+    // never leaves students who were reminded without a record of it, and so reminded again on the next pass.
+    conn.raw().transaction::<_, diesel::result::Error, _>(|| {
+        for (stud, count) in plan.students {
+            reminder::create(
+                conn,
+                &NewSessionReminder {
+                    session: sess.id,
+                    student: stud.id,
+                    hours_before: plan.hours_before as i32,
+                },
+            )?;
+            notification::notify(
+                conn,
+                &stud.email,
+                "session.reminder",
+                format!(
+                    "Selections for {} close on {}. You have made {} of the {} required.",
+                    sess.name,
+                    deadline,
+                    count,
+                    selection::REQUIRED
+                ),
+                "session",
+                Some(sess.id.to_string()),
+            );
+            mail::queue(
+                conn,
+                conf,
+                mail::Template::DeadlineReminder,
+                &stud.email,
+                &[
+                    ("name", stud.full_name.clone()),
+                    ("session", sess.name.clone()),
+                    ("deadline", deadline.clone()),
+                    ("selections", count.to_string()),
+                    ("required", selection::REQUIRED.to_string()),
+                ],
+            );
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Spawns a thread which reminds students in open sessions to finish their selections ahead of the deadline.
+pub fn start_reminder_thread(conf: &Config, pool: Arc<Pool>) {
+    let conf = conf.clone();
+    let hours = conf.get_reminder_hours();
+    if hours.is_empty() {
+        info!("No reminder offsets configured, so deadline reminders are disabled.");
+        return;
+    }
+    thread::Builder::new()
+        .name("deadline-reminders".to_string())
+        .spawn(move || loop {
+            thread::sleep(*REMINDER_SLEEP);
+
+            let conn = match pool.get() {
+                Ok(conn) => DatabaseConnection(conn),
+                Err(e) => {
+                    warn!("Unable to get database worker for deadline reminders: {}", e);
+                    continue;
+                }
+            };
+            let sessions = match session::get_open_sessions(&conn) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Unable to fetch open sessions for deadline reminders: {:?}", e);
+                    continue;
+                }
+            };
+            for sess in sessions.iter().filter(|it| it.deadline.is_some()) {
+                if let Err(e) = send_due(&conn, &conf, &hours, sess) {
+                    error!("Unable to send deadline reminders for session {}: {:?}", sess.id, e);
+                }
+            }
+        })
+        .expect("reminder thread creation");
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::naive::NaiveDateTime;
+use chrono::{Duration as ChronoDuration, Utc};
+
+use config::Config;
+use db::project::attachment;
+use db::{project, staff, student, DatabaseConnection, Pool};
+use storage::Storage;
+
+lazy_static! {
+    static ref PURGE_SLEEP: Duration = Duration::from_secs(60 * 60); // 1 hour
+}
+
+/// Returns the earliest deletion time which is still within the retention window, and so can be restored.
+pub fn get_cutoff(retention_days: u32) -> NaiveDateTime {
+    (Utc::now() - ChronoDuration::days(i64::from(retention_days))).naive_utc()
+}
+
+/// Removes stored attachments which no longer have any metadata, e.g. because their project was purged. Stored keys are
+/// listed before the metadata is read, and uploads record metadata before storing the file, so a file being uploaded
+/// concurrently is never mistaken for an orphan.
+fn purge_orphaned_attachments(conn: &DatabaseConnection, storage: &Storage) -> Result<usize, String> {
+    let stored = storage.keys().map_err(|e| e.to_string())?;
+    let known = attachment::get_all_keys(conn)
+        .map_err(|e| format!("{:?}", e))?
+        .into_iter()
+        .collect::<HashSet<String>>();
+    let mut removed = 0;
+    for key in stored.iter().filter(|it| !known.contains(it)) {
+        storage.delete(key).map_err(|e| e.to_string())?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Spawns a thread which permanently removes soft deleted projects, staff and students once they fall outside of the
+/// retention window, along with any attachments left behind.
+pub fn start_purge_thread(conf: &Config, pool: Arc<Pool>, storage: Arc<Storage>) {
+    let retention_days = conf.get_deleted_retention_days();
+    thread::Builder::new()
+        .name("soft-delete-purge".to_string())
+        .spawn(move || loop {
+            thread::sleep(*PURGE_SLEEP);
+
+            let conn = match pool.get() {
+                Ok(conn) => DatabaseConnection(conn),
+                Err(e) => {
+                    warn!("Unable to get database worker for soft delete purge: {}", e);
+                    continue;
+                }
+            };
+            let cutoff = get_cutoff(retention_days);
+
+            // Students first, since their selections reference projects.
+            let res = student::purge_deleted_before(&conn, cutoff)
+                .and_then(|students| {
+                    project::purge_deleted_before(&conn, cutoff).map(|projects| (students, projects))
+                })
+                .and_then(|(students, projects)| {
+                    staff::purge_deleted_before(&conn, cutoff)
+                        .map(|staff| (students, projects, staff))
+                });
+            match res {
+                Ok((0, 0, 0)) => (),
+                Ok((students, projects, staff)) => info!(
+                    "Purged {} students, {} projects and {} staff deleted before {}.",
+                    students, projects, staff, cutoff
+                ),
+                Err(e) => error!("Error purging soft deleted entries: {}", e),
+            }
+
+            match purge_orphaned_attachments(&conn, &*storage) {
+                Ok(0) => (),
+                Ok(n) => info!("Removed {} orphaned attachments.", n),
+                Err(e) => error!("Error removing orphaned attachments: {}", e),
+            }
+        })
+        .expect("purge thread creation");
+}
@@ -1,3 +1,24 @@
+table! {
+    allocations (student, session) {
+        student -> Int4,
+        session -> Int4,
+        project -> Int4,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Int4,
+        created -> Timestamp,
+        actor -> Text,
+        action -> Text,
+        target_type -> Text,
+        target_id -> Nullable<Text>,
+        data_before -> Nullable<Jsonb>,
+        data_after -> Nullable<Jsonb>,
+    }
+}
+
 table! {
     authn_credentials (login_email) {
         email -> Text,
@@ -6,6 +27,35 @@ table! {
     }
 }
 
+table! {
+    email_outbox (id) {
+        id -> Int4,
+        recipient -> Text,
+        template -> Text,
+        subject -> Text,
+        body -> Text,
+        created -> Timestamp,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        last_error -> Nullable<Text>,
+        sent -> Nullable<Timestamp>,
+        abandoned -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Int4,
+        recipient -> Text,
+        created -> Timestamp,
+        kind -> Text,
+        message -> Text,
+        target_type -> Nullable<Text>,
+        target_id -> Nullable<Text>,
+        read -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     projects (id) {
         id -> Int4,
@@ -14,6 +64,46 @@ table! {
         supervisor_email -> Text,
         name -> Text,
         description_md -> Text,
+        deleted -> Nullable<Timestamp>,
+        status -> Text,
+        status_reason -> Nullable<Text>,
+    }
+}
+
+table! {
+    project_attachments (id) {
+        id -> Int4,
+        project -> Int4,
+        filename -> Text,
+        content_type -> Text,
+        size -> Int8,
+        storage_key -> Text,
+        uploaded_by -> Text,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    project_eligibility (project) {
+        project -> Int4,
+        programmes -> Array<Text>,
+        modules -> Array<Text>,
+    }
+}
+
+table! {
+    project_proposals (id) {
+        id -> Int4,
+        student -> Int4,
+        session -> Int4,
+        name -> Text,
+        description_md -> Text,
+        supervisor_email -> Text,
+        status -> Text,
+        reason -> Nullable<Text>,
+        project -> Nullable<Int4>,
+        created -> Timestamp,
+        decided -> Nullable<Timestamp>,
     }
 }
 
@@ -24,6 +114,13 @@ table! {
     }
 }
 
+table! {
+    project_tags (project, tag) {
+        project -> Int4,
+        tag -> Int4,
+    }
+}
+
 table! {
     sessions (id) {
         id -> Int4,
@@ -32,6 +129,18 @@ table! {
         supervisor_email -> Text,
         created -> Timestamp,
         force_archive -> Bool,
+        deadline -> Nullable<Timestamp>,
+        supervisor_demand -> Text,
+        supervisor_marks -> Bool,
+    }
+}
+
+table! {
+    session_reminders (session, student, hours_before) {
+        session -> Int4,
+        student -> Int4,
+        hours_before -> Int4,
+        sent -> Timestamp,
     }
 }
 
@@ -41,6 +150,15 @@ table! {
         email -> Text,
         full_name -> Text,
         is_admin -> Bool,
+        deleted -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    student_attributes (student) {
+        student -> Int4,
+        programme -> Nullable<Text>,
+        modules -> Array<Text>,
     }
 }
 
@@ -52,6 +170,24 @@ table! {
     }
 }
 
+table! {
+    student_history (id) {
+        id -> Int4,
+        student -> Int4,
+        session -> Int4,
+        created -> Timestamp,
+        comment -> Nullable<Text>,
+    }
+}
+
+table! {
+    student_history_selections (history, project) {
+        history -> Int4,
+        project -> Int4,
+        weight -> Numeric,
+    }
+}
+
 table! {
     student_marks (student, project) {
         student -> Int4,
@@ -64,7 +200,14 @@ table! {
         id -> Int4,
         email -> Text,
         full_name -> Text,
-        last_session -> Nullable<Int4>,
+        deleted -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    student_sessions (student, session) {
+        student -> Int4,
+        session -> Int4,
     }
 }
 
@@ -76,24 +219,102 @@ table! {
     }
 }
 
+table! {
+    tags (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+table! {
+    webhook_attempts (id) {
+        id -> Int4,
+        delivery -> Int4,
+        attempted -> Timestamp,
+        status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        duration_ms -> Int4,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook -> Int4,
+        event -> Text,
+        payload -> Jsonb,
+        created -> Timestamp,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        delivered -> Nullable<Timestamp>,
+        abandoned -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int4,
+        url -> Text,
+        secret -> Text,
+        events -> Array<Text>,
+        active -> Bool,
+        created -> Timestamp,
+    }
+}
+
+joinable!(allocations -> projects (project));
+joinable!(allocations -> sessions (session));
+joinable!(allocations -> students (student));
+joinable!(project_attachments -> projects (project));
+joinable!(project_eligibility -> projects (project));
+joinable!(project_proposals -> sessions (session));
+joinable!(project_proposals -> students (student));
 joinable!(project_staff -> projects (project));
+joinable!(project_tags -> projects (project));
+joinable!(project_tags -> tags (tag));
 joinable!(projects -> sessions (session));
+joinable!(session_reminders -> sessions (session));
+joinable!(session_reminders -> students (student));
+joinable!(student_attributes -> students (student));
 joinable!(student_comments -> sessions (session));
 joinable!(student_comments -> students (student));
+joinable!(student_history -> sessions (session));
+joinable!(student_history -> students (student));
+joinable!(student_history_selections -> student_history (history));
 joinable!(student_marks -> projects (project));
 joinable!(student_marks -> students (student));
 joinable!(student_selections -> projects (project));
 joinable!(student_selections -> students (student));
-joinable!(students -> sessions (last_session));
+joinable!(student_sessions -> sessions (session));
+joinable!(student_sessions -> students (student));
+joinable!(webhook_attempts -> webhook_deliveries (delivery));
+joinable!(webhook_deliveries -> webhooks (webhook));
 
 allow_tables_to_appear_in_same_query!(
+    allocations,
+    audit_events,
     authn_credentials,
+    email_outbox,
+    notifications,
     projects,
+    project_attachments,
+    project_eligibility,
+    project_proposals,
     project_staff,
+    project_tags,
+    session_reminders,
     sessions,
     staff,
+    student_attributes,
     student_comments,
+    student_history,
+    student_history_selections,
     student_marks,
     students,
+    student_sessions,
     student_selections,
+    tags,
+    webhook_attempts,
+    webhook_deliveries,
+    webhooks,
 );
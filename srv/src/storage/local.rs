@@ -0,0 +1,72 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use super::{is_valid_key, Storage};
+
+/// Keeps files in a directory on the local filesystem, named by their keys.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Uses the given directory, creating it if needed.
+    pub fn new(root: &str) -> io::Result<LocalStorage> {
+        fs::create_dir_all(root)?;
+        Ok(LocalStorage {
+            root: PathBuf::from(root),
+        })
+    }
+
+    /// Finds where a file is kept, refusing keys which could escape the storage directory.
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if !is_valid_key(key) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid storage key"));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, body: &[u8]) -> io::Result<()> {
+        let path = self.path(key)?;
+        // Write to a temporary file first, so a partly written file is never visible under the real key.
+        let tmp = self.root.join(format!(".{}.tmp", key));
+        {
+            let mut f = fs::File::create(&tmp)?;
+            f.write_all(body)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, &path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        fs::File::open(self.path(key)?)?.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)?) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    fn keys(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for ent in fs::read_dir(&self.root)? {
+            let ent = ent?;
+            if !ent.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = ent.file_name().to_str() {
+                if is_valid_key(name) {
+                    keys.push(name.to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
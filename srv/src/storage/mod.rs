@@ -0,0 +1,56 @@
+use std::io;
+use std::sync::Arc;
+
+use util;
+
+// `local` keeps files in a directory on the server's filesystem.
+pub mod local;
+
+/// Somewhere to keep uploaded files, such as project attachments. Files are addressed by opaque keys chosen by the
+/// caller; see `new_key`.
+pub trait Storage: Send + Sync {
+    /// Stores a file, replacing any existing file with the same key.
+    fn put(&self, key: &str, body: &[u8]) -> io::Result<()>;
+
+    /// Fetches a file. Fails with `io::ErrorKind::NotFound` if there's no such file.
+    fn get(&self, key: &str) -> io::Result<Vec<u8>>;
+
+    /// Removes a file. Removing a file which doesn't exist is not an error.
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Lists the keys of every stored file.
+    fn keys(&self) -> io::Result<Vec<String>>;
+}
+
+/// Holds the configured storage backend in Rocket's managed state.
+pub struct StorageHolder(pub Arc<Storage>);
+
+impl Storage for StorageHolder {
+    fn put(&self, key: &str, body: &[u8]) -> io::Result<()> {
+        self.0.put(key, body)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.0.delete(key)
+    }
+
+    fn keys(&self) -> io::Result<Vec<String>> {
+        self.0.keys()
+    }
+}
+
+const KEY_LENGTH: usize = 32;
+
+/// Generates a new, random storage key. Keys are only ever alphanumeric, so backends can safely use them as filenames.
+pub fn new_key() -> String {
+    util::generate_rand_string(KEY_LENGTH)
+}
+
+/// Whether a key could have come from `new_key`, so is safe to use as a filename.
+pub fn is_valid_key(key: &str) -> bool {
+    key.len() == KEY_LENGTH && key.chars().all(|c| c.is_ascii_alphanumeric())
+}
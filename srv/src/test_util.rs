@@ -0,0 +1,37 @@
+//! Helpers for tests which need a database. They run against the database named by `TEST_DATABASE_URL` (a full
+//! `postgres://` URL), and are skipped if it isn't set. Each test works inside a transaction which is never committed,
+//! so tests can run in parallel and leave nothing behind.
+
+use std::env;
+use std::sync::{Once, ONCE_INIT};
+
+use diesel::pg::PgConnection;
+use diesel::Connection;
+use r2d2;
+use r2d2_diesel::ConnectionManager;
+
+use db::DatabaseConnection;
+use migrate;
+
+static MIGRATE: Once = ONCE_INIT;
+
+/// Connects to the test database, bringing its schema up to date first. Returns `None` if there's no test database.
+pub fn connection() -> Option<DatabaseConnection> {
+    let url = match env::var("TEST_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("TEST_DATABASE_URL isn't set, so skipping a database test");
+            return None;
+        }
+    };
+    let pool = r2d2::Pool::builder()
+        .max_size(1)
+        .build(ConnectionManager::<PgConnection>::new(url))
+        .expect("test database pool");
+    let conn = DatabaseConnection(pool.get().expect("test database connection"));
+    MIGRATE.call_once(|| migrate::run_on(conn.raw()));
+    conn.raw()
+        .begin_test_transaction()
+        .expect("begin test transaction");
+    Some(conn)
+}
@@ -1,11 +1,12 @@
+use chrono::Duration as ChronoDuration;
 use rand::{OsRng, Rng};
 use ring_pwhash::scrypt::{scrypt_check, scrypt_simple, ScryptParams};
 use rocket::Request;
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
 use rocket::http::hyper::header;
 use rocket::response::content::Html;
 use rocket::response::{Responder, Response};
-use std::io;
+use std::io::{self, Cursor};
 
 lazy_static! {
     // Based on https://blog.filippo.io/the-scrypt-parameters/ for 2017
@@ -41,6 +42,32 @@ pub fn sanitise_email(uname: &str) -> Result<String, ()> {
     Ok(format!("{}@{}", u1.replace(".", ""), u2))
 }
 
+/// Delay before retrying a background delivery, such as an email, which has failed `attempts` times. Starts at a
+/// minute and doubles for each further attempt, up to six hours.
+pub fn retry_delay(attempts: i32) -> ChronoDuration {
+    const BASE_SECS: i64 = 60;
+    const MAX_SECS: i64 = 6 * 60 * 60;
+
+    let exp = attempts.max(1).min(16) as u32 - 1;
+    ChronoDuration::seconds((BASE_SECS * 2i64.pow(exp)).min(MAX_SECS))
+}
+
+/// Escapes text for safe inclusion in HTML.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
 /// Rocket Responder to issue a redirect with HTML body (in case the browser doesn't redirect).
 /// This implementation is derived from the default Rocket Redirect responder; see the docs for that for details.
 #[derive(Debug)]
@@ -88,6 +115,27 @@ impl Responder<'static> for RedirectWithBody {
     }
 }
 
+/// Rocket Responder to send a body as a file download, so browsers save it rather than displaying it.
+#[derive(Debug)]
+pub struct Download {
+    pub filename: String,
+    pub content_type: ContentType,
+    pub body: Vec<u8>,
+}
+
+impl Responder<'static> for Download {
+    fn respond_to(self, _req: &Request) -> Result<Response<'static>, Status> {
+        Response::build()
+            .header(self.content_type)
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            )
+            .sized_body(Cursor::new(self.body))
+            .ok()
+    }
+}
+
 macro_rules! concat_vec {
     [$( $x:expr ),*$(,)*] => ({
         let mut v = Vec::new();
@@ -0,0 +1,330 @@
+//! Delivery of webhook payloads. Events are queued by `db::webhook::emit`, then POSTed to each subscribed URL by a
+//! background thread, which retries failures with exponential backoff and logs every attempt. Payloads are signed with
+//! HMAC-SHA256 using the webhook's secret, so receivers can check they came from us.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use reqwest;
+use reqwest::header::{ContentType, Headers};
+use serde_json;
+
+use db::webhook::{attempt, delivery, Webhook};
+use db::{DatabaseConnection, Pool};
+use util;
+
+lazy_static! {
+    static ref DELIVERY_SLEEP: Duration = Duration::from_secs(10);
+    static ref HTTP: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("webhook HTTP client");
+}
+
+/// Events which webhooks can subscribe to.
+pub const EVENTS: &[&str] = &[
+    "session.opened",
+    "session.archived",
+    "session.deleted",
+    "session.deadline_changed",
+    "project.created",
+    "project.updated",
+    "project.deleted",
+    "project.restored",
+    "project.status_changed",
+    "student.created",
+    "student.removed",
+    "student.restored",
+    "staff.created",
+    "staff.removed",
+    "staff.restored",
+    "allocation.published",
+    "ping",
+];
+
+/// Deliveries sent in each pass over the queue.
+const BATCH_SIZE: i64 = 50;
+const MAX_ATTEMPTS: i32 = 10;
+/// Longest error message kept in the delivery log.
+const MAX_ERROR_LEN: usize = 1000;
+
+/// Signs a payload with a webhook's secret, giving the value of the `X-HPAS-Signature` header.
+pub fn sign(secret: &str, body: &[u8]) -> Result<String, String> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|e| e.to_string())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    signer.update(body).map_err(|e| e.to_string())?;
+    let sig = signer.sign_to_vec().map_err(|e| e.to_string())?;
+    let hex = sig.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    Ok(format!("sha256={}", hex))
+}
+
+/// POSTs a delivery to its webhook, returning the HTTP status if there was a response, and an error if the delivery
+/// didn't succeed.
+fn send(hook: &Webhook, dlv: &delivery::WebhookDelivery) -> (Option<i32>, Option<String>) {
+    let body = match serde_json::to_vec(&dlv.payload) {
+        Ok(b) => b,
+        Err(e) => return (None, Some(format!("unable to serialise payload: {}", e))),
+    };
+    let signature = match sign(&hook.secret, &body) {
+        Ok(s) => s,
+        Err(e) => return (None, Some(format!("unable to sign payload: {}", e))),
+    };
+
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    headers.set_raw("X-HPAS-Event", dlv.event.clone());
+    headers.set_raw("X-HPAS-Delivery", dlv.id.to_string());
+    headers.set_raw("X-HPAS-Signature", signature);
+    match HTTP.post(&hook.url[..]).headers(headers).body(body).send() {
+        Ok(res) => {
+            let status = res.status();
+            if status.is_success() {
+                (Some(i32::from(status.as_u16())), None)
+            } else {
+                (
+                    Some(i32::from(status.as_u16())),
+                    Some(format!(
+                        "server returned HTTP {}: {}",
+                        status.as_u16(),
+                        status.canonical_reason().unwrap_or("(unknown)")
+                    )),
+                )
+            }
+        }
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+/// Sends every delivery which is due, logging each attempt and scheduling retries for failures.
+fn process_queue(conn: &DatabaseConnection) {
+    let due = match delivery::get_due(conn, BATCH_SIZE) {
+        Ok(due) => due,
+        Err(e) => {
+            error!("Unable to fetch webhook deliveries: {:?}", e);
+            return;
+        }
+    };
+
+    for (dlv, hook) in due {
+        let start = Instant::now();
+        let (status, err) = send(&hook, &dlv);
+        let elapsed = start.elapsed();
+        let duration_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000);
+
+        let log = attempt::NewWebhookAttempt {
+            delivery: dlv.id,
+            status,
+            error: err.as_ref().map(|e| e.chars().take(MAX_ERROR_LEN).collect()),
+            duration_ms: duration_ms.min(i32::max_value() as u64) as i32,
+        };
+        if let Err(e) = attempt::create(conn, &log) {
+            error!("Unable to log webhook attempt {:?}: {}", log, e);
+        }
+
+        let res = match err {
+            None => delivery::mark_delivered(conn, &dlv),
+            Some(e) => {
+                let attempts = dlv.attempts + 1;
+                let retry_at = if attempts < MAX_ATTEMPTS {
+                    Some((Utc::now() + util::retry_delay(attempts)).naive_utc())
+                } else {
+                    None
+                };
+                match retry_at {
+                    Some(at) => warn!(
+                        "Webhook delivery {} to {} failed (attempt {}), retrying at {}: {}",
+                        dlv.id, hook.url, attempts, at, e
+                    ),
+                    None => error!(
+                        "Webhook delivery {} to {} failed {} times, giving up: {}",
+                        dlv.id, hook.url, attempts, e
+                    ),
+                }
+                delivery::mark_failed(conn, &dlv, retry_at)
+            }
+        };
+        if let Err(e) = res {
+            error!("Unable to update webhook delivery {}: {}", dlv.id, e);
+        }
+    }
+}
+
+/// Spawns a thread which delivers queued webhook payloads.
+pub fn start_delivery_thread(pool: Arc<Pool>) {
+    thread::Builder::new()
+        .name("webhook-delivery".to_string())
+        .spawn(move || loop {
+            thread::sleep(*DELIVERY_SLEEP);
+
+            let conn = match pool.get() {
+                Ok(conn) => DatabaseConnection(conn),
+                Err(e) => {
+                    warn!("Unable to get database worker for webhook delivery: {}", e);
+                    continue;
+                }
+            };
+            process_queue(&conn);
+        })
+        .expect("webhook thread creation");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use chrono::Utc;
+    use serde_json::{self, Value};
+
+    use super::*;
+    use db::webhook;
+    use test_util;
+
+    /// A request received by the HTTP stand-in, with lowercased header names.
+    struct Received {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    /// Starts an HTTP server on a local port, which records each request and answers it with `status`.
+    fn start_receiver(status: u16) -> (u16, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind HTTP receiver");
+        let port = listener.local_addr().expect("HTTP receiver address").port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    handle_http(stream, status, &sink);
+                }
+            }
+        });
+        (port, received)
+    }
+
+    fn handle_http(stream: TcpStream, status: u16, received: &Mutex<Vec<Received>>) {
+        let mut reader = BufReader::new(stream.try_clone().expect("clone HTTP stream"));
+        let mut writer = stream;
+        let mut headers = HashMap::new();
+        let mut line = String::new();
+        // Skip the request line, then read headers up to the blank line.
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let header = line.trim_right();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(idx) = header.find(':') {
+                headers.insert(header[..idx].to_lowercase(), header[idx + 1..].trim().to_string());
+            }
+        }
+        let len = headers
+            .get("content-length")
+            .and_then(|it| it.parse::<usize>().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; len];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        received.lock().unwrap().push(Received { headers, body });
+        let _ = write!(
+            writer,
+            "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        );
+    }
+
+    fn subscribe(conn: &DatabaseConnection, port: u16) -> Webhook {
+        webhook::create(
+            conn,
+            &webhook::NewWebhook {
+                url: format!("http://127.0.0.1:{}/hook", port),
+                secret: "s3cret".to_string(),
+                events: vec!["allocation.published".to_string()],
+                active: true,
+            },
+        ).expect("create webhook")
+    }
+
+    #[test]
+    fn signs_payloads() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog").unwrap(),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn delivers_signed_events_and_logs_attempts() {
+        let conn = match test_util::connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let (port, received) = start_receiver(200);
+        let hook = subscribe(&conn, port);
+
+        webhook::emit(&conn, "allocation.published", Some(json!({ "student": 1, "project": 2 })));
+        // Events the webhook isn't subscribed to aren't queued for it.
+        webhook::emit(&conn, "session.opened", None);
+        process_queue(&conn);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let req = &received[0];
+        assert_eq!(req.headers["x-hpas-event"], "allocation.published");
+        assert_eq!(req.headers["x-hpas-signature"], sign("s3cret", &req.body).unwrap());
+        let payload: Value = serde_json::from_slice(&req.body).expect("payload JSON");
+        assert_eq!(payload["event"], "allocation.published");
+        assert_eq!(payload["data"]["project"], 2);
+
+        let deliveries = delivery::get_recent_for_webhook(&conn, hook.id, 10).expect("fetch deliveries");
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(req.headers["x-hpas-delivery"], deliveries[0].id.to_string());
+        assert_eq!(deliveries[0].attempts, 1);
+        assert!(deliveries[0].delivered.is_some());
+        let attempts = attempt::get_all_for_deliveries(&conn, &[deliveries[0].id]).expect("fetch attempts");
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, Some(200));
+        assert!(attempts[0].error.is_none());
+    }
+
+    #[test]
+    fn logs_failed_attempts_and_retries() {
+        let conn = match test_util::connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        let (port, received) = start_receiver(500);
+        let hook = subscribe(&conn, port);
+
+        webhook::emit(&conn, "allocation.published", None);
+        let before = Utc::now().naive_utc();
+        process_queue(&conn);
+
+        assert_eq!(received.lock().unwrap().len(), 1);
+        let deliveries = delivery::get_recent_for_webhook(&conn, hook.id, 10).expect("fetch deliveries");
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].attempts, 1);
+        assert!(deliveries[0].delivered.is_none());
+        assert!(deliveries[0].abandoned.is_none());
+        assert!(deliveries[0].next_attempt >= before + util::retry_delay(1));
+        let attempts = attempt::get_all_for_deliveries(&conn, &[deliveries[0].id]).expect("fetch attempts");
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, Some(500));
+        assert!(attempts[0].error.is_some());
+    }
+}
@@ -10,6 +10,8 @@ export interface IProject {
   supervisor_email: string;
   additional_staff: string[];
   description_md: string;
+  // Rendered and sanitised by the server. Unset for projects which haven't been saved yet.
+  description_html?: string;
   id?: number;
   session?: number;
 }